@@ -0,0 +1,336 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::time::{Duration, Instant};
+
+use winit::{
+    event::{Event, StartCause, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    monitor::VideoMode,
+    window::{Fullscreen, Window},
+};
+
+use crate::{
+    input::InputState,
+    render_context::{RenderContext, RenderContextError},
+};
+
+//How the event loop should drive drawing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopMode {
+    //Draw continuously, as fast as presenting allows. For games and
+    //anything animated
+    #[default]
+    Poll,
+    //Sleep until there are events and only draw when a redraw is
+    //requested, either by the OS or through Window::request_redraw. For
+    //tools and editors that would rather save the battery
+    Wait,
+}
+
+impl LoopMode {
+    //The ControlFlow to run the event loop with while rendering
+    pub fn control_flow(self) -> ControlFlow {
+        match self {
+            LoopMode::Poll => ControlFlow::Poll,
+            LoopMode::Wait => ControlFlow::Wait,
+        }
+    }
+}
+
+//How the context's window should cover the screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Windowed,
+    //A window covering the monitor it's on, without changing the monitor's
+    //video mode
+    Borderless,
+    //Take over the monitor and switch it to the closest video mode it has
+    //to this one
+    Exclusive(VideoMode),
+}
+
+//The video mode out of the ones the window's monitor has that's closest to
+//wanted, preferring the same size, then refresh rate, then bit depth
+fn closest_video_mode(
+    window: &Window,
+    wanted: &VideoMode,
+) -> Option<VideoMode> {
+    let wanted_size = wanted.size();
+    window.current_monitor()?.video_modes().min_by_key(|mode| {
+        let size = mode.size();
+        (
+            size.width.abs_diff(wanted_size.width)
+                + size.height.abs_diff(wanted_size.height),
+            mode.refresh_rate_millihertz()
+                .abs_diff(wanted.refresh_rate_millihertz()),
+            mode.bit_depth().abs_diff(wanted.bit_depth()),
+        )
+    })
+}
+
+//What the RenderContext::run callback gets each frame on top of the
+//context itself
+pub struct FrameContext<'a> {
+    pub window: &'a Window,
+    //keyboard and mouse state as of this frame
+    pub input: &'a InputState,
+    //physical pixels per logical pixel on the window's monitor. The
+    //swapchain, and so FrameRecorder's extent and viewports, are always in
+    //physical pixels, multiply logical sizes by this to match
+    pub scale_factor: f64,
+    //time since the callback was last called, zero the first time
+    pub delta_time: Duration,
+    control_flow: &'a mut ControlFlow,
+}
+
+impl FrameContext<'_> {
+    //Stop the event loop once this frame is done
+    pub fn exit(&mut self) {
+        self.control_flow.set_exit();
+    }
+}
+
+//No point burning the GPU on a window nobody can see. Wait instead of poll
+//so we still wake up for events but otherwise yield the thread. Unpausing
+//goes back to whatever the context's loop mode wants
+fn set_paused(
+    render_context: &mut RenderContext,
+    control_flow: &mut ControlFlow,
+    paused: bool,
+) {
+    render_context.set_rendering_paused(paused);
+    if *control_flow == ControlFlow::Exit {
+        return;
+    }
+    if paused {
+        control_flow.set_wait();
+    } else {
+        *control_flow = render_context.loop_mode().control_flow();
+    }
+}
+
+//Hidden windows often never get focus, so until the first frame has shown
+//the window losing focus doesn't count
+fn should_pause(
+    focused: bool,
+    minimized: bool,
+    first_frame_done: bool,
+) -> bool {
+    (!focused && first_frame_done) || minimized
+}
+
+impl RenderContext {
+    //Switch the window between windowed, borderless and exclusive
+    //fullscreen. Exclusive falls back to borderless when the window's
+    //monitor has no video modes to offer. The swapchain is rebuilt for the
+    //new size, though some platforms only resize the window later, in
+    //which case the Resized event has to trigger another rebuild like
+    //RenderContext::run does. Headless contexts ignore this
+    pub fn set_fullscreen(
+        &mut self,
+        mode: FullscreenMode,
+    ) -> Result<(), RenderContextError> {
+        let Some(window) = self.window() else {
+            log::debug!("headless contexts have no window to fullscreen");
+            return Ok(());
+        };
+        let fullscreen = match mode {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+            FullscreenMode::Exclusive(wanted) => {
+                match closest_video_mode(window, &wanted) {
+                    Some(video_mode) => {
+                        log::info!("Switching to video mode {}", video_mode);
+                        Some(Fullscreen::Exclusive(video_mode))
+                    }
+                    None => {
+                        log::warn!(
+                            "No video modes to go exclusive with, using \
+                             borderless fullscreen instead"
+                        );
+                        Some(Fullscreen::Borderless(None))
+                    }
+                }
+            }
+        };
+        window.set_fullscreen(fullscreen);
+        self.recreate_swapchain()?;
+        Ok(())
+    }
+
+    //Run event_loop for the context's window until it's closed or the
+    //callback calls FrameContext::exit. render_fn is called whenever
+    //there's a frame to draw, according to the loop mode, and is expected
+    //to call draw_frame. Resizes, minimizing and losing focus are dealt
+    //with here. Unless RenderContextBuilder::show_after_first_frame turned
+    //it off, a window built hidden is shown once render_fn has presented a
+    //frame so it never shows up blank. Panics for headless contexts
+    pub fn run<F>(mut self, event_loop: EventLoop<()>, mut render_fn: F) -> !
+    where
+        F: FnMut(&mut RenderContext, FrameContext) + 'static,
+    {
+        let window = self
+            .window()
+            .expect("headless contexts have no window to run")
+            .clone();
+        //nothing to wait for if the window isn't ours to show
+        let mut first_frame_done = !self.show_after_first_frame();
+        let mut needs_resize = false;
+        let mut focused = true;
+        let mut minimized = false;
+        let mut last_frame: Option<Instant> = None;
+        let mut input = InputState::new();
+
+        event_loop.run(move |event, _target, control_flow| {
+            if let Event::WindowEvent { window_id, event } = &event {
+                if *window_id == window.id() {
+                    input.handle_event(event);
+                }
+            }
+            match event {
+                Event::NewEvents(StartCause::Init) => {
+                    *control_flow = self.loop_mode().control_flow();
+                    //in wait mode nothing gets drawn until someone asks
+                    window.request_redraw();
+                }
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::CloseRequested,
+                } if window_id == window.id() => {
+                    log::debug!("recieved shutdown request");
+                    control_flow.set_exit();
+                    window.set_visible(false);
+                }
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::Focused(is_focused),
+                } if window_id == window.id() => {
+                    focused = is_focused;
+                    set_paused(
+                        &mut self,
+                        control_flow,
+                        should_pause(focused, minimized, first_frame_done),
+                    );
+                }
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::Resized(size),
+                } if window_id == window.id() => {
+                    //minimizing gives us a 0x0 window which we can't present to
+                    //so park the loop until we get restored
+                    minimized = size.width == 0 || size.height == 0;
+                    set_paused(
+                        &mut self,
+                        control_flow,
+                        should_pause(focused, minimized, first_frame_done),
+                    );
+                    //these can come in bunches while the user drags the window
+                    //so just note it and rebuild once before the next frame
+                    needs_resize = true;
+                    window.request_redraw();
+                }
+                Event::WindowEvent {
+                    window_id,
+                    event:
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            new_inner_size,
+                        },
+                } if window_id == window.id() => {
+                    //we take the size winit suggests, which keeps the window
+                    //the same logical size. It's applied by the time we
+                    //redraw so the swapchain picks up the new physical size
+                    //then
+                    log::debug!(
+                        "scale factor changed to {}, window is now {}x{}",
+                        scale_factor,
+                        new_inner_size.width,
+                        new_inner_size.height
+                    );
+                    minimized =
+                        new_inner_size.width == 0 || new_inner_size.height == 0;
+                    set_paused(
+                        &mut self,
+                        control_flow,
+                        should_pause(focused, minimized, first_frame_done),
+                    );
+                    needs_resize = true;
+                    window.request_redraw();
+                }
+                //polling draws every time around the loop, waiting only draws
+                //when something asked for a redraw
+                Event::MainEventsCleared
+                    if self.loop_mode() == LoopMode::Poll =>
+                {
+                    window.request_redraw();
+                }
+                Event::RedrawRequested(window_id)
+                    if window_id == window.id() =>
+                {
+                    if needs_resize {
+                        match self.recreate_swapchain() {
+                            Ok(recreated) => needs_resize = !recreated,
+                            Err(e) => {
+                                log::error!(
+                                    "Failed to recreate swapchain: {}",
+                                    e
+                                );
+                                control_flow.set_exit();
+                                return;
+                            }
+                        }
+                    }
+                    if should_pause(focused, minimized, first_frame_done) {
+                        //so the pause doesn't show up as one long frame, or
+                        //as a big jump in the mouse
+                        last_frame = None;
+                        input.end_frame();
+                        return;
+                    }
+                    let now = Instant::now();
+                    let delta_time =
+                        last_frame.map_or(Duration::ZERO, |last| now - last);
+                    last_frame = Some(now);
+                    let frames_before = self.frame_stats().frame_count;
+                    render_fn(
+                        &mut self,
+                        FrameContext {
+                            window: &window,
+                            input: &input,
+                            scale_factor: window.scale_factor(),
+                            delta_time,
+                            control_flow,
+                        },
+                    );
+                    input.end_frame();
+                    //render_fn doesn't have to draw every time it's called,
+                    //so wait until a frame actually made it to the window
+                    let presented =
+                        self.frame_stats().frame_count > frames_before;
+                    if !first_frame_done
+                        && presented
+                        && *control_flow != ControlFlow::Exit
+                    {
+                        first_frame_done = true;
+                        log::debug!("first frame done, showing window");
+                        window.set_visible(true);
+                        //losing focus counts from here on
+                        set_paused(
+                            &mut self,
+                            control_flow,
+                            should_pause(focused, minimized, first_frame_done),
+                        );
+                    }
+                }
+                Event::LoopDestroyed => {
+                    log::debug!("Loop exiting");
+                }
+                _ => {}
+            }
+        })
+    }
+}
@@ -0,0 +1,164 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::{
+    memory::{Image, MemoryLocation},
+    render_context::{RenderContext, RenderContextError},
+    render_pass::depth_aspect,
+};
+
+//An image that's used as a render pass attachment, sized to match the
+//swapchain or headless target. Only one of each is needed no matter how many frames are in
+//flight since the render pass clears it every frame and its dependency keeps
+//frames from using it at the same time
+pub struct AttachmentImage {
+    view: vk::ImageView,
+    image: Image,
+    device: Device,
+}
+
+impl AttachmentImage {
+    pub fn depth(
+        render_context: &RenderContext,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Result<AttachmentImage, RenderContextError> {
+        AttachmentImage::new(
+            render_context,
+            format,
+            extent,
+            samples,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            depth_aspect(format),
+        )
+    }
+
+    //A multisampled color target that gets resolved into the swapchain. Its
+    //contents never leave the GPU's tile memory on hardware that supports
+    //transient attachments
+    pub fn color(
+        render_context: &RenderContext,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Result<AttachmentImage, RenderContextError> {
+        AttachmentImage::new(
+            render_context,
+            format,
+            extent,
+            samples,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            vk::ImageAspectFlags::COLOR,
+        )
+    }
+
+    //A single sampled color image that can be rendered to and copied out
+    //of, e.g. the target of a headless context
+    pub fn render_target(
+        render_context: &RenderContext,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<AttachmentImage, RenderContextError> {
+        AttachmentImage::new(
+            render_context,
+            format,
+            extent,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )
+    }
+
+    //A single sampled color image that shaders can read once it's been
+    //rendered to, e.g. the color of an OffscreenTarget
+    pub fn sampled(
+        render_context: &RenderContext,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<AttachmentImage, RenderContextError> {
+        AttachmentImage::new(
+            render_context,
+            format,
+            extent,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+        )
+    }
+
+    fn new(
+        render_context: &RenderContext,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+        usage: vk::ImageUsageFlags,
+        aspect: vk::ImageAspectFlags,
+    ) -> Result<AttachmentImage, RenderContextError> {
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+        let image = render_context
+            .create_image(&create_info, MemoryLocation::GpuOnly)?;
+
+        let device = render_context.device();
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image.handle())
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(aspect)
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build();
+        //SAFETY: the image was just made with a matching format
+        let view = unsafe { device.create_image_view(&view_info, None) }
+            .map_err(RenderContextError::ImageCreationFailed)?;
+
+        Ok(AttachmentImage {
+            view,
+            image,
+            device: device.clone(),
+        })
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+}
+
+impl Drop for AttachmentImage {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the image.
+        //The image itself is dropped after this
+        unsafe { self.device.destroy_image_view(self.view, None) }
+    }
+}
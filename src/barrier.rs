@@ -0,0 +1,461 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::render_context::RenderContextError;
+
+//The accesses that have to be finished before an image leaves layout and
+//the stage they happen in. None for layouts we don't know how to leave
+fn src_scope(
+    layout: vk::ImageLayout,
+) -> Option<(vk::AccessFlags, vk::PipelineStageFlags)> {
+    Some(match layout {
+        //nothing to wait on since the contents get thrown away
+        vk::ImageLayout::UNDEFINED => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::PREINITIALIZED => {
+            (vk::AccessFlags::HOST_WRITE, vk::PipelineStageFlags::HOST)
+        }
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        //a presentable image was either just acquired, which we wait for at
+        //color attachment output, or just rendered to
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        _ => return None,
+    })
+}
+
+//The accesses that have to wait for an image to reach layout and the
+//earliest stage they happen in. None for layouts we don't know how to enter
+fn dst_scope(
+    layout: vk::ImageLayout,
+) -> Option<(vk::AccessFlags, vk::PipelineStageFlags)> {
+    Some(match layout {
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        ),
+        //presentation isn't a pipeline stage, the semaphore handed to
+        //queue_present takes care of making it wait
+        vk::ImageLayout::PRESENT_SRC_KHR => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        ),
+        _ => return None,
+    })
+}
+
+//Access masks and stages for moving an image from old_layout to
+//new_layout, as (src_access, src_stage, dst_access, dst_stage). None if it
+//isn't a transition we know how to make
+pub fn layout_transition_masks(
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> Option<(
+    vk::AccessFlags,
+    vk::PipelineStageFlags,
+    vk::AccessFlags,
+    vk::PipelineStageFlags,
+)> {
+    let (src_access, src_stage) = src_scope(old_layout)?;
+    let (dst_access, dst_stage) = dst_scope(new_layout)?;
+    Some((src_access, src_stage, dst_access, dst_stage))
+}
+
+//Record a barrier moving the first mip_levels levels of image from
+//old_layout to new_layout, with the access masks and stages worked out for
+//us. Fails without recording anything for transitions we don't know
+pub fn transition_image_layout(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    aspect: vk::ImageAspectFlags,
+    mip_levels: u32,
+) -> Result<(), RenderContextError> {
+    transition_subresource(
+        device,
+        command_buffer,
+        image,
+        old_layout,
+        new_layout,
+        vk::ImageSubresourceRange {
+            aspect_mask: aspect,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        },
+    )
+}
+
+//transition_image_layout for an arbitrary range of the image
+pub fn transition_subresource(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    range: vk::ImageSubresourceRange,
+) -> Result<(), RenderContextError> {
+    Barrier::new()
+        .image_transition(image, range, old_layout, new_layout)
+        .record(device, command_buffer)
+}
+
+//Make everything compute shaders wrote so far visible to whatever the
+//graphics pipeline might read it as: vertices, indices, indirect draws,
+//uniforms or anything else shaders read
+pub fn compute_to_graphics_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+) {
+    Barrier::new()
+        .memory(
+            Scope::new(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            ),
+            Scope::new(
+                vk::PipelineStageFlags::DRAW_INDIRECT
+                    | vk::PipelineStageFlags::VERTEX_INPUT
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::INDIRECT_COMMAND_READ
+                    | vk::AccessFlags::INDEX_READ
+                    | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                    | vk::AccessFlags::UNIFORM_READ
+                    | vk::AccessFlags::SHADER_READ,
+            ),
+        )
+        .record(device, command_buffer)
+        .expect("memory barriers can't fail")
+}
+
+//The stages on one side of a barrier and the accesses in them it covers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Scope {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl Scope {
+    pub fn new(stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        Scope { stage, access }
+    }
+}
+
+//Collects a global memory barrier plus any number of buffer and image
+//barriers and records them all with one cmd_pipeline_barrier, waiting
+//between the union of their stages. Image barriers made with
+//image_transition get their scopes worked out like transition_image_layout
+#[derive(Default)]
+pub struct Barrier {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    //None until memory is called
+    memory: Option<vk::MemoryBarrier>,
+    buffers: Vec<vk::BufferMemoryBarrier>,
+    images: Vec<vk::ImageMemoryBarrier>,
+    //the first transition we didn't know, handed back by record
+    error: Option<RenderContextError>,
+}
+
+impl Barrier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Make src's writes available and visible to dst for all memory. Calling
+    //this again widens the same global barrier
+    pub fn memory(mut self, src: Scope, dst: Scope) -> Self {
+        let memory = self.memory.get_or_insert_with(Default::default);
+        memory.src_access_mask |= src.access;
+        memory.dst_access_mask |= dst.access;
+        self.add_scopes(src, dst);
+        self
+    }
+
+    //Same as memory but only for size bytes of buffer starting at offset.
+    //vk::WHOLE_SIZE covers the rest of the buffer
+    pub fn buffer(
+        mut self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        src: Scope,
+        dst: Scope,
+    ) -> Self {
+        self.buffers.push(
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(src.access)
+                .dst_access_mask(dst.access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size)
+                .build(),
+        );
+        self.add_scopes(src, dst);
+        self
+    }
+
+    //Move range of image from old_layout to new_layout with src finished
+    //before and dst waiting after. range needs an aspect mask
+    pub fn image(
+        mut self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src: Scope,
+        dst: Scope,
+    ) -> Self {
+        assert!(
+            !range.aspect_mask.is_empty(),
+            "image barriers need an aspect mask"
+        );
+        if old_layout == vk::ImageLayout::UNDEFINED
+            && new_layout == vk::ImageLayout::UNDEFINED
+        {
+            log::warn!(
+                "barrier on image {:?} goes from UNDEFINED to UNDEFINED, \
+                which only throws its contents away",
+                image
+            );
+        }
+        self.images.push(
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src.access)
+                .dst_access_mask(dst.access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(range)
+                .build(),
+        );
+        self.add_scopes(src, dst);
+        self
+    }
+
+    //image with the scopes layout_transition_masks picks for the two
+    //layouts. Transitions it doesn't know make record fail
+    pub fn image_transition(
+        self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> Self {
+        match layout_transition_masks(old_layout, new_layout) {
+            Some((src_access, src_stage, dst_access, dst_stage)) => self.image(
+                image,
+                range,
+                old_layout,
+                new_layout,
+                Scope::new(src_stage, src_access),
+                Scope::new(dst_stage, dst_access),
+            ),
+            None => {
+                let mut barrier = self;
+                barrier.error.get_or_insert(
+                    RenderContextError::UnsupportedLayoutTransition {
+                        old_layout,
+                        new_layout,
+                    },
+                );
+                barrier
+            }
+        }
+    }
+
+    fn add_scopes(&mut self, src: Scope, dst: Scope) {
+        self.src_stage |= src.stage;
+        self.dst_stage |= dst.stage;
+    }
+
+    //Record everything added as one barrier. Records nothing if nothing was
+    //added, or if an image_transition failed
+    pub fn record(
+        self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+    ) -> Result<(), RenderContextError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let memory: Vec<_> = self.memory.into_iter().collect();
+        if memory.is_empty()
+            && self.buffers.is_empty()
+            && self.images.is_empty()
+        {
+            return Ok(());
+        }
+        //SAFETY: the caller hands us a recording command buffer, and the
+        //buffers and images are alive
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage,
+                self.dst_stage,
+                vk::DependencyFlags::empty(),
+                &memory,
+                &self.buffers,
+                &self.images,
+            )
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::PipelineStageFlags as Stage;
+    use vk::{AccessFlags as Access, ImageLayout as Layout};
+
+    #[test]
+    fn undefined_to_transfer_dst() {
+        assert_eq!(
+            layout_transition_masks(
+                Layout::UNDEFINED,
+                Layout::TRANSFER_DST_OPTIMAL
+            ),
+            Some((
+                Access::empty(),
+                Stage::TOP_OF_PIPE,
+                Access::TRANSFER_WRITE,
+                Stage::TRANSFER
+            ))
+        );
+    }
+
+    #[test]
+    fn transfer_dst_to_shader_read() {
+        assert_eq!(
+            layout_transition_masks(
+                Layout::TRANSFER_DST_OPTIMAL,
+                Layout::SHADER_READ_ONLY_OPTIMAL
+            ),
+            Some((
+                Access::TRANSFER_WRITE,
+                Stage::TRANSFER,
+                Access::SHADER_READ,
+                Stage::FRAGMENT_SHADER
+            ))
+        );
+    }
+
+    #[test]
+    fn color_attachment_to_present() {
+        assert_eq!(
+            layout_transition_masks(
+                Layout::COLOR_ATTACHMENT_OPTIMAL,
+                Layout::PRESENT_SRC_KHR
+            ),
+            Some((
+                Access::COLOR_ATTACHMENT_WRITE,
+                Stage::COLOR_ATTACHMENT_OUTPUT,
+                Access::empty(),
+                Stage::BOTTOM_OF_PIPE
+            ))
+        );
+    }
+
+    //nothing can be transitioned into UNDEFINED
+    #[test]
+    fn unknown_destination() {
+        assert_eq!(
+            layout_transition_masks(
+                Layout::TRANSFER_DST_OPTIMAL,
+                Layout::UNDEFINED
+            ),
+            None
+        );
+    }
+
+    //record hands back the stored error before touching the device, so
+    //checking what it stored is enough
+    #[test]
+    fn image_transition_stores_unsupported_error() {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let barrier = Barrier::new().image_transition(
+            vk::Image::null(),
+            range,
+            Layout::TRANSFER_DST_OPTIMAL,
+            Layout::UNDEFINED,
+        );
+        assert!(barrier.images.is_empty());
+        assert!(matches!(
+            barrier.error,
+            Some(RenderContextError::UnsupportedLayoutTransition {
+                old_layout: Layout::TRANSFER_DST_OPTIMAL,
+                new_layout: Layout::UNDEFINED,
+            })
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::vk;
+
+//A 4x4 matrix as 4 columns, the layout GLSL's mat4 expects in a uniform
+//buffer. Any math library with column major matrices converts straight to
+//and from this
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    //fov_y is the vertical field of view in radians
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    //height is how much of the world fits top to bottom, width follows
+    //from the aspect ratio
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+//A right handed camera looking from position at target. The projections
+//produce vulkan clip space, with y pointing down and depth going from 0 at
+//near to 1 at far, so world space +y still ends up at the top of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    pub projection: Projection,
+}
+
+impl Camera {
+    //A perspective camera at z = 1 looking down -z at the origin
+    pub fn perspective(fov_y: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            position: [0.0, 0.0, 1.0],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            projection: Projection::Perspective { fov_y, near, far },
+        }
+    }
+
+    //An orthographic camera at z = 1 looking down -z at the origin
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            position: [0.0, 0.0, 1.0],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            projection: Projection::Orthographic { height, near, far },
+        }
+    }
+
+    //World space to view space, where the camera sits at the origin
+    //looking down -z
+    pub fn view(&self) -> Mat4 {
+        let forward = normalize(sub(self.target, self.position));
+        let side = normalize(cross(forward, self.up));
+        let up = cross(side, forward);
+        [
+            [side[0], up[0], -forward[0], 0.0],
+            [side[1], up[1], -forward[1], 0.0],
+            [side[2], up[2], -forward[2], 0.0],
+            [
+                -dot(side, self.position),
+                -dot(up, self.position),
+                dot(forward, self.position),
+                1.0,
+            ],
+        ]
+    }
+
+    //View space to vulkan clip space for a target with this width over
+    //height
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                let height = 1.0 / (fov_y / 2.0).tan();
+                let depth = far / (near - far);
+                [
+                    [height / aspect, 0.0, 0.0, 0.0],
+                    [0.0, -height, 0.0, 0.0],
+                    [0.0, 0.0, depth, -1.0],
+                    [0.0, 0.0, depth * near, 0.0],
+                ]
+            }
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height / 2.0;
+                let depth = 1.0 / (near - far);
+                [
+                    [1.0 / (half_height * aspect), 0.0, 0.0, 0.0],
+                    [0.0, -1.0 / half_height, 0.0, 0.0],
+                    [0.0, 0.0, depth, 0.0],
+                    [0.0, 0.0, depth * near, 1.0],
+                ]
+            }
+        }
+    }
+
+    //projection * view, ready to go in a uniform buffer
+    pub fn view_projection(&self, aspect: f32) -> Mat4 {
+        mul(&self.projection(aspect), &self.view())
+    }
+
+    //view_projection with the aspect ratio of extent, e.g.
+    //FrameRecorder::extent
+    pub fn view_projection_for(&self, extent: vk::Extent2D) -> Mat4 {
+        self.view_projection(aspect_ratio(extent))
+    }
+}
+
+//Width over height, 1 for empty extents so nothing divides by zero
+pub fn aspect_ratio(extent: vk::Extent2D) -> f32 {
+    if extent.width == 0 || extent.height == 0 {
+        return 1.0;
+    }
+    extent.width as f32 / extent.height as f32
+}
+
+//a * b, so b applies first
+pub fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut result = [[0.0; 4]; 4];
+    for (column, result_column) in result.iter_mut().enumerate() {
+        for (row, value) in result_column.iter_mut().enumerate() {
+            *value = (0..4).map(|i| a[i][row] * b[column][i]).sum();
+        }
+    }
+    result
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    [a[0] / length, a[1] / length, a[2] / length]
+}
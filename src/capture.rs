@@ -0,0 +1,161 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+use image::RgbaImage;
+
+use crate::{
+    barrier::transition_image_layout,
+    memory::{Buffer, MemoryLocation},
+    render_context::{RenderContext, RenderContextError},
+};
+
+//A frame's pixels on their way back to the CPU. The copy into buffer has
+//been recorded but may not have finished yet
+pub(crate) struct Capture {
+    buffer: Buffer,
+    extent: vk::Extent2D,
+    format: vk::Format,
+}
+
+impl Capture {
+    //A host visible buffer big enough for one frame of the given size.
+    //Fails up front for formats to_rgba_image can't convert
+    pub fn new(
+        render_context: &RenderContext,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Capture, RenderContextError> {
+        if swizzle(format).is_none() {
+            return Err(RenderContextError::UnsupportedCaptureFormat(format));
+        }
+        let size = extent.width as vk::DeviceSize
+            * extent.height as vk::DeviceSize
+            * 4;
+        let buffer = render_context.create_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        )?;
+        render_context.set_object_name(buffer.handle(), "capture buffer");
+        Ok(Capture {
+            buffer,
+            extent,
+            format,
+        })
+    }
+
+    //Copy image, which is in layout, into our buffer and put it back in
+    //layout afterwards. The image has to have been made with TRANSFER_SRC
+    //usage
+    pub fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+    ) -> Result<(), RenderContextError> {
+        if layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+            transition_image_layout(
+                device,
+                command_buffer,
+                image,
+                layout,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )?;
+        }
+        //a row length of 0 means tightly packed, so the buffer comes out
+        //without any row padding no matter how the image is tiled
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .build();
+        //the CPU reads the buffer once the submission's fence signals
+        let host_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.handle())
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        //SAFETY: the command buffer is recording, the image was just put in
+        //TRANSFER_SRC_OPTIMAL and the buffer holds a full frame
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffer.handle(),
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[host_barrier],
+                &[],
+            );
+        }
+        if layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+            transition_image_layout(
+                device,
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                layout,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            )?;
+        }
+        Ok(())
+    }
+
+    //Only call once the copy has finished executing
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        let len = self.extent.width as usize * self.extent.height as usize * 4;
+        //the allocation can be bigger than the frame so only take the frame
+        let mut pixels = self
+            .buffer
+            .mapped_slice()
+            .expect("capture buffers are host visible")[..len]
+            .to_vec();
+        if swizzle(self.format) == Some(true) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+            .expect("capture buffers hold exactly one frame")
+    }
+}
+
+//Whether a frame in format needs its red and blue swapped to come out as
+//RGBA. None for formats that aren't 8 bits per channel RGBA or BGRA
+fn swizzle(format: vk::Format) -> Option<bool> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Some(false),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Some(true),
+        _ => None,
+    }
+}
@@ -0,0 +1,270 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::render_context::VkError;
+use crate::submit::Submission;
+
+//Owns the graphics command pool and one primary command buffer per frame in
+//flight. The buffers get reset and reused every frame instead of being
+//reallocated
+pub struct CommandManager {
+    command_pool: vk::CommandPool,
+    frame_command_buffers: Vec<vk::CommandBuffer>,
+    device: Device,
+}
+
+impl CommandManager {
+    pub(crate) fn new(
+        device: &Device,
+        graphics_family: u32,
+        frames_in_flight: usize,
+    ) -> Result<CommandManager, VkError> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(graphics_family)
+            .build();
+        //SAFETY: pool_info was made with a builder
+        let command_pool =
+            unsafe { device.create_command_pool(&pool_info, None) }
+                .map_err(|e| VkError::new("vkCreateCommandPool", e))?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(frames_in_flight as u32)
+            .build();
+        //SAFETY: the pool was just made from this device
+        let frame_command_buffers =
+            match unsafe { device.allocate_command_buffers(&alloc_info) } {
+                Ok(command_buffers) => command_buffers,
+                Err(e) => {
+                    //SAFETY: nothing has been allocated from the pool
+                    unsafe { device.destroy_command_pool(command_pool, None) };
+                    return Err(VkError::new("vkAllocateCommandBuffers", e));
+                }
+            };
+
+        Ok(CommandManager {
+            command_pool,
+            frame_command_buffers,
+            device: device.clone(),
+        })
+    }
+
+    pub fn command_pool(&self) -> vk::CommandPool {
+        self.command_pool
+    }
+
+    //Reset this frame's command buffer and begin recording into it. The
+    //caller must make sure the GPU is done with the previous submission of
+    //this frame, e.g. by waiting on the frame's fence
+    pub fn begin_frame_commands(
+        &self,
+        frame_index: usize,
+    ) -> Result<vk::CommandBuffer, VkError> {
+        let command_buffer = self.frame_command_buffers[frame_index];
+        //SAFETY: the pool was made with RESET_COMMAND_BUFFER and the caller
+        //guarantees the buffer isn't in use
+        unsafe {
+            self.device
+                .reset_command_buffer(
+                    command_buffer,
+                    vk::CommandBufferResetFlags::empty(),
+                )
+                .map_err(|e| VkError::new("vkResetCommandBuffer", e))?;
+            self.device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                        .build(),
+                )
+                .map_err(|e| VkError::new("vkBeginCommandBuffer", e))?;
+        }
+        Ok(command_buffer)
+    }
+
+    //Record a throwaway command buffer with record, submit it to queue and
+    //block until it's done. Meant for setup work like uploads, not anything
+    //that happens every frame. queue has to be from the graphics family
+    pub fn submit_one_time<F>(
+        &self,
+        queue: vk::Queue,
+        record: F,
+    ) -> Result<(), VkError>
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+        //SAFETY: alloc_info was made with a builder from our own pool
+        let command_buffer =
+            unsafe { self.device.allocate_command_buffers(&alloc_info) }
+                .map_err(|e| VkError::new("vkAllocateCommandBuffers", e))?[0];
+        //SAFETY: default create infos are valid
+        let fence = match unsafe {
+            self.device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+        } {
+            Ok(fence) => fence,
+            Err(e) => {
+                //SAFETY: the command buffer hasn't been used
+                unsafe {
+                    self.device.free_command_buffers(
+                        self.command_pool,
+                        &[command_buffer],
+                    )
+                };
+                return Err(VkError::new("vkCreateFence", e));
+            }
+        };
+
+        //SAFETY: the command buffer is fresh so it can be begun, and we wait
+        //on the fence before freeing anything so nothing is still in use
+        let result = unsafe {
+            self.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .build(),
+            )
+        }
+        .map_err(|e| VkError::new("vkBeginCommandBuffer", e))
+        .and_then(|_| {
+            record(command_buffer);
+            //SAFETY: as above
+            unsafe {
+                self.device
+                    .end_command_buffer(command_buffer)
+                    .map_err(|e| VkError::new("vkEndCommandBuffer", e))?;
+                Submission::default()
+                    .command_buffer(command_buffer)
+                    .fence(fence)
+                    .submit(&self.device, queue)?;
+                self.device
+                    .wait_for_fences(&[fence], true, u64::MAX)
+                    .map_err(|e| VkError::new("vkWaitForFences", e))
+            }
+        });
+
+        //SAFETY: either the submission finished or it never happened
+        unsafe {
+            self.device.destroy_fence(fence, None);
+            self.device
+                .free_command_buffers(self.command_pool, &[command_buffer]);
+        }
+        result
+    }
+}
+
+//One worker thread's command pools for secondary command buffers. Pools
+//can't be shared between threads so every worker gets its own, and there's
+//one per frame in flight so a frame's buffers can all be reset at once
+//after its fence signals
+pub struct SecondaryPool {
+    command_pools: Vec<vk::CommandPool>,
+    //what's been allocated from each pool so far, reused once it's reset
+    command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    //how many of each pool's buffers have been handed out since its reset
+    used: Vec<usize>,
+    device: Device,
+}
+
+impl SecondaryPool {
+    pub(crate) fn new(
+        device: &Device,
+        graphics_family: u32,
+        frames_in_flight: usize,
+    ) -> Result<SecondaryPool, VkError> {
+        //anything we've made gets cleaned up by Drop if we bail
+        let mut pool = SecondaryPool {
+            command_pools: Vec::with_capacity(frames_in_flight),
+            command_buffers: vec![Vec::new(); frames_in_flight],
+            used: vec![0; frames_in_flight],
+            device: device.clone(),
+        };
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(graphics_family)
+            .build();
+        for _ in 0..frames_in_flight {
+            //SAFETY: pool_info was made with a builder
+            let command_pool =
+                unsafe { device.create_command_pool(&pool_info, None) }
+                    .map_err(|e| VkError::new("vkCreateCommandPool", e))?;
+            pool.command_pools.push(command_pool);
+        }
+        Ok(pool)
+    }
+
+    //Recycle everything frame_index's pool handed out. The caller must make
+    //sure the GPU is done with that frame, e.g. by waiting on its fence
+    pub(crate) fn reset(
+        &mut self,
+        frame_index: usize,
+    ) -> Result<(), vk::Result> {
+        //SAFETY: the caller guarantees none of the pool's buffers are in use
+        unsafe {
+            self.device.reset_command_pool(
+                self.command_pools[frame_index],
+                vk::CommandPoolResetFlags::empty(),
+            )
+        }?;
+        self.used[frame_index] = 0;
+        Ok(())
+    }
+
+    //A secondary command buffer from frame_index's pool that hasn't been
+    //handed out since the last reset, allocating one if need be
+    pub(crate) fn next(
+        &mut self,
+        frame_index: usize,
+    ) -> Result<vk::CommandBuffer, vk::Result> {
+        let used = self.used[frame_index];
+        let command_buffers = &mut self.command_buffers[frame_index];
+        if used == command_buffers.len() {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.command_pools[frame_index])
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1)
+                .build();
+            //SAFETY: alloc_info was made with a builder from our own pool
+            let command_buffer =
+                unsafe { self.device.allocate_command_buffers(&alloc_info) }?
+                    [0];
+            command_buffers.push(command_buffer);
+        }
+        self.used[frame_index] += 1;
+        Ok(command_buffers[used])
+    }
+}
+
+impl Drop for SecondaryPool {
+    fn drop(&mut self) {
+        //SAFETY: our owner waits for the device to go idle before dropping us.
+        //Destroying the pools frees the buffers with them
+        unsafe {
+            for &command_pool in &self.command_pools {
+                self.device.destroy_command_pool(command_pool, None);
+            }
+        }
+    }
+}
+
+impl Drop for CommandManager {
+    fn drop(&mut self) {
+        //SAFETY: our owner waits for the device to go idle before dropping us.
+        //Destroying the pool frees the buffers with it
+        unsafe {
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
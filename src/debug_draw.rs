@@ -0,0 +1,246 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::mem::offset_of;
+
+use ash::vk;
+
+use crate::{
+    camera::Mat4,
+    frame::FrameRecorder,
+    memory::FrameArena,
+    pipeline::{GraphicsPipelineBuilder, Pipeline},
+    render_context::{RenderContext, RenderContextError},
+    shader::ShaderModule,
+};
+
+//The built in line shaders, assembled by hand so the crate doesn't need a
+//shader compiler to build. They're equivalent to
+//
+//  layout(push_constant) uniform Push { mat4 view_proj; };
+//  layout(location = 0) in vec3 position;
+//  layout(location = 1) in vec4 color;
+//  layout(location = 0) out vec4 out_color;
+//  void main() {
+//      gl_Position = view_proj * vec4(position, 1.0);
+//      out_color = color;
+//  }
+//
+//and
+//
+//  layout(location = 0) in vec4 color;
+//  layout(location = 0) out vec4 out_color;
+//  void main() { out_color = color; }
+
+const LINE_VERT: &[u32] = &[
+    0x07230203, 0x00010000, 0x00000000, 0x0000001d, 0x00000000, 0x00020011,
+    0x00000001, 0x0003000e, 0x00000000, 0x00000001, 0x0009000f, 0x00000000,
+    0x00000001, 0x6e69616d, 0x00000000, 0x00000011, 0x00000012, 0x00000013,
+    0x00000014, 0x00040047, 0x00000011, 0x0000001e, 0x00000000, 0x00040047,
+    0x00000012, 0x0000001e, 0x00000001, 0x00040047, 0x00000013, 0x0000001e,
+    0x00000000, 0x00040047, 0x00000014, 0x0000000b, 0x00000000, 0x00030047,
+    0x00000008, 0x00000002, 0x00040048, 0x00000008, 0x00000000, 0x00000005,
+    0x00050048, 0x00000008, 0x00000000, 0x00000023, 0x00000000, 0x00050048,
+    0x00000008, 0x00000000, 0x00000007, 0x00000010, 0x00020013, 0x00000002,
+    0x00030021, 0x00000003, 0x00000002, 0x00030016, 0x00000004, 0x00000020,
+    0x00040017, 0x00000005, 0x00000004, 0x00000003, 0x00040017, 0x00000006,
+    0x00000004, 0x00000004, 0x00040018, 0x00000007, 0x00000006, 0x00000004,
+    0x0003001e, 0x00000008, 0x00000007, 0x00040020, 0x00000009, 0x00000009,
+    0x00000008, 0x0004003b, 0x00000009, 0x0000000a, 0x00000009, 0x00040015,
+    0x0000000b, 0x00000020, 0x00000001, 0x0004002b, 0x0000000b, 0x0000000c,
+    0x00000000, 0x00040020, 0x0000000d, 0x00000009, 0x00000007, 0x00040020,
+    0x0000000e, 0x00000001, 0x00000005, 0x00040020, 0x0000000f, 0x00000001,
+    0x00000006, 0x00040020, 0x00000010, 0x00000003, 0x00000006, 0x0004003b,
+    0x0000000e, 0x00000011, 0x00000001, 0x0004003b, 0x0000000f, 0x00000012,
+    0x00000001, 0x0004003b, 0x00000010, 0x00000013, 0x00000003, 0x0004003b,
+    0x00000010, 0x00000014, 0x00000003, 0x0004002b, 0x00000004, 0x00000015,
+    0x3f800000, 0x00050036, 0x00000002, 0x00000001, 0x00000000, 0x00000003,
+    0x000200f8, 0x00000016, 0x00050041, 0x0000000d, 0x00000017, 0x0000000a,
+    0x0000000c, 0x0004003d, 0x00000007, 0x00000018, 0x00000017, 0x0004003d,
+    0x00000005, 0x00000019, 0x00000011, 0x00050050, 0x00000006, 0x0000001a,
+    0x00000019, 0x00000015, 0x00050091, 0x00000006, 0x0000001b, 0x00000018,
+    0x0000001a, 0x0003003e, 0x00000014, 0x0000001b, 0x0004003d, 0x00000006,
+    0x0000001c, 0x00000012, 0x0003003e, 0x00000013, 0x0000001c, 0x000100fd,
+    0x00010038,
+];
+
+const LINE_FRAG: &[u32] = &[
+    0x07230203, 0x00010000, 0x00000000, 0x0000000c, 0x00000000, 0x00020011,
+    0x00000001, 0x0003000e, 0x00000000, 0x00000001, 0x0007000f, 0x00000004,
+    0x00000001, 0x6e69616d, 0x00000000, 0x00000008, 0x00000009, 0x00030010,
+    0x00000001, 0x00000007, 0x00040047, 0x00000008, 0x0000001e, 0x00000000,
+    0x00040047, 0x00000009, 0x0000001e, 0x00000000, 0x00020013, 0x00000002,
+    0x00030021, 0x00000003, 0x00000002, 0x00030016, 0x00000004, 0x00000020,
+    0x00040017, 0x00000005, 0x00000004, 0x00000004, 0x00040020, 0x00000006,
+    0x00000001, 0x00000005, 0x00040020, 0x00000007, 0x00000003, 0x00000005,
+    0x0004003b, 0x00000006, 0x00000008, 0x00000001, 0x0004003b, 0x00000007,
+    0x00000009, 0x00000003, 0x00050036, 0x00000002, 0x00000001, 0x00000000,
+    0x00000003, 0x000200f8, 0x0000000a, 0x0004003d, 0x00000005, 0x0000000b,
+    0x00000008, 0x0003003e, 0x00000009, 0x0000000b, 0x000100fd, 0x00010038,
+];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+//Collects world space lines over a frame and draws them all at once with
+//flush, for seeing bounds, physics shapes, culling volumes and the like.
+//They're depth tested against the rest of the frame's draws but don't
+//write depth, so draw them last. Colors are RGBA with alpha blending
+pub struct DebugDraw {
+    pipeline: Pipeline,
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    //Build the line pipeline for the context's own render pass
+    pub fn new(
+        render_context: &RenderContext,
+    ) -> Result<DebugDraw, RenderContextError> {
+        let device = render_context.device();
+        let vertex_shader = ShaderModule::new(device, LINE_VERT)
+            .map_err(RenderContextError::ShaderModuleCreationFailed)?;
+        let fragment_shader = ShaderModule::new(device, LINE_FRAG)
+            .map_err(RenderContextError::ShaderModuleCreationFailed)?;
+        let pipeline = GraphicsPipelineBuilder::new(
+            &vertex_shader,
+            &fragment_shader,
+            render_context.render_pass(),
+        )
+        .vertex_input(
+            &[vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<DebugVertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            &[
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: offset_of!(DebugVertex, position) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: offset_of!(DebugVertex, color) as u32,
+                },
+            ],
+        )
+        .topology(vk::PrimitiveTopology::LINE_LIST)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .alpha_blending(true)
+        .depth_write(false)
+        .push_constant_range(
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            std::mem::size_of::<Mat4>() as u32,
+        )
+        .name("debug lines")
+        .build(render_context)?;
+        Ok(DebugDraw {
+            pipeline,
+            vertices: Vec::new(),
+        })
+    }
+
+    pub fn draw_line(&mut self, a: [f32; 3], b: [f32; 3], color: [f32; 4]) {
+        self.vertices.push(DebugVertex { position: a, color });
+        self.vertices.push(DebugVertex { position: b, color });
+    }
+
+    //A small cross size wide centered on point, one line along each axis
+    pub fn draw_point(&mut self, point: [f32; 3], size: f32, color: [f32; 4]) {
+        let half = size / 2.0;
+        for axis in 0..3 {
+            let mut a = point;
+            let mut b = point;
+            a[axis] -= half;
+            b[axis] += half;
+            self.draw_line(a, b, color);
+        }
+    }
+
+    //The 12 edges of the axis aligned box between min and max
+    pub fn draw_aabb(&mut self, min: [f32; 3], max: [f32; 3], color: [f32; 4]) {
+        let corner = |i: usize| {
+            [
+                if i & 1 == 0 { min[0] } else { max[0] },
+                if i & 2 == 0 { min[1] } else { max[1] },
+                if i & 4 == 0 { min[2] } else { max[2] },
+            ]
+        };
+        //corners one bit apart share an edge
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.draw_line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    //transform's x, y and z axes from its origin, in red, green and blue.
+    //The lines are as long as the axes are, so 1 for an unscaled transform
+    pub fn draw_axes(&mut self, transform: &Mat4) {
+        let origin = [transform[3][0], transform[3][1], transform[3][2]];
+        for (axis, color) in transform[..3].iter().zip([
+            [1.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 1.0],
+        ]) {
+            let end = [
+                origin[0] + axis[0],
+                origin[1] + axis[1],
+                origin[2] + axis[2],
+            ];
+            self.draw_line(origin, end, color);
+        }
+    }
+
+    //How many lines are waiting for the next flush
+    pub fn line_count(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    //Throw away everything drawn since the last flush without drawing it
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    //Draw everything collected since the last flush with view_proj, copying
+    //the vertices into arena, then start collecting again. arena has to
+    //have been reset for this frame already. If the lines don't fit they're
+    //dropped with a warning
+    pub fn flush(
+        &mut self,
+        recorder: &FrameRecorder,
+        arena: &mut FrameArena,
+        view_proj: &Mat4,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        match arena.alloc_slice(&self.vertices) {
+            Some(slice) => {
+                recorder.bind_pipeline(&self.pipeline);
+                recorder
+                    .push_constants(view_proj, vk::ShaderStageFlags::VERTEX);
+                recorder.bind_vertex_slice(0, slice);
+                recorder.draw(self.vertices.len() as u32, 0);
+            }
+            None => log::warn!(
+                "{} debug lines don't fit in what's left of the frame arena",
+                self.line_count()
+            ),
+        }
+        self.vertices.clear();
+    }
+}
@@ -0,0 +1,445 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+#[cfg(feature = "bindless")]
+use std::collections::VecDeque;
+
+use ash::{vk, Device};
+
+use crate::render_context::{RenderContext, RenderContextError};
+
+//Describes what a shader expects to find in a descriptor set. Each binding
+//holds a single descriptor
+#[derive(Default)]
+pub struct DescriptorSetLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_uniform_buffer(
+        self,
+        binding: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.add_binding(binding, vk::DescriptorType::UNIFORM_BUFFER, stages)
+    }
+
+    pub fn add_combined_image_sampler(
+        self,
+        binding: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.add_binding(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            stages,
+        )
+    }
+
+    fn add_binding(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(1)
+                .stage_flags(stages)
+                .build(),
+        );
+        self
+    }
+
+    pub fn build(
+        self,
+        render_context: &RenderContext,
+    ) -> Result<DescriptorSetLayout, RenderContextError> {
+        let device = render_context.device();
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&self.bindings)
+            .build();
+        //SAFETY: create_info was made with a builder and the bindings live
+        //until the end of this function
+        let layout =
+            unsafe { device.create_descriptor_set_layout(&create_info, None) }
+                .map_err(RenderContextError::DescriptorCreationFailed)?;
+        Ok(DescriptorSetLayout {
+            layout,
+            bindings: self.bindings,
+            device: device.clone(),
+        })
+    }
+}
+
+pub struct DescriptorSetLayout {
+    layout: vk::DescriptorSetLayout,
+    //kept around so pools can be sized to fit sets of this layout
+    bindings: Vec<vk::DescriptorSetLayoutBinding>,
+    device: Device,
+}
+
+impl DescriptorSetLayout {
+    pub fn handle(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+}
+
+impl Drop for DescriptorSetLayout {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure nothing is still using the layout
+        unsafe { self.device.destroy_descriptor_set_layout(self.layout, None) }
+    }
+}
+
+//A pool with room for exactly one set of layout per frame in flight. Sets
+//are freed along with the pool
+pub struct DescriptorPool {
+    pool: vk::DescriptorPool,
+    sets: u32,
+    device: Device,
+}
+
+impl DescriptorPool {
+    pub fn new(
+        render_context: &RenderContext,
+        layout: &DescriptorSetLayout,
+    ) -> Result<DescriptorPool, RenderContextError> {
+        let device = render_context.device();
+        let sets = render_context.frames_in_flight() as u32;
+
+        let mut pool_sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+        for binding in &layout.bindings {
+            let count = binding.descriptor_count * sets;
+            match pool_sizes
+                .iter_mut()
+                .find(|size| size.ty == binding.descriptor_type)
+            {
+                Some(size) => size.descriptor_count += count,
+                None => pool_sizes.push(vk::DescriptorPoolSize {
+                    ty: binding.descriptor_type,
+                    descriptor_count: count,
+                }),
+            }
+        }
+
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(sets)
+            .pool_sizes(&pool_sizes)
+            .build();
+        //SAFETY: create_info was made with a builder and pool_sizes lives
+        //until the end of this function
+        let pool = unsafe { device.create_descriptor_pool(&create_info, None) }
+            .map_err(RenderContextError::DescriptorCreationFailed)?;
+        Ok(DescriptorPool {
+            pool,
+            sets,
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::DescriptorPool {
+        self.pool
+    }
+
+    //One set of layout per frame in flight, indexed by frame index. The pool
+    //only has room for one call to this
+    pub fn allocate_sets(
+        &self,
+        layout: &DescriptorSetLayout,
+    ) -> Result<Vec<vk::DescriptorSet>, RenderContextError> {
+        let layouts = vec![layout.handle(); self.sets as usize];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool)
+            .set_layouts(&layouts)
+            .build();
+        //SAFETY: alloc_info was made with a builder from our own pool
+        unsafe { self.device.allocate_descriptor_sets(&alloc_info) }
+            .map_err(RenderContextError::DescriptorCreationFailed)
+    }
+
+    //Point binding in set at a buffer, e.g. UniformBuffer::descriptor_info.
+    //The set must not be in use by the GPU
+    pub fn update_uniform(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        buffer_info: vk::DescriptorBufferInfo,
+    ) {
+        let buffer_infos = [buffer_info];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_infos)
+            .build();
+        //SAFETY: write was made with a builder and buffer_infos outlives it
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) }
+    }
+
+    //Point binding in set at an image view sampled with sampler. The image
+    //has to be in SHADER_READ_ONLY_OPTIMAL whenever the set is used
+    pub fn update_sampler(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) {
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        //SAFETY: write was made with a builder and image_infos outlives it
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) }
+    }
+}
+
+impl Drop for DescriptorPool {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with every set
+        //from this pool before dropping it
+        unsafe { self.device.destroy_descriptor_pool(self.pool, None) }
+    }
+}
+
+//One descriptor set holding a single big array of combined image samplers
+//at binding 0, which shaders index into with a u32 handed out by add,
+//usually passed in through push constants. Slots that haven't been added
+//to are left unbound, so shaders must only read slots they were given. The
+//set stays the same for every frame in flight and can be updated while
+//frames using it are still in flight
+#[cfg(feature = "bindless")]
+pub struct BindlessTextureArray {
+    set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+    layout: DescriptorSetLayout,
+    capacity: u32,
+    //slots from here on have never been handed out
+    next_unused: u32,
+    free: Vec<u32>,
+    //removed slots along with the frames_submitted count after which no
+    //frame still in flight can be reading them
+    retiring: VecDeque<(u32, u64)>,
+    device: Device,
+}
+
+#[cfg(feature = "bindless")]
+impl BindlessTextureArray {
+    //Room for up to capacity textures, visible to stages. capacity gets
+    //clamped to what the device can bind after the fact
+    pub fn new(
+        render_context: &RenderContext,
+        capacity: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Result<BindlessTextureArray, RenderContextError> {
+        if !render_context.descriptor_indexing_supported() {
+            return Err(RenderContextError::DescriptorIndexingUnsupported);
+        }
+        let device = render_context.device();
+
+        let mut indexing_properties =
+            vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut indexing_properties)
+            .build();
+        //SAFETY: descriptor indexing is only enabled on 1.1 and up so
+        //properties2 can be queried, and indexing_properties outlives the
+        //call
+        unsafe {
+            render_context.instance().get_physical_device_properties2(
+                render_context.physical_device_info().handle,
+                &mut properties2,
+            )
+        };
+        let max = indexing_properties
+            .max_per_stage_descriptor_update_after_bind_sampled_images
+            .min(
+                indexing_properties
+                    .max_descriptor_set_update_after_bind_sampled_images,
+            )
+            .min(
+                indexing_properties
+                    .max_per_stage_descriptor_update_after_bind_samplers,
+            );
+        let capacity = if capacity > max {
+            log::warn!(
+                "bindless texture arrays can only hold {} textures on this \
+                device, not {}",
+                max,
+                capacity
+            );
+            max
+        } else {
+            capacity
+        };
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .stage_flags(stages)
+            .build()];
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING];
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+                .binding_flags(&binding_flags);
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags_info)
+            .build();
+        //SAFETY: create_info was made with a builder and everything it points
+        //at lives until the end of this function
+        let layout =
+            unsafe { device.create_descriptor_set_layout(&create_info, None) }
+                .map_err(RenderContextError::DescriptorCreationFailed)?;
+        let layout = DescriptorSetLayout {
+            layout,
+            bindings: bindings.to_vec(),
+            device: device.clone(),
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: capacity,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&pool_sizes)
+            .build();
+        //SAFETY: pool_info was made with a builder and pool_sizes lives
+        //until the end of this function
+        let pool = unsafe { device.create_descriptor_pool(&pool_info, None) }
+            .map_err(RenderContextError::DescriptorCreationFailed)?;
+
+        let layouts = [layout.handle()];
+        let counts = [capacity];
+        let mut count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                .descriptor_counts(&counts);
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts)
+            .push_next(&mut count_info)
+            .build();
+        //SAFETY: alloc_info was made with a builder from the pool above
+        let set = match unsafe { device.allocate_descriptor_sets(&alloc_info) }
+        {
+            Ok(sets) => sets[0],
+            Err(e) => {
+                //SAFETY: nothing has been allocated from the pool
+                unsafe { device.destroy_descriptor_pool(pool, None) };
+                return Err(RenderContextError::DescriptorCreationFailed(e));
+            }
+        };
+        render_context.set_object_name(set, "bindless textures");
+
+        Ok(BindlessTextureArray {
+            set,
+            pool,
+            layout,
+            capacity,
+            next_unused: 0,
+            free: Vec::new(),
+            retiring: VecDeque::new(),
+            device: device.clone(),
+        })
+    }
+
+    //For pipeline layouts and binding
+    pub fn layout(&self) -> &DescriptorSetLayout {
+        &self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    //Put a texture in a free slot and hand back its index, e.g. from
+    //Texture::descriptor_info. None when every slot is taken. The image has
+    //to stay alive and in image.image_layout until it's removed
+    pub fn add(
+        &mut self,
+        render_context: &RenderContext,
+        image: vk::DescriptorImageInfo,
+    ) -> Option<u32> {
+        let frames_submitted = render_context.frames_submitted();
+        while let Some(&(index, retired_after)) = self.retiring.front() {
+            if retired_after > frames_submitted {
+                break;
+            }
+            self.retiring.pop_front();
+            self.free.push(index);
+        }
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if self.next_unused < self.capacity => {
+                self.next_unused += 1;
+                self.next_unused - 1
+            }
+            None => return None,
+        };
+        let image_infos = [image];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        //SAFETY: write was made with a builder and image_infos outlives it.
+        //No frame in flight reads this slot since it was either never used
+        //or retired after every frame that could have read it finished
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) }
+        Some(index)
+    }
+
+    //Give index back. Frames already submitted may still read it, so it
+    //only gets reused once they're done. Shaders must not read it in
+    //frames recorded after this
+    pub fn remove(&mut self, render_context: &RenderContext, index: u32) {
+        assert!(
+            index < self.next_unused,
+            "index {} was never handed out",
+            index
+        );
+        let retired_after = render_context.frames_submitted()
+            + render_context.frames_in_flight() as u64;
+        self.retiring.push_back((index, retired_after));
+    }
+}
+
+#[cfg(feature = "bindless")]
+impl Drop for BindlessTextureArray {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the set
+        //before dropping it. The set is freed along with the pool and the
+        //layout is dropped after this
+        unsafe { self.device.destroy_descriptor_pool(self.pool, None) }
+    }
+}
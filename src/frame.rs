@@ -0,0 +1,1034 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{
+    cell::Cell,
+    ffi::CString,
+    time::{Duration, Instant},
+};
+
+use ash::{extensions::ext::DebugUtils, vk, Device};
+
+#[cfg(feature = "allocator")]
+use crate::{
+    barrier::compute_to_graphics_barrier,
+    memory::{DrawIndirectBuffer, IndexBuffer, InstanceBuffer, VertexBuffer},
+    offscreen::OffscreenTarget,
+};
+use crate::{
+    command::SecondaryPool,
+    pipeline::{Pipeline, PipelineVariants},
+    query::OcclusionQueryPool,
+    render_context::{RenderContextError, VkError},
+};
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+//How often FrameStats::fps gets recomputed
+const FPS_WINDOW: Duration = Duration::from_millis(500);
+
+//CPU side frame timing as seen by draw_frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    //frames per second averaged over the last half second or so. 0 until
+    //the first half second has passed
+    pub fps: f64,
+    //time between the last two frames
+    pub frame_time_ms: f64,
+    //frames submitted since the context was made
+    pub frame_count: u64,
+}
+
+//Fills in FrameStats as frames get submitted
+#[derive(Default)]
+pub(crate) struct FrameTimer {
+    last_frame: Option<Instant>,
+    window_start: Option<Instant>,
+    window_frames: u32,
+    stats: FrameStats,
+}
+
+impl FrameTimer {
+    pub fn frame_done(&mut self) {
+        let now = Instant::now();
+        self.stats.frame_count += 1;
+        if let Some(last_frame) = self.last_frame {
+            self.stats.frame_time_ms =
+                now.duration_since(last_frame).as_secs_f64() * 1000.0;
+        }
+        self.last_frame = Some(now);
+        //the frame that starts a window only marks when it began
+        let Some(window_start) = self.window_start else {
+            self.window_start = Some(now);
+            return;
+        };
+        self.window_frames += 1;
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= FPS_WINDOW {
+            self.stats.fps = self.window_frames as f64 / elapsed.as_secs_f64();
+            self.window_start = Some(now);
+            self.window_frames = 0;
+        }
+    }
+
+    //Forget when the last frame was, e.g. after a pause, so the gap
+    //doesn't show up as one very long frame
+    pub fn restart(&mut self) {
+        self.last_frame = None;
+        self.window_start = None;
+        self.window_frames = 0;
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
+
+//What to do after acquiring or presenting a swapchain image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentOutcome {
+    Ok,
+    //still usable but doesn't match the surface exactly anymore. Finish the
+    //frame then recreate
+    Suboptimal,
+    //can't be used at all until the swapchain is recreated
+    NeedsRecreate,
+    Failed(vk::Result),
+}
+
+//Sort the result codes acquire_next_image and queue_present can give us
+//into what we should do about them
+pub fn classify_present_result(result: vk::Result) -> PresentOutcome {
+    match result {
+        vk::Result::SUCCESS => PresentOutcome::Ok,
+        vk::Result::SUBOPTIMAL_KHR => PresentOutcome::Suboptimal,
+        vk::Result::ERROR_OUT_OF_DATE_KHR => PresentOutcome::NeedsRecreate,
+        e => PresentOutcome::Failed(e),
+    }
+}
+
+//ash folds SUCCESS and SUBOPTIMAL_KHR into Ok(bool) so unfold them again
+pub(crate) fn present_result_code<T>(
+    result: &Result<T, vk::Result>,
+    suboptimal: impl Fn(&T) -> bool,
+) -> vk::Result {
+    match result {
+        Ok(value) if suboptimal(value) => vk::Result::SUBOPTIMAL_KHR,
+        Ok(_) => vk::Result::SUCCESS,
+        Err(e) => *e,
+    }
+}
+
+//Sync objects for one frame in flight
+pub(crate) struct FrameSync {
+    //signaled when the swapchain image we acquired is ready to draw to
+    pub image_available: vk::Semaphore,
+    //signaled when our commands are done so the image can be presented
+    pub render_finished: vk::Semaphore,
+    //signaled when the GPU is done with everything this frame submitted so
+    //we can reuse its resources
+    pub in_flight: vk::Fence,
+}
+
+//Owns the per frame sync objects and tracks which frame we're on
+pub(crate) struct Frames {
+    frames: Vec<FrameSync>,
+    current: usize,
+    device: Device,
+}
+
+impl Frames {
+    pub fn new(
+        device: &Device,
+        frames_in_flight: usize,
+    ) -> Result<Frames, VkError> {
+        //anything we've made gets cleaned up by Drop if we bail
+        let mut frames = Frames {
+            frames: Vec::with_capacity(frames_in_flight),
+            current: 0,
+            device: device.clone(),
+        };
+
+        for _ in 0..frames_in_flight {
+            //SAFETY: default create infos are valid. Fences start signaled so
+            //the first wait on each frame doesn't block forever
+            unsafe {
+                let image_available = device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                    .map_err(|e| VkError::new("vkCreateSemaphore", e))?;
+                let render_finished = device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                    .map_err(|e| {
+                        device.destroy_semaphore(image_available, None);
+                        VkError::new("vkCreateSemaphore", e)
+                    })?;
+                let in_flight = device
+                    .create_fence(
+                        &vk::FenceCreateInfo::builder()
+                            .flags(vk::FenceCreateFlags::SIGNALED)
+                            .build(),
+                        None,
+                    )
+                    .map_err(|e| {
+                        device.destroy_semaphore(image_available, None);
+                        device.destroy_semaphore(render_finished, None);
+                        VkError::new("vkCreateFence", e)
+                    })?;
+                frames.frames.push(FrameSync {
+                    image_available,
+                    render_finished,
+                    in_flight,
+                });
+            }
+        }
+        Ok(frames)
+    }
+
+    pub fn current(&self) -> &FrameSync {
+        &self.frames[self.current]
+    }
+
+    pub fn get(&self, index: usize) -> &FrameSync {
+        &self.frames[index]
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.frames.len();
+    }
+
+    //How many frames the GPU is still working on, going by which fences
+    //are unsignaled. Fences we can't query count as unfinished
+    pub fn unfinished(&self) -> usize {
+        self.frames
+            .iter()
+            .filter(|frame| {
+                //SAFETY: the fence is ours and alive
+                let status =
+                    unsafe { self.device.get_fence_status(frame.in_flight) };
+                !matches!(status, Ok(true))
+            })
+            .count()
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        //SAFETY: our owner waits for the device to go idle before dropping us
+        //so none of these are in use
+        unsafe {
+            for frame in &self.frames {
+                self.device.destroy_semaphore(frame.image_available, None);
+                self.device.destroy_semaphore(frame.render_finished, None);
+                self.device.destroy_fence(frame.in_flight, None);
+            }
+        }
+    }
+}
+
+//Handed to the draw_frame callback. The command buffer has already been
+//begun and is inside the context's render pass, targeting framebuffer().
+//The pass is ended and the command buffer submitted after the callback
+//returns. ComputeRecorder::draw_offscreen hands out one of these for an
+//OffscreenTarget's pass too
+pub struct FrameRecorder<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) command_buffer: vk::CommandBuffer,
+    pub(crate) image: vk::Image,
+    pub(crate) image_view: vk::ImageView,
+    pub(crate) framebuffer: vk::Framebuffer,
+    //layout of the last pipeline bound through bind_pipeline, for
+    //push_constants
+    pub(crate) bound_layout: Cell<vk::PipelineLayout>,
+    pub(crate) extent: vk::Extent2D,
+    pub(crate) frame_index: usize,
+    //set through RenderContext::set_wireframe
+    pub(crate) wireframe: bool,
+    //whether the multiDrawIndirect feature was required
+    #[cfg_attr(not(feature = "allocator"), allow(dead_code))]
+    pub(crate) multi_draw_indirect: bool,
+    //None when validation is off, which turns the debug label calls into
+    //no-ops
+    pub(crate) debug_utils: Option<&'a DebugUtils>,
+}
+
+impl<'a> FrameRecorder<'a> {
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    //The image being drawn this frame. A swapchain image, the target of a
+    //headless context or an OffscreenTarget's color image
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn image_view(&self) -> vk::ImageView {
+        self.image_view
+    }
+
+    //Null when the context draws with dynamic rendering
+    pub fn framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffer
+    }
+
+    //Size of the target in physical pixels
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    //Which frame in flight this is, for indexing per frame resources
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    //Draw to just part of the target from here on, e.g. one half for split
+    //screen. Only pipelines with a dynamic viewport, the default, listen to
+    //this. Every frame starts out covering the whole target. In physical
+    //pixels, like extent
+    pub fn set_viewport(&self, x: f32, y: f32, width: f32, height: f32) {
+        let viewport = vk::Viewport {
+            x,
+            y,
+            width,
+            height,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        //SAFETY: the command buffer is recording
+        unsafe {
+            self.device
+                .cmd_set_viewport(self.command_buffer, 0, &[viewport])
+        }
+    }
+
+    //Throw away anything drawn outside rect. Like set_viewport this only
+    //matters to pipelines with a dynamic scissor and starts out covering the
+    //whole target
+    pub fn set_scissor(&self, rect: vk::Rect2D) {
+        //SAFETY: the command buffer is recording
+        unsafe { self.device.cmd_set_scissor(self.command_buffer, 0, &[rect]) }
+    }
+
+    //Cover the whole target with the viewport and scissor again
+    pub fn reset_viewport(&self) {
+        self.set_viewport(
+            0.0,
+            0.0,
+            self.extent.width as f32,
+            self.extent.height as f32,
+        );
+        self.set_scissor(vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent: self.extent,
+        });
+    }
+
+    //Whether RenderContext::set_wireframe asked for wireframes
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    //Bind whichever of the variants RenderContext::set_wireframe picked
+    pub fn bind_variant(&self, variants: &PipelineVariants) {
+        self.bind_pipeline(variants.get(self.wireframe));
+    }
+
+    pub fn bind_pipeline(&self, pipeline: &Pipeline) {
+        //SAFETY: the command buffer is recording and the pipeline is alive
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                self.command_buffer,
+                pipeline.bind_point(),
+                pipeline.handle(),
+            )
+        }
+        self.bound_layout.set(pipeline.layout());
+    }
+
+    //Bind set as set number index of the pipeline last bound with
+    //bind_pipeline
+    pub fn bind_descriptor_set(&self, index: u32, set: vk::DescriptorSet) {
+        let layout = self.bound_layout.get();
+        assert!(
+            layout != vk::PipelineLayout::null(),
+            "bind a pipeline before binding descriptor sets"
+        );
+        //SAFETY: the command buffer is recording and layout came from a
+        //pipeline that is bound to it
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                index,
+                &[set],
+                &[],
+            )
+        }
+    }
+
+    //Push value at offset 0 for stages of the pipeline last bound with
+    //bind_pipeline. T should be repr(C) with no padding so every byte the
+    //shader sees is initialized
+    pub fn push_constants<T: Copy>(
+        &self,
+        value: &T,
+        stages: vk::ShaderStageFlags,
+    ) {
+        push_constants(
+            self.device,
+            self.command_buffer,
+            self.bound_layout.get(),
+            value,
+            stages,
+        )
+    }
+
+    #[cfg(feature = "allocator")]
+    pub fn bind_vertex_buffer(&self, binding: u32, vertices: &VertexBuffer) {
+        //SAFETY: the command buffer is recording and the buffer is alive
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                binding,
+                &[vertices.handle()],
+                &[0],
+            )
+        }
+    }
+
+    //Bind vertices written somewhere other than a VertexBuffer, e.g. a
+    //FrameArena allocation
+    pub fn bind_vertex_slice(
+        &self,
+        binding: u32,
+        (buffer, offset): (vk::Buffer, vk::DeviceSize),
+    ) {
+        //SAFETY: the command buffer is recording and the caller keeps the
+        //buffer alive until the frame is done
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                binding,
+                &[buffer],
+                &[offset],
+            )
+        }
+    }
+
+    //Bind indices written somewhere other than an IndexBuffer, e.g. a
+    //FrameArena allocation
+    pub fn bind_index_slice(
+        &self,
+        (buffer, offset): (vk::Buffer, vk::DeviceSize),
+        index_type: vk::IndexType,
+    ) {
+        //SAFETY: the command buffer is recording and the caller keeps the
+        //buffer alive until the frame is done
+        unsafe {
+            self.device.cmd_bind_index_buffer(
+                self.command_buffer,
+                buffer,
+                offset,
+                index_type,
+            )
+        }
+    }
+
+    //Bind this frame's copy of instances, for a pipeline with an
+    //instance_input at binding
+    #[cfg(feature = "allocator")]
+    pub fn bind_instance_buffer<T: Copy>(
+        &self,
+        binding: u32,
+        instances: &InstanceBuffer<T>,
+    ) {
+        //SAFETY: the command buffer is recording and the buffer is alive
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                binding,
+                &[instances.handle(self.frame_index)],
+                &[0],
+            )
+        }
+    }
+
+    #[cfg(feature = "allocator")]
+    pub fn bind_index_buffer(&self, indices: &IndexBuffer) {
+        //SAFETY: the command buffer is recording and the buffer is alive
+        unsafe {
+            self.device.cmd_bind_index_buffer(
+                self.command_buffer,
+                indices.handle(),
+                0,
+                indices.index_type(),
+            )
+        }
+    }
+
+    //Draw vertex_count vertices out of the bound vertex buffers starting
+    //at first_vertex. A pipeline and its vertex buffers have to be bound
+    //already
+    pub fn draw(&self, vertex_count: u32, first_vertex: u32) {
+        //SAFETY: the command buffer is recording inside the render pass
+        unsafe {
+            self.device.cmd_draw(
+                self.command_buffer,
+                vertex_count,
+                1,
+                first_vertex,
+                0,
+            );
+        }
+    }
+
+    //Draw index_count bound indices starting at first_index,
+    //instance_count times. A pipeline, its vertex and instance buffers and
+    //an index buffer have to be bound already
+    pub fn draw_indexed_instanced(
+        &self,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) {
+        //SAFETY: the command buffer is recording inside the render pass
+        unsafe {
+            self.device.cmd_draw_indexed(
+                self.command_buffer,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            );
+        }
+    }
+
+    //Draw draw_count commands out of commands, starting offset bytes in
+    //and stride bytes apart. A pipeline, its vertex buffers and an index
+    //buffer have to be bound already. Without the multiDrawIndirect
+    //feature each command is drawn on its own
+    #[cfg(feature = "allocator")]
+    pub fn draw_indexed_indirect(
+        &self,
+        commands: &DrawIndirectBuffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        //SAFETY: the command buffer is recording inside the render pass and
+        //the buffer is alive
+        unsafe {
+            if draw_count <= 1 || self.multi_draw_indirect {
+                self.device.cmd_draw_indexed_indirect(
+                    self.command_buffer,
+                    commands.handle(),
+                    offset,
+                    draw_count,
+                    stride,
+                );
+                return;
+            }
+            for draw in 0..draw_count as vk::DeviceSize {
+                self.device.cmd_draw_indexed_indirect(
+                    self.command_buffer,
+                    commands.handle(),
+                    offset + draw * stride as vk::DeviceSize,
+                    1,
+                    stride,
+                );
+            }
+        }
+    }
+
+    //Start counting the samples that pass depth and stencil testing into
+    //query index of this frame's share of pool. The query has to have been
+    //reset this frame through ComputeRecorder::reset_occlusion_queries
+    pub fn begin_query(&self, pool: &OcclusionQueryPool, index: u32) {
+        pool.begin(self.device, self.command_buffer, self.frame_index, index)
+    }
+
+    //Stop counting into the query begin_query started
+    pub fn end_query(&self, pool: &OcclusionQueryPool, index: u32) {
+        pool.end(self.device, self.command_buffer, self.frame_index, index)
+    }
+
+    //Bind indices and draw all of them once. A pipeline and any vertex
+    //buffers it needs have to be bound already
+    #[cfg(feature = "allocator")]
+    pub fn draw_indexed(&self, indices: &IndexBuffer) {
+        //SAFETY: the command buffer is recording inside the render pass and
+        //the buffer is alive
+        unsafe {
+            self.device.cmd_bind_index_buffer(
+                self.command_buffer,
+                indices.handle(),
+                0,
+                indices.index_type(),
+            );
+            self.device.cmd_draw_indexed(
+                self.command_buffer,
+                indices.count(),
+                1,
+                0,
+                0,
+                0,
+            );
+        }
+    }
+
+    //Open a labeled region that RenderDoc, Nsight and validation messages
+    //group the following commands under. Every begin needs a matching
+    //end_debug_label before the frame is done. Regions can nest
+    pub fn begin_debug_label(&self, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = self.debug_utils {
+            let label_name = debug_label_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&label_name)
+                .color(color)
+                .build();
+            //SAFETY: the command buffer is recording and label_name outlives
+            //the call
+            unsafe {
+                debug_utils
+                    .cmd_begin_debug_utils_label(self.command_buffer, &label)
+            }
+        }
+    }
+
+    //Close the region opened by the last begin_debug_label
+    pub fn end_debug_label(&self) {
+        if let Some(debug_utils) = self.debug_utils {
+            //SAFETY: the command buffer is recording and the caller opened a
+            //region for us to close
+            unsafe {
+                debug_utils.cmd_end_debug_utils_label(self.command_buffer)
+            }
+        }
+    }
+
+    //Mark a single point in the command stream rather than a region
+    pub fn insert_debug_label(&self, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = self.debug_utils {
+            let label_name = debug_label_name(name);
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&label_name)
+                .color(color)
+                .build();
+            //SAFETY: the command buffer is recording and label_name outlives
+            //the call
+            unsafe {
+                debug_utils
+                    .cmd_insert_debug_utils_label(self.command_buffer, &label)
+            }
+        }
+    }
+}
+
+//What secondary command buffers need to know about the pass they continue
+#[derive(Clone, Copy)]
+pub(crate) struct PassInheritance {
+    //null for dynamic passes, which describe their formats instead
+    pub render_pass: vk::RenderPass,
+    pub color_format: vk::Format,
+    //UNDEFINED when there's no depth attachment
+    pub depth_format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl<'a> FrameRecorder<'a> {
+    //Reset this frame's pools, let record fill in secondary command buffers
+    //with a SecondaryRecorder per pool, then execute them all. Only valid
+    //while the pass was begun for secondary command buffers
+    pub(crate) fn record_secondaries<F>(
+        &self,
+        pools: &mut [SecondaryPool],
+        inheritance: PassInheritance,
+        image_index: u32,
+        record: F,
+    ) -> Result<(), RenderContextError>
+    where
+        F: FnOnce(&mut [SecondaryRecorder], u32),
+    {
+        for pool in pools.iter_mut() {
+            pool.reset(self.frame_index).map_err(VkError::with_op(
+                "vkResetCommandPool",
+                RenderContextError::DrawFrameFailed,
+            ))?;
+        }
+        let mut recorders: Vec<_> = pools
+            .iter_mut()
+            .map(|pool| SecondaryRecorder {
+                pool,
+                inheritance,
+                device: self.device,
+                image: self.image,
+                image_view: self.image_view,
+                framebuffer: self.framebuffer,
+                extent: self.extent,
+                frame_index: self.frame_index,
+                wireframe: self.wireframe,
+                multi_draw_indirect: self.multi_draw_indirect,
+                debug_utils: self.debug_utils,
+                recorded: Vec::new(),
+            })
+            .collect();
+        record(&mut recorders, image_index);
+        let command_buffers: Vec<_> = recorders
+            .into_iter()
+            .flat_map(|recorder| recorder.recorded)
+            .collect();
+        if !command_buffers.is_empty() {
+            //SAFETY: the pass was begun for secondary command buffers and
+            //these were all recorded to continue it
+            unsafe {
+                self.device
+                    .cmd_execute_commands(self.command_buffer, &command_buffers)
+            }
+        }
+        Ok(())
+    }
+}
+
+//Handed out one per worker to the callback of
+//RenderContext::draw_frame_parallel. Each can be sent off to its own thread
+//to record secondary command buffers continuing the frame's pass. They're
+//executed in worker order, and in the order each worker recorded them
+pub struct SecondaryRecorder<'a> {
+    pool: &'a mut SecondaryPool,
+    inheritance: PassInheritance,
+    device: &'a Device,
+    image: vk::Image,
+    image_view: vk::ImageView,
+    //null for dynamic passes
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    frame_index: usize,
+    wireframe: bool,
+    multi_draw_indirect: bool,
+    debug_utils: Option<&'a DebugUtils>,
+    recorded: Vec<vk::CommandBuffer>,
+}
+
+impl<'a> SecondaryRecorder<'a> {
+    //Which frame in flight this is, for indexing per frame resources
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    //Record one secondary command buffer with record. Nothing is bound in
+    //it to begin with, and the viewport and scissor cover the whole target
+    pub fn record<F>(&mut self, record: F) -> Result<(), RenderContextError>
+    where
+        F: FnOnce(&FrameRecorder),
+    {
+        let command_buffer =
+            self.pool.next(self.frame_index).map_err(VkError::with_op(
+                "vkAllocateCommandBuffers",
+                RenderContextError::DrawFrameFailed,
+            ))?;
+        let color_formats = [self.inheritance.color_format];
+        let mut rendering_info =
+            vk::CommandBufferInheritanceRenderingInfo::builder()
+                .color_attachment_formats(&color_formats)
+                .depth_attachment_format(self.inheritance.depth_format)
+                .rasterization_samples(self.inheritance.samples);
+        let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(self.inheritance.render_pass)
+            .subpass(0)
+            .framebuffer(self.framebuffer);
+        //dynamic passes have no render pass object to point at
+        if self.inheritance.render_pass == vk::RenderPass::null() {
+            inheritance_info = inheritance_info.push_next(&mut rendering_info);
+        }
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info)
+            .build();
+        //SAFETY: the pool was reset once this frame's fence signaled so the
+        //buffer isn't in use, and everything begin_info points at is alive
+        unsafe {
+            self.device
+                .begin_command_buffer(command_buffer, &begin_info)
+        }
+        .map_err(VkError::with_op(
+            "vkBeginCommandBuffer",
+            RenderContextError::DrawFrameFailed,
+        ))?;
+        let recorder = FrameRecorder {
+            device: self.device,
+            command_buffer,
+            image: self.image,
+            image_view: self.image_view,
+            framebuffer: self.framebuffer,
+            bound_layout: Cell::new(vk::PipelineLayout::null()),
+            extent: self.extent,
+            frame_index: self.frame_index,
+            wireframe: self.wireframe,
+            multi_draw_indirect: self.multi_draw_indirect,
+            debug_utils: self.debug_utils,
+        };
+        //secondary command buffers don't inherit any dynamic state
+        recorder.reset_viewport();
+        record(&recorder);
+        //SAFETY: the command buffer was begun above
+        unsafe { self.device.end_command_buffer(command_buffer) }.map_err(
+            VkError::with_op(
+                "vkEndCommandBuffer",
+                RenderContextError::DrawFrameFailed,
+            ),
+        )?;
+        self.recorded.push(command_buffer);
+        Ok(())
+    }
+}
+
+//Handed to the compute callback of RenderContext::draw_frame_with_compute.
+//Records into the frame's command buffer before the render pass begins, so
+//whatever gets dispatched or drawn offscreen here is finished and visible
+//by the time the frame's draws read it
+pub struct ComputeRecorder<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) command_buffer: vk::CommandBuffer,
+    pub(crate) bound_layout: Cell<vk::PipelineLayout>,
+    //the barrier after compute work is only needed if there was any
+    pub(crate) dispatched: Cell<bool>,
+    pub(crate) frame_index: usize,
+    //passed on to the FrameRecorders draw_offscreen makes
+    #[cfg_attr(not(feature = "allocator"), allow(dead_code))]
+    pub(crate) wireframe: bool,
+    #[cfg_attr(not(feature = "allocator"), allow(dead_code))]
+    pub(crate) multi_draw_indirect: bool,
+    #[cfg_attr(not(feature = "allocator"), allow(dead_code))]
+    pub(crate) debug_utils: Option<&'a DebugUtils>,
+}
+
+impl<'a> ComputeRecorder<'a> {
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    //Which frame in flight this is, for indexing per frame resources
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    pub fn bind_pipeline(&self, pipeline: &Pipeline) {
+        assert_eq!(
+            pipeline.bind_point(),
+            vk::PipelineBindPoint::COMPUTE,
+            "only compute pipelines can be used for compute work"
+        );
+        //SAFETY: the command buffer is recording and the pipeline is alive
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                self.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.handle(),
+            )
+        }
+        self.bound_layout.set(pipeline.layout());
+    }
+
+    //Push value at offset 0 for the compute pipeline last bound with
+    //bind_pipeline. Same rules as FrameRecorder::push_constants
+    pub fn push_constants<T: Copy>(&self, value: &T) {
+        push_constants(
+            self.device,
+            self.command_buffer,
+            self.bound_layout.get(),
+            value,
+            vk::ShaderStageFlags::COMPUTE,
+        )
+    }
+
+    //Reset this frame's queries in pool so the frame's draws can begin
+    //them. Read last time's results first, the reset throws them away
+    pub fn reset_occlusion_queries(&self, pool: &OcclusionQueryPool) {
+        pool.reset(self.command_buffer, self.frame_index)
+    }
+
+    //Run x * y * z workgroups of the bound compute pipeline
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        assert!(
+            self.bound_layout.get() != vk::PipelineLayout::null(),
+            "bind a compute pipeline before dispatching"
+        );
+        //SAFETY: the command buffer is recording outside a render pass with
+        //a compute pipeline bound
+        unsafe { self.device.cmd_dispatch(self.command_buffer, x, y, z) }
+        self.dispatched.set(true);
+    }
+
+    //Run target's render pass with record drawing into it. The color image
+    //ends up in SHADER_READ_ONLY_OPTIMAL, ready for the fragment shaders of
+    //later passes this frame, including the context's own. Compute work
+    //dispatched before this is visible to record's draws
+    #[cfg(feature = "allocator")]
+    pub fn draw_offscreen<F>(&self, target: &OffscreenTarget, record: F)
+    where
+        F: FnOnce(&FrameRecorder),
+    {
+        if self.dispatched.replace(false) {
+            compute_to_graphics_barrier(self.device, self.command_buffer);
+        }
+        let extent = target.extent();
+        let render_pass = target.render_pass();
+        let render_pass_begin = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass.handle())
+            .framebuffer(target.framebuffer())
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            })
+            .clear_values(render_pass.clear_values())
+            .build();
+        //SAFETY: the command buffer is recording outside a render pass and
+        //the framebuffer was made for this render pass
+        unsafe {
+            self.device.cmd_begin_render_pass(
+                self.command_buffer,
+                &render_pass_begin,
+                vk::SubpassContents::INLINE,
+            )
+        };
+        let recorder = FrameRecorder {
+            device: self.device,
+            command_buffer: self.command_buffer,
+            image: target.color().image().handle(),
+            image_view: target.color().view(),
+            framebuffer: target.framebuffer(),
+            bound_layout: Cell::new(vk::PipelineLayout::null()),
+            extent,
+            frame_index: self.frame_index,
+            wireframe: self.wireframe,
+            multi_draw_indirect: self.multi_draw_indirect,
+            debug_utils: self.debug_utils,
+        };
+        recorder.reset_viewport();
+        record(&recorder);
+        //SAFETY: the render pass was begun above. Its dependencies take care
+        //of the layout and making the writes visible to later reads
+        unsafe { self.device.cmd_end_render_pass(self.command_buffer) };
+    }
+}
+
+fn push_constants<T: Copy>(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    value: &T,
+    stages: vk::ShaderStageFlags,
+) {
+    assert!(
+        layout != vk::PipelineLayout::null(),
+        "bind a pipeline before pushing constants"
+    );
+    //SAFETY: value is a live T so size_of::<T> bytes starting at it are
+    //readable
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            (value as *const T).cast::<u8>(),
+            std::mem::size_of::<T>(),
+        )
+    };
+    //SAFETY: the command buffer is recording and layout came from a
+    //pipeline that is bound to it
+    unsafe {
+        device.cmd_push_constants(command_buffer, layout, stages, 0, bytes)
+    }
+}
+
+//Labels are purely informational so rather than fail over an interior nul we
+//cut the name off there
+fn debug_label_name(name: &str) -> CString {
+    let name = name.split('\0').next().unwrap_or_default();
+    CString::new(name).expect("the name was cut off at the first nul")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_success_and_suboptimal() {
+        assert_eq!(
+            classify_present_result(vk::Result::SUCCESS),
+            PresentOutcome::Ok
+        );
+        assert_eq!(
+            classify_present_result(vk::Result::SUBOPTIMAL_KHR),
+            PresentOutcome::Suboptimal
+        );
+    }
+
+    #[test]
+    fn classify_out_of_date_needs_recreate() {
+        assert_eq!(
+            classify_present_result(vk::Result::ERROR_OUT_OF_DATE_KHR),
+            PresentOutcome::NeedsRecreate
+        );
+    }
+
+    //a lost surface can't be fixed by recreating the swapchain on it
+    #[test]
+    fn classify_surface_lost_fails() {
+        assert_eq!(
+            classify_present_result(vk::Result::ERROR_SURFACE_LOST_KHR),
+            PresentOutcome::Failed(vk::Result::ERROR_SURFACE_LOST_KHR)
+        );
+    }
+
+    #[test]
+    fn classify_device_error_fails() {
+        assert_eq!(
+            classify_present_result(vk::Result::ERROR_DEVICE_LOST),
+            PresentOutcome::Failed(vk::Result::ERROR_DEVICE_LOST)
+        );
+    }
+
+    #[test]
+    fn present_result_code_unfolds_suboptimal() {
+        let suboptimal = |&suboptimal: &bool| suboptimal;
+        assert_eq!(
+            present_result_code(&Ok(false), suboptimal),
+            vk::Result::SUCCESS
+        );
+        assert_eq!(
+            present_result_code(&Ok(true), suboptimal),
+            vk::Result::SUBOPTIMAL_KHR
+        );
+        assert_eq!(
+            present_result_code(
+                &Err(vk::Result::ERROR_OUT_OF_DATE_KHR),
+                suboptimal
+            ),
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+        );
+    }
+}
@@ -0,0 +1,82 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::render_pass::RenderPass;
+
+//One framebuffer per image we render to, usually the swapchain's. These
+//point at those image views, plus the depth and msaa views if the render
+//pass has them, so they have to be thrown away and rebuilt whenever those
+//are recreated
+pub struct Framebuffers {
+    framebuffers: Vec<vk::Framebuffer>,
+    device: Device,
+}
+
+impl Framebuffers {
+    pub fn new(
+        device: &Device,
+        render_pass: &RenderPass,
+        image_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+        depth_view: Option<vk::ImageView>,
+        msaa_view: Option<vk::ImageView>,
+    ) -> Result<Framebuffers, vk::Result> {
+        //anything we've made gets cleaned up by Drop if we bail
+        let mut framebuffers = Framebuffers {
+            framebuffers: Vec::with_capacity(image_views.len()),
+            device: device.clone(),
+        };
+        for &image_view in image_views {
+            //with msaa the final image is the resolve target which the
+            //render pass puts last
+            let attachments: Vec<_> = match msaa_view {
+                Some(msaa_view) => {
+                    [Some(msaa_view), depth_view, Some(image_view)]
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                }
+                None => [Some(image_view), depth_view]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            };
+            let create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass.handle())
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1)
+                .build();
+            //SAFETY: the render pass and image view are alive and
+            //create_info was made with a builder
+            let framebuffer =
+                unsafe { device.create_framebuffer(&create_info, None) }?;
+            framebuffers.framebuffers.push(framebuffer);
+        }
+        Ok(framebuffers)
+    }
+
+    //The framebuffer for the image at image_index, e.g. the swapchain image
+    //acquire_next_image gave us
+    pub fn framebuffer(&self, image_index: u32) -> vk::Framebuffer {
+        self.framebuffers[image_index as usize]
+    }
+}
+
+impl Drop for Framebuffers {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with these before
+        //dropping them
+        unsafe {
+            for &framebuffer in &self.framebuffers {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use notify::{event::EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    pipeline::Pipeline,
+    render_context::{RenderContext, RenderContextError},
+    shader::ShaderModule,
+};
+
+//Builds a pipeline out of a vertex and fragment shader. Called once up
+//front and again every time either shader changes on disk
+pub type PipelineRecipe = Box<
+    dyn Fn(
+            &RenderContext,
+            &ShaderModule,
+            &ShaderModule,
+        ) -> Result<Pipeline, RenderContextError>
+        + Send,
+>;
+
+//A pipeline that gets rebuilt by RenderContext::reload_shaders when its
+//shaders change. Clones share the same pipeline
+#[derive(Clone)]
+pub struct WatchedPipeline {
+    pipeline: Arc<Mutex<Pipeline>>,
+}
+
+impl WatchedPipeline {
+    //Don't hold on to the guard across reload_shaders, a pipeline that's
+    //locked at the time just gets rebuilt on the next call instead
+    pub fn lock(&self) -> MutexGuard<'_, Pipeline> {
+        //a panic while holding the lock can't leave a Pipeline half built
+        self.pipeline
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+struct Entry {
+    vertex_path: PathBuf,
+    fragment_path: PathBuf,
+    recipe: PipelineRecipe,
+    pipeline: Arc<Mutex<Pipeline>>,
+    stale: bool,
+}
+
+//Watches a directory for .spv files being written and keeps track of which
+//pipelines were built from them
+pub(crate) struct ShaderWatcher {
+    //stops watching when dropped
+    _watcher: RecommendedWatcher,
+    changes: Receiver<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl ShaderWatcher {
+    pub fn new(directory: &Path) -> Result<ShaderWatcher, notify::Error> {
+        let (sender, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(
+            move |event: notify::Result<notify::Event>| match event {
+                Ok(event)
+                    if matches!(
+                        event.kind,
+                        EventKind::Create(_) | EventKind::Modify(_)
+                    ) =>
+                {
+                    for path in event.paths {
+                        if path.extension().is_some_and(|ext| ext == "spv") {
+                            //the receiver only goes away along with us
+                            let _ = sender.send(path);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Shader watcher error: {}", e),
+            },
+        )?;
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {} for shader changes", directory.display());
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            changes,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        vertex_path: &Path,
+        fragment_path: &Path,
+        recipe: PipelineRecipe,
+        pipeline: Pipeline,
+    ) -> WatchedPipeline {
+        let pipeline = Arc::new(Mutex::new(pipeline));
+        self.entries.push(Entry {
+            vertex_path: canonical(vertex_path),
+            fragment_path: canonical(fragment_path),
+            recipe,
+            pipeline: pipeline.clone(),
+            stale: false,
+        });
+        WatchedPipeline { pipeline }
+    }
+
+    //Mark every pipeline using a shader that changed since the last call.
+    //Returns whether any pipeline needs rebuilding
+    pub fn poll(&mut self) -> bool {
+        for changed in self.changes.try_iter() {
+            let changed = canonical(&changed);
+            for entry in &mut self.entries {
+                if entry.vertex_path == changed
+                    || entry.fragment_path == changed
+                {
+                    log::debug!("{} changed", changed.display());
+                    entry.stale = true;
+                }
+            }
+        }
+        self.entries.iter().any(|entry| entry.stale)
+    }
+
+    //Rebuild every stale pipeline. The GPU must not be using any of them.
+    //A shader that fails to load or a recipe that fails keeps the old
+    //pipeline so a typo doesn't take the app down. Returns how many were
+    //rebuilt
+    pub fn rebuild(&mut self, render_context: &RenderContext) -> usize {
+        let mut rebuilt = 0;
+        for entry in self.entries.iter_mut().filter(|entry| entry.stale) {
+            let Ok(mut pipeline) = entry.pipeline.try_lock() else {
+                //still in use, try again next time
+                continue;
+            };
+            entry.stale = false;
+            let result = render_context
+                .load_shader_file(&entry.vertex_path)
+                .and_then(|vertex_shader| {
+                    let fragment_shader = render_context
+                        .load_shader_file(&entry.fragment_path)?;
+                    (entry.recipe)(
+                        render_context,
+                        &vertex_shader,
+                        &fragment_shader,
+                    )
+                });
+            match result {
+                Ok(new_pipeline) => {
+                    *pipeline = new_pipeline;
+                    rebuilt += 1;
+                    log::info!(
+                        "Reloaded pipeline for {} and {}",
+                        entry.vertex_path.display(),
+                        entry.fragment_path.display()
+                    );
+                }
+                Err(e) => log::error!(
+                    "Failed to reload pipeline for {} and {}, keeping the \
+                    old one: {:?}",
+                    entry.vertex_path.display(),
+                    entry.fragment_path.display(),
+                    e
+                ),
+            }
+        }
+        rebuilt
+    }
+}
+
+//Editors love saving through temporary files and symlinks so compare
+//canonical paths. Falls back to the path as given if it can't be resolved
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,180 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::collections::HashSet;
+
+use winit::event::{
+    ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
+
+//How many pixels of touchpad scrolling count as one line of mouse wheel
+const PIXELS_PER_LINE: f64 = 20.0;
+
+//Keyboard and mouse state built up from window events. Deltas add up
+//everything since the last end_frame, which RenderContext::run calls after
+//each frame
+#[derive(Debug, Default, Clone)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    //None until the cursor has been over the window
+    mouse_position: Option<(f64, f64)>,
+    mouse_delta: (f64, f64),
+    scroll_delta: (f32, f32),
+    //everything below is since the last end_frame, in the order it
+    //happened
+    pressed_keys: Vec<VirtualKeyCode>,
+    released_keys: Vec<VirtualKeyCode>,
+    pressed_buttons: Vec<MouseButton>,
+    released_buttons: Vec<MouseButton>,
+    text: String,
+    modifiers: ModifiersState,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Fold an event for the window into the state. Anything that isn't
+    //input is ignored
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    self.keys_down.insert(key);
+                    self.pressed_keys.push(key);
+                }
+                ElementState::Released => {
+                    self.keys_down.remove(&key);
+                    self.released_keys.push(key);
+                }
+            },
+            //control characters come through as key presses already
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                self.text.push(c);
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some((x, y)) = self.mouse_position {
+                    self.mouse_delta.0 += position.x - x;
+                    self.mouse_delta.1 += position.y - y;
+                }
+                self.mouse_position = Some((position.x, position.y));
+            }
+            WindowEvent::CursorLeft { .. } => self.mouse_position = None,
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(button);
+                    self.pressed_buttons.push(button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(&button);
+                    self.released_buttons.push(button);
+                }
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (x, y),
+                    MouseScrollDelta::PixelDelta(position) => (
+                        (position.x / PIXELS_PER_LINE) as f32,
+                        (position.y / PIXELS_PER_LINE) as f32,
+                    ),
+                };
+                self.scroll_delta.0 += x;
+                self.scroll_delta.1 += y;
+            }
+            //releases that happen while we're unfocused never reach us so
+            //don't leave anything stuck down
+            WindowEvent::Focused(false) => {
+                self.keys_down.clear();
+                self.buttons_down.clear();
+                self.modifiers = ModifiersState::empty();
+            }
+            _ => {}
+        }
+    }
+
+    //Reset the deltas for the next frame
+    pub fn end_frame(&mut self) {
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = (0.0, 0.0);
+        self.pressed_keys.clear();
+        self.released_keys.clear();
+        self.pressed_buttons.clear();
+        self.released_buttons.clear();
+        self.text.clear();
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    //Cursor position in physical pixels from the window's top left. None
+    //while the cursor is outside the window
+    pub fn mouse_position(&self) -> Option<(f64, f64)> {
+        self.mouse_position
+    }
+
+    //How far the cursor moved in physical pixels since the last frame
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.mouse_delta
+    }
+
+    //Horizontal and vertical scrolling in lines since the last frame.
+    //Positive y is scrolling up
+    pub fn scroll_delta(&self) -> (f32, f32) {
+        self.scroll_delta
+    }
+
+    //Whether key went down since the last frame, including key repeats
+    //while it's held
+    pub fn was_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    //Every key press since the last frame in order, key repeats included
+    pub fn pressed_keys(&self) -> &[VirtualKeyCode] {
+        &self.pressed_keys
+    }
+
+    pub fn released_keys(&self) -> &[VirtualKeyCode] {
+        &self.released_keys
+    }
+
+    pub fn pressed_buttons(&self) -> &[MouseButton] {
+        &self.pressed_buttons
+    }
+
+    pub fn released_buttons(&self) -> &[MouseButton] {
+        &self.released_buttons
+    }
+
+    //Characters typed since the last frame, for text entry
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    //Which modifier keys are held
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+}
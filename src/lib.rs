@@ -0,0 +1,48 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+#![deny(unsafe_op_in_unsafe_fn)]
+
+pub mod app;
+#[cfg(feature = "allocator")]
+pub mod attachment;
+pub mod barrier;
+pub mod camera;
+#[cfg(feature = "textures")]
+pub mod capture;
+pub mod command;
+#[cfg(feature = "allocator")]
+pub mod debug_draw;
+pub mod descriptor;
+pub mod frame;
+pub mod framebuffer;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod input;
+#[cfg(feature = "allocator")]
+pub mod memory;
+#[cfg(feature = "gltf")]
+pub mod model;
+#[cfg(feature = "allocator")]
+pub mod offscreen;
+pub mod physical_device;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod query;
+pub mod render_context;
+pub mod render_pass;
+pub mod sampler;
+pub mod shader;
+pub mod submit;
+pub mod surface;
+pub mod swapchain;
+#[cfg(feature = "textures")]
+pub mod texture;
+pub mod timeline;
+#[cfg(feature = "egui")]
+pub mod ui;
+#[cfg(feature = "allocator")]
+pub mod upload;
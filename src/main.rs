@@ -9,47 +9,136 @@ file, You can obtain one at https://mozilla.org/MPL/2.0/.
 use std::sync::Arc;
 
 use log::debug;
-use render_context::RenderContext;
+use sufat::{
+    app::LoopMode,
+    render_context::{RenderContextBuilder, RenderContextError},
+};
+#[cfg(feature = "textures")]
+use winit::event::VirtualKeyCode;
 use winit::{
     dpi::{LogicalSize, Size},
-    event::{Event, StartCause, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
 };
-mod render_context;
+
+const TITLE: &str = "sufat";
+#[cfg(feature = "textures")]
+const SCREENSHOT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+
+struct Config {
+    loop_mode: LoopMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            loop_mode: LoopMode::Poll,
+        }
+    }
+}
 
 fn main() {
     env_logger::init();
+    let config = Config::default();
     let event_loop = EventLoop::new();
     let window = Arc::new(
         WindowBuilder::new()
+            .with_title(TITLE)
+            //run shows it once the first frame is up so it never shows up
+            //blank
             .with_visible(false)
             .with_inner_size(Size::Logical(LogicalSize::new(1280f64, 720f64)))
             .build(&event_loop)
             .unwrap(),
     );
 
-    let render_context = RenderContext::new(window.clone()).unwrap();
-
-    event_loop.run(move |event, _target, control_flow| match event {
-        Event::NewEvents(StartCause::Init) => {
-            control_flow.set_poll();
-            window.set_visible(true)
-        }
-        Event::WindowEvent { window_id, event } => {
-            if event == WindowEvent::CloseRequested && window_id == window.id()
+    let render_context = match RenderContextBuilder::new()
+        .loop_mode(config.loop_mode)
+        .build(window)
+    {
+        Ok(render_context) => render_context,
+        Err(e) => {
+            if let RenderContextError::MissingExtension(extensions)
+            | RenderContextError::MissingExtensionAndLayer {
+                extensions,
+                ..
+            } = &e
             {
-                debug!("recieved shutdown request");
-                control_flow.set_exit();
-                window.set_visible(false);
+                log::error!(
+                    "Your vulkan driver is missing the extensions {:?}",
+                    extensions
+                )
             }
+            if let RenderContextError::MissingLayer(layers)
+            | RenderContextError::MissingExtensionAndLayer {
+                layers, ..
+            } = &e
+            {
+                log::error!(
+                    "Missing the layers {:?}. If you want validation make \
+                    sure the vulkan SDK is installed",
+                    layers
+                )
+            }
+            log::error!("Unable to create render context: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    debug!("{}", render_context.diagnostic_report());
+    let mut shown_fps = 0.0;
+
+    render_context.run(event_loop, move |render_context, mut frame| {
+        #[cfg(feature = "textures")]
+        let screenshot = frame.input.was_key_pressed(SCREENSHOT_KEY)
+            && match render_context.request_capture() {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("Can't take a screenshot: {}", e);
+                    false
+                }
+            };
+        //the render pass clears the image and gets it ready to present so
+        //there's nothing to record yet
+        if let Err(e) = render_context.draw_frame(|_, _| {}) {
+            log::error!("Failed to draw frame: {:?}", e);
+            frame.exit();
         }
-        Event::LoopDestroyed => {
-            //temporary capture so we can make sure that render_context is moved
-            //in
-            Some(&render_context);
-            debug!("Loop exiting");
+        #[cfg(feature = "textures")]
+        if screenshot {
+            save_screenshot(render_context);
+        }
+        //fps only changes every half second so don't spam set_title
+        let stats = render_context.frame_stats();
+        if stats.fps != shown_fps {
+            shown_fps = stats.fps;
+            frame.window.set_title(&format!(
+                "{} - {:.0} fps ({:.2} ms)",
+                TITLE, stats.fps, stats.frame_time_ms
+            ));
         }
-        _ => {}
     })
 }
+
+//Write the frame that was just drawn to a PNG in the working directory,
+//named after when it was taken so screenshots don't overwrite each other.
+//capture_frame waits for the GPU to finish the copy before handing the
+//pixels over
+#[cfg(feature = "textures")]
+fn save_screenshot(render_context: &mut sufat::render_context::RenderContext) {
+    let image = match render_context.capture_frame() {
+        Ok(image) => image,
+        Err(e) => {
+            log::error!("Failed to capture screenshot: {}", e);
+            return;
+        }
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = format!("screenshot-{}.png", timestamp);
+    match image.save(&path) {
+        Ok(()) => log::info!("Saved screenshot to {}", path),
+        Err(e) => log::error!("Failed to save screenshot {}: {}", path, e),
+    }
+}
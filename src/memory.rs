@@ -0,0 +1,531 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use ash::{vk, Device};
+use gpu_allocator::vulkan::{
+    Allocation, AllocationCreateDesc, AllocationScheme, Allocator,
+};
+pub use gpu_allocator::MemoryLocation;
+
+use crate::render_context::RenderContextError;
+
+//Shared between the context and every resource made from it so resources
+//can hand their memory back when they're dropped
+pub(crate) type SharedAllocator = Arc<Mutex<Allocator>>;
+
+fn free_allocation(allocator: &SharedAllocator, allocation: Allocation) {
+    //a poisoned lock only means some other thread panicked mid allocation.
+    //The allocator itself is still fine to use
+    let mut allocator = allocator
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Err(e) = allocator.free(allocation) {
+        log::error!("Failed to free allocation: {}", e);
+    }
+}
+
+fn allocate(
+    allocator: &SharedAllocator,
+    name: &str,
+    requirements: vk::MemoryRequirements,
+    location: MemoryLocation,
+    linear: bool,
+) -> Result<Allocation, RenderContextError> {
+    allocator
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .allocate(&AllocationCreateDesc {
+            name,
+            requirements,
+            location,
+            linear,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })
+        .map_err(RenderContextError::AllocationFailed)
+}
+
+//A buffer along with the memory backing it
+pub struct Buffer {
+    buffer: vk::Buffer,
+    size: vk::DeviceSize,
+    //only None while we're being dropped
+    allocation: Option<Allocation>,
+    allocator: SharedAllocator,
+    device: Device,
+}
+
+impl Buffer {
+    pub(crate) fn new(
+        device: &Device,
+        allocator: &SharedAllocator,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<Buffer, RenderContextError> {
+        //vulkan doesn't allow empty buffers
+        if size == 0 {
+            return Err(RenderContextError::EmptyBuffer);
+        }
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+        //SAFETY: create_info was made with a builder
+        let buffer = unsafe { device.create_buffer(&create_info, None) }
+            .map_err(RenderContextError::BufferCreationFailed)?;
+        //SAFETY: buffer was just made from this device
+        let requirements =
+            unsafe { device.get_buffer_memory_requirements(buffer) };
+
+        let allocation = match allocate(
+            allocator,
+            "sufat buffer",
+            requirements,
+            location,
+            true,
+        ) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                //SAFETY: nothing has used the buffer yet
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(e);
+            }
+        };
+        //SAFETY: the allocation was made to fit this buffer's requirements
+        if let Err(e) = unsafe {
+            device.bind_buffer_memory(
+                buffer,
+                allocation.memory(),
+                allocation.offset(),
+            )
+        } {
+            free_allocation(allocator, allocation);
+            //SAFETY: nothing has used the buffer yet
+            unsafe { device.destroy_buffer(buffer, None) };
+            return Err(RenderContextError::BufferCreationFailed(e));
+        }
+
+        Ok(Buffer {
+            buffer,
+            size,
+            allocation: Some(allocation),
+            allocator: allocator.clone(),
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation
+            .as_ref()
+            .expect("allocation is only taken when dropping")
+    }
+
+    //Read only view of the buffer's memory if the CPU can see it, e.g.
+    //MemoryLocation::GpuToCpu for readbacks
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.allocation
+            .as_ref()
+            .expect("allocation is only taken when dropping")
+            .mapped_slice()
+    }
+
+    //The buffer's memory if it was allocated somewhere the CPU can see, e.g.
+    //MemoryLocation::CpuToGpu
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        self.allocation
+            .as_mut()
+            .expect("allocation is only taken when dropping")
+            .mapped_slice_mut()
+    }
+
+    //Copy data to the start of the buffer. Panics if the buffer isn't
+    //mapped or data doesn't fit
+    pub fn write<T: Copy>(&mut self, data: &[T]) {
+        self.write_at(0, data);
+    }
+
+    //Copy data to offset bytes into the buffer. Panics if the buffer isn't
+    //mapped or data doesn't fit after offset
+    pub fn write_at<T: Copy>(&mut self, offset: vk::DeviceSize, data: &[T]) {
+        let len = std::mem::size_of_val(data);
+        let mapped = self
+            .mapped_slice_mut()
+            .expect("only host visible buffers can be written to");
+        let offset = offset as usize;
+        assert!(
+            offset <= mapped.len() && len <= mapped.len() - offset,
+            "{} bytes at offset {} don't fit in a {} byte buffer",
+            len,
+            offset,
+            mapped.len()
+        );
+        let mapped = &mut mapped[offset..];
+        //SAFETY: the destination has room for len bytes as checked above and
+        //can't overlap data since we have it mutably borrowed. Copying bytes
+        //rather than T means the mapping doesn't have to be aligned for T
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                mapped.as_mut_ptr(),
+                len,
+            )
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the buffer
+        //before dropping it
+        unsafe { self.device.destroy_buffer(self.buffer, None) };
+        if let Some(allocation) = self.allocation.take() {
+            free_allocation(&self.allocator, allocation);
+        }
+    }
+}
+
+//A device local buffer of vertices along with how many there are
+pub struct VertexBuffer {
+    pub(crate) buffer: Buffer,
+    pub(crate) count: u32,
+}
+
+impl VertexBuffer {
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+//A device local buffer of indices along with how many there are and how
+//wide each one is
+pub struct IndexBuffer {
+    pub(crate) buffer: Buffer,
+    pub(crate) count: u32,
+    pub(crate) index_type: vk::IndexType,
+}
+
+impl IndexBuffer {
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+//A T the shaders can read, with a separate copy for every frame in flight
+//so the CPU never writes the copy a frame the GPU is still working on is
+//reading. Every copy stays mapped for the life of the buffer
+pub struct UniformBuffer<T: Copy> {
+    pub(crate) buffers: Vec<Buffer>,
+    pub(crate) _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffer<T> {
+    //Overwrite frame_index's copy. Use the index from the FrameRecorder so
+    //the copy being written is one the GPU is done with
+    pub fn update(&mut self, frame_index: usize, value: &T) {
+        self.buffers[frame_index].write(std::slice::from_ref(value));
+    }
+
+    pub fn handle(&self, frame_index: usize) -> vk::Buffer {
+        self.buffers[frame_index].handle()
+    }
+
+    //What a descriptor set needs to point at frame_index's copy
+    pub fn descriptor_info(
+        &self,
+        frame_index: usize,
+    ) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffers[frame_index].handle(),
+            offset: 0,
+            range: std::mem::size_of::<T>() as vk::DeviceSize,
+        }
+    }
+
+    //How many copies there are. Same as the context's frames in flight
+    pub fn copies(&self) -> usize {
+        self.buffers.len()
+    }
+}
+
+//Per instance data for instanced draws. Like UniformBuffer there's a copy
+//for every frame in flight, so the instances can change every frame, e.g.
+//for moving particles. Each copy holds up to capacity instances and stays
+//mapped for the life of the buffer
+pub struct InstanceBuffer<T: Copy> {
+    pub(crate) buffers: Vec<Buffer>,
+    //how many instances were last written to each copy
+    pub(crate) counts: Vec<u32>,
+    pub(crate) capacity: u32,
+    pub(crate) _marker: PhantomData<T>,
+}
+
+impl<T: Copy> InstanceBuffer<T> {
+    //Replace frame_index's instances. Use the index from the FrameRecorder
+    //so the copy being written is one the GPU is done with. Panics if
+    //there are more than capacity instances
+    pub fn update(&mut self, frame_index: usize, instances: &[T]) {
+        assert!(
+            instances.len() <= self.capacity as usize,
+            "{} instances don't fit in an instance buffer for {}",
+            instances.len(),
+            self.capacity
+        );
+        self.buffers[frame_index].write(instances);
+        self.counts[frame_index] = instances.len() as u32;
+    }
+
+    pub fn handle(&self, frame_index: usize) -> vk::Buffer {
+        self.buffers[frame_index].handle()
+    }
+
+    //How many instances the last update for frame_index wrote
+    pub fn count(&self, frame_index: usize) -> u32 {
+        self.counts[frame_index]
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+}
+
+//Device local VkDrawIndexedIndirectCommands for
+//FrameRecorder::draw_indexed_indirect. Also a storage buffer so compute
+//shaders can fill it in, e.g. after culling, as long as the dispatch
+//happens in draw_frame_with_compute's compute callback
+pub struct DrawIndirectBuffer {
+    pub(crate) buffer: Buffer,
+    pub(crate) count: u32,
+}
+
+impl DrawIndirectBuffer {
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer.handle()
+    }
+
+    //How many commands fit
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    //For binding as a storage buffer
+    pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer.handle(),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }
+    }
+}
+
+//Scratch memory for data that's rebuilt every frame, e.g. UI vertices or
+//debug lines, so it doesn't need a buffer per draw. Each frame in flight
+//gets its own capacity sized host visible buffer that allocations are
+//bumped out of. Call reset with the FrameRecorder's frame_index before
+//allocating each frame, which throws away everything that copy held the
+//last time around. Every allocation is aligned for both uniform and
+//storage buffer descriptors, and the buffers can also be bound as vertex
+//or index buffers
+pub struct FrameArena {
+    pub(crate) buffers: Vec<Buffer>,
+    pub(crate) capacity: vk::DeviceSize,
+    //the larger of the device's uniform and storage buffer offset
+    //alignments
+    pub(crate) alignment: vk::DeviceSize,
+    pub(crate) frame_index: usize,
+    //where the next allocation in frame_index's copy can start
+    pub(crate) head: vk::DeviceSize,
+}
+
+impl FrameArena {
+    //Start handing out frame_index's copy from the beginning again. Use the
+    //index from the FrameRecorder so the copy being reused is one the GPU
+    //is done with
+    pub fn reset(&mut self, frame_index: usize) {
+        assert!(
+            frame_index < self.buffers.len(),
+            "frame index {} out of range for {} frames in flight",
+            frame_index,
+            self.buffers.len()
+        );
+        self.frame_index = frame_index;
+        self.head = 0;
+    }
+
+    //Copy value into this frame's copy, returning the buffer and offset it
+    //landed at. None if there isn't room left this frame
+    pub fn alloc<T: Copy>(
+        &mut self,
+        value: &T,
+    ) -> Option<(vk::Buffer, vk::DeviceSize)> {
+        self.alloc_slice(std::slice::from_ref(value))
+    }
+
+    //Copy values into this frame's copy back to back, returning the buffer
+    //and offset of the first one. None if there isn't room left this frame
+    pub fn alloc_slice<T: Copy>(
+        &mut self,
+        values: &[T],
+    ) -> Option<(vk::Buffer, vk::DeviceSize)> {
+        let alignment = self
+            .alignment
+            .max(std::mem::align_of::<T>() as vk::DeviceSize);
+        //both alignments are powers of two so the larger one satisfies both
+        let offset = self.head.checked_add(alignment - 1)? & !(alignment - 1);
+        let len = std::mem::size_of_val(values) as vk::DeviceSize;
+        let end = offset.checked_add(len)?;
+        if end > self.capacity {
+            return None;
+        }
+        let buffer = &mut self.buffers[self.frame_index];
+        buffer.write_at(offset, values);
+        self.head = end;
+        Some((buffer.handle(), offset))
+    }
+
+    //How many bytes of this frame's copy have been handed out, including
+    //alignment padding
+    pub fn used(&self) -> vk::DeviceSize {
+        self.head
+    }
+
+    //How many bytes each frame in flight's copy holds
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.capacity
+    }
+
+    //What every allocation's offset is a multiple of, at least
+    pub fn alignment(&self) -> vk::DeviceSize {
+        self.alignment
+    }
+}
+
+//An image along with the memory backing it
+pub struct Image {
+    image: vk::Image,
+    format: vk::Format,
+    extent: vk::Extent3D,
+    //only None while we're being dropped
+    allocation: Option<Allocation>,
+    allocator: SharedAllocator,
+    device: Device,
+}
+
+impl Image {
+    pub(crate) fn new(
+        device: &Device,
+        allocator: &SharedAllocator,
+        create_info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<Image, RenderContextError> {
+        //SAFETY: the caller hands us a valid create_info
+        let image = unsafe { device.create_image(create_info, None) }
+            .map_err(RenderContextError::ImageCreationFailed)?;
+        //SAFETY: image was just made from this device
+        let requirements =
+            unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = allocate(
+            allocator,
+            "sufat image",
+            requirements,
+            location,
+            create_info.tiling == vk::ImageTiling::LINEAR,
+        )
+        .inspect_err(|_| {
+            //SAFETY: nothing has used the image yet
+            unsafe { device.destroy_image(image, None) }
+        })?;
+        //SAFETY: the allocation was made to fit this image's requirements
+        if let Err(e) = unsafe {
+            device.bind_image_memory(
+                image,
+                allocation.memory(),
+                allocation.offset(),
+            )
+        } {
+            free_allocation(allocator, allocation);
+            //SAFETY: nothing has used the image yet
+            unsafe { device.destroy_image(image, None) };
+            return Err(RenderContextError::ImageCreationFailed(e));
+        }
+
+        Ok(Image {
+            image,
+            format: create_info.format,
+            extent: create_info.extent,
+            allocation: Some(allocation),
+            allocator: allocator.clone(),
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation
+            .as_ref()
+            .expect("allocation is only taken when dropping")
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the image
+        //before dropping it
+        unsafe { self.device.destroy_image(self.image, None) };
+        if let Some(allocation) = self.allocation.take() {
+            free_allocation(&self.allocator, allocation);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{mem::size_of, path::Path};
+
+use ash::vk;
+use gltf::{buffer, mesh::Mode, Gltf, Node};
+
+use crate::{
+    camera::{mul, Mat4, IDENTITY},
+    frame::FrameRecorder,
+    memory::{IndexBuffer, VertexBuffer},
+    render_context::{RenderContext, RenderContextError},
+};
+
+//The one vertex layout models are flattened into
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl ModelVertex {
+    //Vertex input for a pipeline drawing models with ModelVertex bound at
+    //binding and the attributes at locations 0 to 2
+    pub fn binding_description(
+        binding: u32,
+    ) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding,
+            stride: size_of::<ModelVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub fn attribute_descriptions(
+        binding: u32,
+    ) -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 3 * size_of::<f32>() as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 6 * size_of::<f32>() as u32,
+            },
+        ]
+    }
+}
+
+//One draw's worth of a model's index buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Primitive {
+    pub first_index: u32,
+    pub index_count: u32,
+    //index into Model::materials, None for glTF's default material
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub base_color_factor: [f32; 4],
+    //index into the glTF file's textures
+    pub base_color_texture: Option<usize>,
+}
+
+//Every triangle mesh in a glTF file's scene, moved into world space and
+//packed into one vertex and one index buffer
+pub struct Model {
+    vertices: VertexBuffer,
+    indices: IndexBuffer,
+    primitives: Vec<Primitive>,
+    materials: Vec<Material>,
+}
+
+//What loading builds up before anything goes to the GPU
+#[derive(Default)]
+struct ModelData {
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    primitives: Vec<Primitive>,
+}
+
+impl Model {
+    pub(crate) fn load(
+        render_context: &RenderContext,
+        path: &Path,
+    ) -> Result<Model, RenderContextError> {
+        let model_load_failed = |e| {
+            log::error!("Failed to load model {}: {}", path.display(), e);
+            RenderContextError::ModelLoadFailed(e)
+        };
+        let Gltf { document, blob } =
+            Gltf::open(path).map_err(model_load_failed)?;
+        let buffers = gltf::import_buffers(&document, path.parent(), blob)
+            .map_err(model_load_failed)?;
+
+        let mut data = ModelData::default();
+        //files without a default scene usually only have the one
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next());
+        for node in scene.iter().flat_map(|scene| scene.nodes()) {
+            add_node(&mut data, &buffers, &node, &IDENTITY);
+        }
+        if data.indices.is_empty() {
+            log::error!("{} has no triangles to draw", path.display());
+            return Err(RenderContextError::EmptyModel);
+        }
+
+        let materials = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                Material {
+                    base_color_factor: pbr.base_color_factor(),
+                    base_color_texture: pbr
+                        .base_color_texture()
+                        .map(|info| info.texture().index()),
+                }
+            })
+            .collect();
+        let vertices = render_context.create_vertex_buffer(&data.vertices)?;
+        let indices = render_context.create_index_buffer(&data.indices)?;
+        log::debug!(
+            "Loaded model {} ({} vertices, {} primitives)",
+            path.display(),
+            data.vertices.len(),
+            data.primitives.len()
+        );
+        Ok(Model {
+            vertices,
+            indices,
+            primitives: data.primitives,
+            materials,
+        })
+    }
+
+    pub fn vertices(&self) -> &VertexBuffer {
+        &self.vertices
+    }
+
+    //32 bit indices, already offset to point at the right vertices for
+    //every primitive
+    pub fn indices(&self) -> &IndexBuffer {
+        &self.indices
+    }
+
+    pub fn primitives(&self) -> &[Primitive] {
+        &self.primitives
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    //Bind the model's buffers and draw every primitive. A pipeline taking
+    //ModelVertex at binding 0 has to be bound already. Anything per
+    //material has to be done with draw_primitive instead
+    pub fn draw(&self, recorder: &FrameRecorder) {
+        self.bind(recorder);
+        for primitive in &self.primitives {
+            self.draw_primitive(recorder, primitive);
+        }
+    }
+
+    //Bind the vertex buffer at binding 0 and the index buffer, ready for
+    //draw_primitive
+    pub fn bind(&self, recorder: &FrameRecorder) {
+        recorder.bind_vertex_buffer(0, &self.vertices);
+        recorder.bind_index_buffer(&self.indices);
+    }
+
+    //Draw one of the model's primitives. bind has to have been called
+    pub fn draw_primitive(
+        &self,
+        recorder: &FrameRecorder,
+        primitive: &Primitive,
+    ) {
+        recorder.draw_indexed_instanced(
+            primitive.index_count,
+            1,
+            primitive.first_index,
+            0,
+            0,
+        );
+    }
+}
+
+//Add node's mesh and all of its children's, transformed by everything
+//above them
+fn add_node(
+    data: &mut ModelData,
+    buffers: &[buffer::Data],
+    node: &Node,
+    parent_transform: &Mat4,
+) {
+    let transform = mul(parent_transform, &node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                log::warn!(
+                    "Skipping {:?} primitive in mesh {}, only triangle lists \
+                     are supported",
+                    primitive.mode(),
+                    mesh.index()
+                );
+                continue;
+            }
+            let reader = primitive
+                .reader(|buffer| buffers.get(buffer.index()).map(|d| &d.0[..]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions
+                .map(|position| transform_point(&transform, position))
+                .collect();
+            let normals: Option<Vec<[f32; 3]>> =
+                reader.read_normals().map(|normals| {
+                    normals
+                        .map(|normal| transform_normal(&transform, normal))
+                        .collect()
+                });
+            let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+                Some(uvs) => uvs.into_f32().collect(),
+                None => vec![[0.0; 2]; positions.len()],
+            };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let first_index = data.indices.len() as u32;
+            match normals {
+                Some(normals) => {
+                    let base = data.vertices.len() as u32;
+                    data.vertices.extend(
+                        positions.iter().zip(&normals).zip(&uvs).map(
+                            |((&position, &normal), &uv)| ModelVertex {
+                                position,
+                                normal,
+                                uv,
+                            },
+                        ),
+                    );
+                    data.indices.extend(indices.iter().map(|i| base + i));
+                }
+                //flat normals mean every triangle needs its own vertices
+                None => {
+                    for triangle in indices.chunks_exact(3) {
+                        let corners = [
+                            positions[triangle[0] as usize],
+                            positions[triangle[1] as usize],
+                            positions[triangle[2] as usize],
+                        ];
+                        let normal = face_normal(corners);
+                        for (&i, position) in triangle.iter().zip(corners) {
+                            data.indices.push(data.vertices.len() as u32);
+                            data.vertices.push(ModelVertex {
+                                position,
+                                normal,
+                                uv: uvs[i as usize],
+                            });
+                        }
+                    }
+                }
+            }
+            data.primitives.push(Primitive {
+                first_index,
+                index_count: data.indices.len() as u32 - first_index,
+                material: primitive.material().index(),
+            });
+        }
+    }
+    for child in node.children() {
+        add_node(data, buffers, &child, &transform);
+    }
+}
+
+fn transform_point(transform: &Mat4, point: [f32; 3]) -> [f32; 3] {
+    let mut result = transform[3];
+    for (column, &value) in transform.iter().zip(&point) {
+        for row in 0..3 {
+            result[row] += column[row] * value;
+        }
+    }
+    [result[0], result[1], result[2]]
+}
+
+//Normals only take the rotation and scale part of the transform. Right for
+//uniform scales, close enough for the rest
+fn transform_normal(transform: &Mat4, normal: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for (column, &value) in transform.iter().zip(&normal) {
+        for row in 0..3 {
+            result[row] += column[row] * value;
+        }
+    }
+    normalize(result)
+}
+
+//Counter clockwise winding faces the viewer, as glTF defines it
+fn face_normal([a, b, c]: [[f32; 3]; 3]) -> [f32; 3] {
+    let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    normalize([
+        ab[1] * ac[2] - ab[2] * ac[1],
+        ab[2] * ac[0] - ab[0] * ac[2],
+        ab[0] * ac[1] - ab[1] * ac[0],
+    ])
+}
+
+//Degenerate vectors come back as zero rather than NaN
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 {
+        return v;
+    }
+    [v[0] / length, v[1] / length, v[2] / length]
+}
@@ -0,0 +1,136 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::vk;
+
+use crate::{
+    attachment::AttachmentImage,
+    framebuffer::Framebuffers,
+    render_context::{RenderContext, RenderContextError},
+    render_pass::RenderPass,
+    sampler::{Sampler, SamplerBuilder},
+};
+
+//A color image (plus a depth image if asked for) with its own render pass
+//and framebuffer, for drawing something that a later pass samples, e.g. a
+//post processing input or a minimap. Draw to it with
+//ComputeRecorder::draw_offscreen, after which the color image is left in
+//SHADER_READ_ONLY_OPTIMAL and safe to sample from the frame's draws. Like
+//the context's attachments there's only one of these no matter how many
+//frames are in flight
+pub struct OffscreenTarget {
+    //dropped in this order, framebuffer first since it points at the rest
+    framebuffers: Framebuffers,
+    render_pass: RenderPass,
+    sampler: Sampler,
+    depth: Option<AttachmentImage>,
+    color: AttachmentImage,
+    extent: vk::Extent2D,
+}
+
+impl OffscreenTarget {
+    //depth is the format and clear value of the depth image, if there
+    //should be one. The sampler clamps to the edge with linear filtering
+    pub fn new(
+        render_context: &RenderContext,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth: Option<(vk::Format, f32)>,
+        clear_color: [f32; 4],
+    ) -> Result<OffscreenTarget, RenderContextError> {
+        let device = render_context.device();
+        let color = AttachmentImage::sampled(render_context, format, extent)?;
+        render_context
+            .set_object_name(color.image().handle(), "offscreen color");
+        let depth_image = match depth {
+            Some((depth_format, _)) => {
+                let depth = AttachmentImage::depth(
+                    render_context,
+                    depth_format,
+                    extent,
+                    vk::SampleCountFlags::TYPE_1,
+                )?;
+                render_context
+                    .set_object_name(depth.image().handle(), "offscreen depth");
+                Some(depth)
+            }
+            None => None,
+        };
+        let render_pass = RenderPass::offscreen(
+            device,
+            format,
+            depth,
+            vk::SampleCountFlags::TYPE_1,
+            clear_color,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .map_err(RenderContextError::RenderPassCreationFailed)?;
+        render_context.set_object_name(render_pass.handle(), "offscreen pass");
+        let framebuffers = Framebuffers::new(
+            device,
+            &render_pass,
+            &[color.view()],
+            extent,
+            depth_image.as_ref().map(|depth| depth.view()),
+            None,
+        )
+        .map_err(RenderContextError::FramebufferCreationFailed)?;
+        let sampler = SamplerBuilder::new()
+            .address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .anisotropy(1.0)
+            .lod_range(0.0, 1.0)
+            .build(render_context)?;
+        Ok(OffscreenTarget {
+            framebuffers,
+            render_pass,
+            sampler,
+            depth: depth_image,
+            color,
+            extent,
+        })
+    }
+
+    //For building the pipelines that draw to this target
+    pub fn render_pass(&self) -> &RenderPass {
+        &self.render_pass
+    }
+
+    pub fn framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffers.framebuffer(0)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn color(&self) -> &AttachmentImage {
+        &self.color
+    }
+
+    pub fn depth(&self) -> Option<&AttachmentImage> {
+        self.depth.as_ref()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler.handle()
+    }
+
+    //Change what the color image gets cleared to. Takes effect the next
+    //time it's drawn to
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.render_pass.set_clear_color(clear_color);
+    }
+
+    //What a combined image sampler descriptor needs to point at the color
+    //image
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.handle(),
+            image_view: self.color.view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+}
@@ -0,0 +1,672 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::ffi::CStr;
+
+use ash::{
+    extensions::khr::Surface,
+    vk::{self, PhysicalDevice, PhysicalDeviceType, SurfaceKHR},
+    Instance,
+};
+
+//What we know about a GPU before committing to it
+#[derive(Debug, Clone)]
+pub struct PhysicalDeviceInfo {
+    //position in the list vulkan handed back to us
+    pub index: usize,
+    pub handle: PhysicalDevice,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub api_version: u32,
+    //whether any queue family on this device can present to our surface.
+    //Always true for headless contexts since there's nothing to present to
+    pub supports_present: bool,
+    pub limits: vk::PhysicalDeviceLimits,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    //identifies the driver build pipeline cache data is valid for
+    pub pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+    //bytes across all the DEVICE_LOCAL heaps, roughly how much VRAM it has
+    pub device_local_memory: vk::DeviceSize,
+}
+
+//The surface we have to be able to present to, None when headless
+pub(crate) type PresentTarget<'a> = Option<(&'a Surface, SurfaceKHR)>;
+
+pub(crate) fn enumerate_physical_devices(
+    instance: &Instance,
+    present_target: PresentTarget,
+) -> Result<Vec<PhysicalDeviceInfo>, vk::Result> {
+    //SAFETY: instance is valid for as long as we hold a reference to it
+    let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+
+    Ok(physical_devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, handle)| {
+            //SAFETY: handle came straight from enumerate_physical_devices
+            let properties =
+                unsafe { instance.get_physical_device_properties(handle) };
+            //SAFETY: device_name is always null terminated
+            let name =
+                unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned();
+
+            //SAFETY: handle came straight from enumerate_physical_devices
+            let features =
+                unsafe { instance.get_physical_device_features(handle) };
+
+            //SAFETY: handle came straight from enumerate_physical_devices
+            let memory_properties = unsafe {
+                instance.get_physical_device_memory_properties(handle)
+            };
+
+            //SAFETY: handle came straight from enumerate_physical_devices
+            let queue_family_count = unsafe {
+                instance.get_physical_device_queue_family_properties(handle)
+            }
+            .len() as u32;
+            let supports_present = (0..queue_family_count).any(|family| {
+                let Some((surface_callbacks, surface)) = present_target else {
+                    return true;
+                };
+                //SAFETY: family is in range of the families this device has
+                //and the surface is alive as long as the caller says it is
+                unsafe {
+                    surface_callbacks.get_physical_device_surface_support(
+                        handle, family, surface,
+                    )
+                }
+                .unwrap_or_else(|e| {
+                    log::warn!(
+                        "Couldn't query present support for {} family {}: \
+                        {:?}",
+                        name,
+                        family,
+                        e
+                    );
+                    false
+                })
+            });
+
+            PhysicalDeviceInfo {
+                index,
+                handle,
+                name,
+                device_type: properties.device_type,
+                api_version: properties.api_version,
+                supports_present,
+                limits: properties.limits,
+                features,
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                pipeline_cache_uuid: properties.pipeline_cache_uuid,
+                device_local_memory: device_local_memory(&memory_properties),
+            }
+        })
+        .collect())
+}
+
+//Sum of the DEVICE_LOCAL heap sizes. Integrated GPUs usually report some or
+//all of system memory here so don't read too much into it for them
+pub(crate) fn device_local_memory(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+) -> vk::DeviceSize {
+    memory_properties.memory_heaps
+        [..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum()
+}
+
+//Whether the device has at least min bytes of DEVICE_LOCAL memory. No
+//minimum lets every device through
+pub(crate) fn has_device_local_memory(
+    device: &PhysicalDeviceInfo,
+    min: Option<vk::DeviceSize>,
+) -> bool {
+    min.is_none_or(|min| device.device_local_memory >= min)
+}
+
+//Rates a device for rendering. None rejects it outright, otherwise the
+//highest score wins
+pub type DeviceScorer = Box<dyn Fn(&PhysicalDeviceInfo) -> Option<u32>>;
+
+//Discrete beats integrated beats everything else. Devices that can't present
+//to our surface are useless to us so they're rejected
+pub fn default_device_scorer(device: &PhysicalDeviceInfo) -> Option<u32> {
+    if !device.supports_present {
+        return None;
+    }
+    Some(match device.device_type {
+        PhysicalDeviceType::DISCRETE_GPU => 3,
+        PhysicalDeviceType::INTEGRATED_GPU => 2,
+        PhysicalDeviceType::VIRTUAL_GPU => 1,
+        _ => 0,
+    })
+}
+
+//Pick a device to render with. If the user asked for an index we use that
+//as long as it can present, otherwise the scorer decides. Ties go to
+//whichever device vulkan listed first
+pub(crate) fn select_physical_device<'a>(
+    devices: &'a [PhysicalDeviceInfo],
+    requested_index: Option<usize>,
+    scorer: &dyn Fn(&PhysicalDeviceInfo) -> Option<u32>,
+) -> Option<&'a PhysicalDeviceInfo> {
+    if let Some(requested_index) = requested_index {
+        //devices may have been filtered so go by the index vulkan gave them
+        let device = devices
+            .iter()
+            .find(|device| device.index == requested_index);
+        match device {
+            Some(device) if device.supports_present => {}
+            Some(device) => log::error!(
+                "Requested device {} ({}) can't present to our surface",
+                requested_index,
+                device.name
+            ),
+            None => log::error!(
+                "Requested device {} isn't one of the {} usable devices",
+                requested_index,
+                devices.len()
+            ),
+        }
+        return device.filter(|device| device.supports_present);
+    }
+
+    let mut best: Option<(u32, &PhysicalDeviceInfo)> = None;
+    for device in devices {
+        match scorer(device) {
+            Some(score) => {
+                log::debug!("Device {} scored {}", device.name, score);
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    best = Some((score, device));
+                }
+            }
+            None => log::debug!("Device {} rejected by scorer", device.name),
+        }
+    }
+    best.map(|(_, device)| device)
+}
+
+//Set this to a device index or part of a device's name to pick that device
+//without touching the code. Handy on machines with more than one GPU
+pub const DEVICE_ENV_VAR: &str = "SUFAT_DEVICE";
+
+//The device request names, either its index or a case insensitive piece of
+//its name. Devices that can't present are skipped. None if nothing matches
+fn find_requested_device<'a>(
+    devices: &'a [PhysicalDeviceInfo],
+    request: &str,
+) -> Option<&'a PhysicalDeviceInfo> {
+    let request = request.trim();
+    if let Ok(index) = request.parse::<usize>() {
+        return devices
+            .iter()
+            .find(|device| device.index == index && device.supports_present);
+    }
+    let request = request.to_lowercase();
+    devices.iter().find(|device| {
+        device.supports_present && device.name.to_lowercase().contains(&request)
+    })
+}
+
+//Pick a device the way RenderContext does: a device named in DEVICE_ENV_VAR
+//wins, then the requested index, then the scorer
+pub(crate) fn choose_device<'a>(
+    devices: &'a [PhysicalDeviceInfo],
+    env_request: Option<&str>,
+    requested_index: Option<usize>,
+    scorer: &dyn Fn(&PhysicalDeviceInfo) -> Option<u32>,
+) -> Option<&'a PhysicalDeviceInfo> {
+    if let Some(request) = env_request {
+        match find_requested_device(devices, request) {
+            Some(device) => {
+                log::info!("{} picked device {}", DEVICE_ENV_VAR, device.name);
+                return Some(device);
+            }
+            None => log::warn!(
+                "{}={:?} doesn't match any usable device, ignoring it",
+                DEVICE_ENV_VAR,
+                request
+            ),
+        }
+    }
+    select_physical_device(devices, requested_index, scorer)
+}
+
+//Vulkan gives us no way to walk the fields of PhysicalDeviceFeatures so
+//they're all spelled out here
+macro_rules! missing_features {
+    ($available:expr, $required:expr, $($field:ident,)*) => {{
+        let mut missing = Vec::new();
+        $(
+            if $required.$field == vk::TRUE && $available.$field != vk::TRUE {
+                missing.push(stringify!($field));
+            }
+        )*
+        missing
+    }};
+}
+
+//Names of the features in required that available doesn't have
+pub fn missing_features(
+    available: &vk::PhysicalDeviceFeatures,
+    required: &vk::PhysicalDeviceFeatures,
+) -> Vec<&'static str> {
+    missing_features!(
+        available,
+        required,
+        robust_buffer_access,
+        full_draw_index_uint32,
+        image_cube_array,
+        independent_blend,
+        geometry_shader,
+        tessellation_shader,
+        sample_rate_shading,
+        dual_src_blend,
+        logic_op,
+        multi_draw_indirect,
+        draw_indirect_first_instance,
+        depth_clamp,
+        depth_bias_clamp,
+        fill_mode_non_solid,
+        depth_bounds,
+        wide_lines,
+        large_points,
+        alpha_to_one,
+        multi_viewport,
+        sampler_anisotropy,
+        texture_compression_etc2,
+        texture_compression_astc_ldr,
+        texture_compression_bc,
+        occlusion_query_precise,
+        pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics,
+        fragment_stores_and_atomics,
+        shader_tessellation_and_geometry_point_size,
+        shader_image_gather_extended,
+        shader_storage_image_extended_formats,
+        shader_storage_image_multisample,
+        shader_storage_image_read_without_format,
+        shader_storage_image_write_without_format,
+        shader_uniform_buffer_array_dynamic_indexing,
+        shader_sampled_image_array_dynamic_indexing,
+        shader_storage_buffer_array_dynamic_indexing,
+        shader_storage_image_array_dynamic_indexing,
+        shader_clip_distance,
+        shader_cull_distance,
+        shader_float64,
+        shader_int64,
+        shader_int16,
+        shader_resource_residency,
+        shader_resource_min_lod,
+        sparse_binding,
+        sparse_residency_buffer,
+        sparse_residency_image2_d,
+        sparse_residency_image3_d,
+        sparse_residency2_samples,
+        sparse_residency4_samples,
+        sparse_residency8_samples,
+        sparse_residency16_samples,
+        sparse_residency_aliased,
+        variable_multisample_rate,
+        inherited_queries,
+    )
+}
+
+//Which queue families we use for what. compute and transfer are only set
+//when the device has a family dedicated to that job, otherwise that work
+//goes on the graphics family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    pub present: u32,
+    pub compute: Option<u32>,
+    pub transfer: Option<u32>,
+}
+
+impl QueueFamilies {
+    //None if the device doesn't have both a graphics family and a family
+    //that can present to the surface. Without a surface the graphics family
+    //stands in as the present family
+    pub(crate) fn find(
+        instance: &Instance,
+        present_target: PresentTarget,
+        physical_device: PhysicalDevice,
+    ) -> Option<QueueFamilies> {
+        //SAFETY: physical_device came from this instance
+        let families = unsafe {
+            instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+        let can_present = |family: u32| {
+            let Some((surface_callbacks, surface)) = present_target else {
+                return true;
+            };
+            //SAFETY: family is in range and the surface outlives this call
+            unsafe {
+                surface_callbacks.get_physical_device_surface_support(
+                    physical_device,
+                    family,
+                    surface,
+                )
+            }
+            .unwrap_or(false)
+        };
+        let find_family = |predicate: &dyn Fn(vk::QueueFlags) -> bool| {
+            families
+                .iter()
+                .enumerate()
+                .find(|(_, family)| {
+                    family.queue_count > 0 && predicate(family.queue_flags)
+                })
+                .map(|(index, _)| index as u32)
+        };
+
+        let graphics =
+            find_family(&|flags| flags.contains(vk::QueueFlags::GRAPHICS))?;
+        //presenting from the graphics family saves us an ownership transfer
+        //of every swapchain image so prefer that
+        let present = if can_present(graphics) {
+            graphics
+        } else {
+            (0..families.len() as u32).find(|&family| can_present(family))?
+        };
+        let compute = find_family(&|flags| {
+            flags.contains(vk::QueueFlags::COMPUTE)
+                && !flags.contains(vk::QueueFlags::GRAPHICS)
+        });
+        //a family that can only do transfers is usually backed by a DMA
+        //engine which is what we want for uploads. Failing that take one
+        //that at least isn't the graphics family
+        let transfer = find_family(&|flags| {
+            flags.contains(vk::QueueFlags::TRANSFER)
+                && !flags.intersects(
+                    vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+                )
+        })
+        .or_else(|| {
+            find_family(&|flags| {
+                flags.contains(vk::QueueFlags::TRANSFER)
+                    && !flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+        });
+
+        Some(QueueFamilies {
+            graphics,
+            present,
+            compute,
+            transfer,
+        })
+    }
+
+    //Every family we use with duplicates removed. This is what a logical
+    //device needs to be created with
+    pub fn unique_families(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics, self.present];
+        families.extend(self.compute);
+        families.extend(self.transfer);
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+}
+
+//How much of one memory heap we're using and how much we can get away with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub flags: vk::MemoryHeapFlags,
+    pub size: vk::DeviceSize,
+    //bytes this process can allocate from the heap before things start
+    //failing or slowing down. Just the heap size without VK_EXT_memory_budget
+    pub budget: vk::DeviceSize,
+    //bytes this process has allocated from the heap. None without
+    //VK_EXT_memory_budget
+    pub usage: Option<vk::DeviceSize>,
+}
+
+impl HeapBudget {
+    //What's left of the budget, all of it if we don't know the usage
+    pub fn available(&self) -> vk::DeviceSize {
+        self.budget.saturating_sub(self.usage.unwrap_or(0))
+    }
+
+    pub fn is_device_local(&self) -> bool {
+        self.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::Handle;
+
+    fn device(
+        index: usize,
+        name: &str,
+        device_type: PhysicalDeviceType,
+        supports_present: bool,
+    ) -> PhysicalDeviceInfo {
+        PhysicalDeviceInfo {
+            index,
+            handle: PhysicalDevice::from_raw(index as u64 + 1),
+            name: name.to_string(),
+            device_type,
+            api_version: vk::API_VERSION_1_0,
+            supports_present,
+            limits: Default::default(),
+            features: Default::default(),
+            vendor_id: 0,
+            device_id: 0,
+            pipeline_cache_uuid: [0; vk::UUID_SIZE],
+            device_local_memory: 0,
+        }
+    }
+
+    fn picked(device: Option<&PhysicalDeviceInfo>) -> Option<usize> {
+        device.map(|device| device.index)
+    }
+
+    #[test]
+    fn discrete_beats_integrated() {
+        let devices = [
+            device(0, "Integrated", PhysicalDeviceType::INTEGRATED_GPU, true),
+            device(1, "Discrete", PhysicalDeviceType::DISCRETE_GPU, true),
+        ];
+        assert_eq!(
+            picked(select_physical_device(
+                &devices,
+                None,
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn requested_index_is_honored() {
+        let devices = [
+            device(0, "Discrete", PhysicalDeviceType::DISCRETE_GPU, true),
+            device(1, "Integrated", PhysicalDeviceType::INTEGRATED_GPU, true),
+        ];
+        assert_eq!(
+            picked(select_physical_device(
+                &devices,
+                Some(1),
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn device_that_cant_present_is_rejected() {
+        let devices = [
+            device(0, "Discrete", PhysicalDeviceType::DISCRETE_GPU, false),
+            device(1, "Integrated", PhysicalDeviceType::INTEGRATED_GPU, true),
+        ];
+        assert_eq!(default_device_scorer(&devices[0]), None);
+        assert_eq!(
+            picked(select_physical_device(
+                &devices,
+                None,
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+        //asking for it by index doesn't help either
+        assert_eq!(
+            picked(select_physical_device(
+                &devices,
+                Some(0),
+                &default_device_scorer
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_first_device() {
+        let devices = [
+            device(0, "First", PhysicalDeviceType::DISCRETE_GPU, true),
+            device(1, "Second", PhysicalDeviceType::DISCRETE_GPU, true),
+        ];
+        assert_eq!(
+            picked(select_physical_device(
+                &devices,
+                None,
+                &default_device_scorer
+            )),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn env_request_matches_name_case_insensitively() {
+        let devices = [
+            device(0, "AMD Radeon", PhysicalDeviceType::DISCRETE_GPU, true),
+            device(1, "Intel UHD", PhysicalDeviceType::INTEGRATED_GPU, true),
+        ];
+        assert_eq!(
+            picked(choose_device(
+                &devices,
+                Some("intel"),
+                None,
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+        //a request that matches nothing falls through to the scorer
+        assert_eq!(
+            picked(choose_device(
+                &devices,
+                Some("nvidia"),
+                None,
+                &default_device_scorer
+            )),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn env_request_overrides_requested_index() {
+        let devices = [
+            device(0, "Discrete", PhysicalDeviceType::DISCRETE_GPU, true),
+            device(1, "Integrated", PhysicalDeviceType::INTEGRATED_GPU, true),
+        ];
+        assert_eq!(
+            picked(choose_device(
+                &devices,
+                Some(" 1 "),
+                Some(0),
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+        assert_eq!(
+            picked(choose_device(
+                &devices,
+                None,
+                Some(1),
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn memory_floor_rejects_small_devices() {
+        const GIB: vk::DeviceSize = 1 << 30;
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: 3,
+            ..Default::default()
+        };
+        memory_properties.memory_heaps[0] = vk::MemoryHeap {
+            size: 2 * GIB,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        memory_properties.memory_heaps[1] = vk::MemoryHeap {
+            size: 16 * GIB,
+            flags: vk::MemoryHeapFlags::empty(),
+        };
+        memory_properties.memory_heaps[2] = vk::MemoryHeap {
+            size: GIB / 4,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        //heaps past memory_heap_count aren't real and don't count
+        memory_properties.memory_heaps[3] = vk::MemoryHeap {
+            size: 64 * GIB,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        assert_eq!(device_local_memory(&memory_properties), 2 * GIB + GIB / 4);
+
+        let mut discrete =
+            device(0, "Discrete", PhysicalDeviceType::DISCRETE_GPU, true);
+        discrete.device_local_memory = 2 * GIB;
+        let mut integrated =
+            device(1, "Integrated", PhysicalDeviceType::INTEGRATED_GPU, true);
+        integrated.device_local_memory = 8 * GIB;
+        let devices = [discrete, integrated];
+
+        let with_at_least = |min| {
+            devices
+                .iter()
+                .filter(|device| has_device_local_memory(device, min))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            picked(select_physical_device(
+                &with_at_least(None),
+                None,
+                &default_device_scorer
+            )),
+            Some(0)
+        );
+        assert_eq!(
+            picked(select_physical_device(
+                &with_at_least(Some(4 * GIB)),
+                None,
+                &default_device_scorer
+            )),
+            Some(1)
+        );
+        assert_eq!(
+            picked(select_physical_device(
+                &with_at_least(Some(16 * GIB)),
+                None,
+                &default_device_scorer
+            )),
+            None
+        );
+    }
+}
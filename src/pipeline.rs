@@ -0,0 +1,708 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+use cstr::cstr;
+
+use crate::{
+    descriptor::DescriptorSetLayout,
+    render_context::{RenderContext, RenderContextError},
+    render_pass::RenderPass,
+    shader::ShaderModule,
+};
+
+//A built pipeline along with its layout. Both get destroyed on drop
+pub struct Pipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    bind_point: vk::PipelineBindPoint,
+    device: Device,
+}
+
+impl Pipeline {
+    pub fn handle(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    pub fn bind_point(&self) -> vk::PipelineBindPoint {
+        self.bind_point
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the pipeline
+        //before dropping it
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+//Only 128 bytes of push constants are guaranteed so check before the driver
+//does
+fn check_push_constants_size(
+    render_context: &RenderContext,
+    ranges: &[vk::PushConstantRange],
+) -> Result<(), RenderContextError> {
+    let size = ranges
+        .iter()
+        .map(|range| range.offset + range.size)
+        .max()
+        .unwrap_or(0);
+    let max = render_context.max_push_constants_size();
+    if size > max {
+        return Err(RenderContextError::PushConstantsTooLarge { size, max });
+    }
+    Ok(())
+}
+
+//Types specialization constants can be set to. bools go in as VkBool32s so
+//every one of these is 4 bytes
+pub trait SpecializationValue: Copy {
+    fn to_bytes(self) -> [u8; 4];
+}
+
+impl SpecializationValue for u32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecializationValue for i32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecializationValue for f32 {
+    fn to_bytes(self) -> [u8; 4] {
+        self.to_ne_bytes()
+    }
+}
+
+impl SpecializationValue for bool {
+    fn to_bytes(self) -> [u8; 4] {
+        (self as vk::Bool32).to_ne_bytes()
+    }
+}
+
+//Map entries and the data blob they point into for a
+//vk::SpecializationInfo
+#[derive(Clone, Default)]
+struct SpecializationConstants {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    //Setting the same id twice keeps the last value
+    fn set<T: SpecializationValue>(&mut self, id: u32, value: T) {
+        let bytes = value.to_bytes();
+        match self.entries.iter().find(|entry| entry.constant_id == id) {
+            Some(entry) => {
+                let offset = entry.offset as usize;
+                self.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            }
+            None => {
+                self.entries.push(vk::SpecializationMapEntry {
+                    constant_id: id,
+                    offset: self.data.len() as u32,
+                    size: bytes.len(),
+                });
+                self.data.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    //Borrows self so it has to outlive the pipeline creation call
+    fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+//The same pipeline built filled and as a wireframe, so
+//RenderContext::set_wireframe can flip between them. wireframe is None when
+//the device can't draw lines
+pub struct PipelineVariants {
+    filled: Pipeline,
+    wireframe: Option<Pipeline>,
+}
+
+impl PipelineVariants {
+    pub fn filled(&self) -> &Pipeline {
+        &self.filled
+    }
+
+    pub fn wireframe(&self) -> Option<&Pipeline> {
+        self.wireframe.as_ref()
+    }
+
+    //The wireframe pipeline if asked for and we have one, otherwise the
+    //filled one
+    pub fn get(&self, wireframe: bool) -> &Pipeline {
+        match &self.wireframe {
+            Some(pipeline) if wireframe => pipeline,
+            _ => &self.filled,
+        }
+    }
+}
+
+//Builds a graphics pipeline with defaults that suit most simple cases: back
+//face culling with counter clockwise front faces, no blending,
+//depth testing and a dynamic viewport and scissor so resizing doesn't need a
+//rebuild
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder<'a> {
+    vertex_shader: &'a ShaderModule,
+    fragment_shader: &'a ShaderModule,
+    render_pass: vk::RenderPass,
+    //taken from the render pass since the two have to match
+    samples: vk::SampleCountFlags,
+    //color and depth formats for dynamic passes, which pipelines name
+    //instead of a render pass object
+    rendering_formats: Option<(vk::Format, vk::Format)>,
+    subpass: u32,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    //Some means bake a viewport and scissor covering this extent into the
+    //pipeline instead of making them dynamic
+    baked_extent: Option<vk::Extent2D>,
+    polygon_mode: vk::PolygonMode,
+    line_width: f32,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    alpha_blending: bool,
+    //blend with src color factor one instead of src_alpha
+    premultiplied_alpha: bool,
+    depth_test: bool,
+    depth_write: bool,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    specialization_constants: SpecializationConstants,
+    name: &'a str,
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    pub fn new(
+        vertex_shader: &'a ShaderModule,
+        fragment_shader: &'a ShaderModule,
+        render_pass: &RenderPass,
+    ) -> Self {
+        GraphicsPipelineBuilder {
+            vertex_shader,
+            fragment_shader,
+            render_pass: render_pass.handle(),
+            samples: render_pass.samples(),
+            rendering_formats: render_pass.is_dynamic().then(|| {
+                (
+                    render_pass.color_format(),
+                    render_pass.depth_format().unwrap_or(vk::Format::UNDEFINED),
+                )
+            }),
+            subpass: 0,
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            baked_extent: None,
+            polygon_mode: vk::PolygonMode::FILL,
+            line_width: 1.0,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            alpha_blending: false,
+            premultiplied_alpha: false,
+            depth_test: true,
+            depth_write: true,
+            descriptor_set_layouts: Vec::new(),
+            push_constant_ranges: Vec::new(),
+            specialization_constants: SpecializationConstants::default(),
+            name: "graphics pipeline",
+        }
+    }
+
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+
+    //Layout of the vertex buffers. Leave it empty if the vertex shader makes
+    //up its own vertices
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    //Add a binding of T that steps once per instance rather than once per
+    //vertex, for InstanceBuffers. Adds to what vertex_input set so call it
+    //after that
+    pub fn instance_input<T: Copy>(
+        mut self,
+        binding: u32,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings
+            .push(vk::VertexInputBindingDescription {
+                binding,
+                stride: std::mem::size_of::<T>() as u32,
+                input_rate: vk::VertexInputRate::INSTANCE,
+            });
+        self.vertex_attributes.extend_from_slice(attributes);
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    //Bake a viewport and scissor covering extent into the pipeline. The
+    //pipeline then has to be rebuilt whenever the target changes size
+    pub fn baked_viewport(mut self, extent: vk::Extent2D) -> Self {
+        self.baked_extent = Some(extent);
+        self
+    }
+
+    //LINE and POINT need the fillModeNonSolid feature
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    //Anything but 1 needs the wideLines feature
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    //Standard src_alpha/one_minus_src_alpha blending on the color attachment
+    pub fn alpha_blending(mut self, alpha_blending: bool) -> Self {
+        self.alpha_blending = alpha_blending;
+        self.premultiplied_alpha = false;
+        self
+    }
+
+    //one/one_minus_src_alpha blending, for colors that already have alpha
+    //multiplied in, e.g. egui's
+    pub fn premultiplied_alpha_blending(mut self, blending: bool) -> Self {
+        self.alpha_blending = blending;
+        self.premultiplied_alpha = blending;
+        self
+    }
+
+    //Test against and write to the depth attachment, keeping the nearest
+    //fragment. Has no effect in subpasses without a depth attachment
+    pub fn depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    //Turn off just the depth writes so the pipeline still hides behind
+    //what's already drawn without hiding anything itself, e.g. for overlays
+    pub fn depth_write(mut self, depth_write: bool) -> Self {
+        self.depth_write = depth_write;
+        self
+    }
+
+    //Set layouts the shaders use, in set number order
+    pub fn descriptor_set_layouts(
+        mut self,
+        layouts: &[&DescriptorSetLayout],
+    ) -> Self {
+        self.descriptor_set_layouts =
+            layouts.iter().map(|layout| layout.handle()).collect();
+        self
+    }
+
+    //Reserve size bytes of push constants starting at offset for stages.
+    //Both have to be multiples of 4
+    pub fn push_constant_range(
+        mut self,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        size: u32,
+    ) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange {
+            stage_flags: stages,
+            offset,
+            size,
+        });
+        self
+    }
+
+    //Set the constant_id = id specialization constant to value. Both shaders
+    //get the same constants, an id a shader doesn't declare is ignored
+    pub fn specialization_constant<T: SpecializationValue>(
+        mut self,
+        id: u32,
+        value: T,
+    ) -> Self {
+        self.specialization_constants.set(id, value);
+        self
+    }
+
+    //What the pipeline and its layout are called in validation messages and
+    //graphics debuggers
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn build(
+        self,
+        render_context: &RenderContext,
+    ) -> Result<Pipeline, RenderContextError> {
+        let device = render_context.device();
+        //catch these here rather than as validation errors at draw time
+        let features = render_context.enabled_features();
+        if self.polygon_mode != vk::PolygonMode::FILL
+            && features.fill_mode_non_solid != vk::TRUE
+        {
+            return Err(RenderContextError::MissingDeviceFeature(vec![
+                "fill_mode_non_solid".to_string(),
+            ]));
+        }
+        if self.line_width != 1.0 && features.wide_lines != vk::TRUE {
+            return Err(RenderContextError::MissingDeviceFeature(vec![
+                "wide_lines".to_string(),
+            ]));
+        }
+        check_push_constants_size(render_context, &self.push_constant_ranges)?;
+        let entry_point = cstr!("main");
+        let specialization_info = self.specialization_constants.info();
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(self.vertex_shader.handle())
+                .name(entry_point)
+                .specialization_info(&specialization_info)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.fragment_shader.handle())
+                .name(entry_point)
+                .specialization_info(&specialization_info)
+                .build(),
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes)
+            .build();
+        let input_assembly =
+            vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(self.topology)
+                .primitive_restart_enable(false)
+                .build();
+
+        let viewports;
+        let scissors;
+        let dynamic_states;
+        let (viewport_state, dynamic_state) = match self.baked_extent {
+            Some(extent) => {
+                viewports = [vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }];
+                scissors = [vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                }];
+                (
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&viewports)
+                        .scissors(&scissors)
+                        .build(),
+                    vk::PipelineDynamicStateCreateInfo::default(),
+                )
+            }
+            None => {
+                dynamic_states =
+                    [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+                (
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewport_count(1)
+                        .scissor_count(1)
+                        .build(),
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+            }
+        };
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(self.line_width)
+            .build();
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(self.samples)
+            .build();
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_test && self.depth_write)
+            .depth_compare_op(vk::CompareOp::LESS)
+            .build();
+
+        let color_blend_attachments =
+            [vk::PipelineColorBlendAttachmentState::builder()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .blend_enable(self.alpha_blending)
+                .src_color_blend_factor(if self.premultiplied_alpha {
+                    vk::BlendFactor::ONE
+                } else {
+                    vk::BlendFactor::SRC_ALPHA
+                })
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .build()];
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachments)
+            .build();
+
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&self.push_constant_ranges)
+            .build();
+        //SAFETY: layout_info was made with a builder and the set layouts are
+        //alive
+        let layout =
+            unsafe { device.create_pipeline_layout(&layout_info, None) }
+                .map_err(RenderContextError::PipelineCreationFailed)?;
+
+        let (color_format, depth_format) =
+            self.rendering_formats.unwrap_or_default();
+        let color_formats = [color_format];
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&color_formats)
+            .depth_attachment_format(depth_format);
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .render_pass(self.render_pass)
+            .subpass(self.subpass);
+        if self.rendering_formats.is_some() {
+            create_info = create_info.push_next(&mut rendering_info);
+        }
+        let create_info = create_info.build();
+
+        //SAFETY: everything create_info points at lives until the end of this
+        //function and the shader modules are borrowed for that long too
+        let pipeline = match unsafe {
+            device.create_graphics_pipelines(
+                render_context.pipeline_cache().handle(),
+                &[create_info],
+                None,
+            )
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, e)) => {
+                //SAFETY: the layout was made above and nothing uses it
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(RenderContextError::PipelineCreationFailed(e));
+            }
+        };
+
+        render_context.set_object_name(pipeline, self.name);
+        render_context
+            .set_object_name(layout, &format!("{} layout", self.name));
+        Ok(Pipeline {
+            pipeline,
+            layout,
+            bind_point: vk::PipelineBindPoint::GRAPHICS,
+            device: device.clone(),
+        })
+    }
+
+    //Build this pipeline filled plus a LINE mode copy of it. Without the
+    //fillModeNonSolid feature only the filled one gets built
+    pub fn build_variants(
+        self,
+        render_context: &RenderContext,
+    ) -> Result<PipelineVariants, RenderContextError> {
+        let wireframe = if render_context.enabled_features().fill_mode_non_solid
+            == vk::TRUE
+        {
+            let name = format!("{} wireframe", self.name);
+            Some(
+                self.clone()
+                    .polygon_mode(vk::PolygonMode::LINE)
+                    //backfaces are the interesting part of a wireframe
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .name(&name)
+                    .build(render_context)?,
+            )
+        } else {
+            log::debug!(
+                "fillModeNonSolid isn't enabled, {} has no wireframe",
+                self.name
+            );
+            None
+        };
+        Ok(PipelineVariants {
+            filled: self
+                .polygon_mode(vk::PolygonMode::FILL)
+                .build(render_context)?,
+            wireframe,
+        })
+    }
+}
+
+//Builds a compute pipeline out of a single compute shader
+pub struct ComputePipelineBuilder<'a> {
+    shader: &'a ShaderModule,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    specialization_constants: SpecializationConstants,
+    name: &'a str,
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn new(shader: &'a ShaderModule) -> Self {
+        ComputePipelineBuilder {
+            shader,
+            descriptor_set_layouts: Vec::new(),
+            push_constant_ranges: Vec::new(),
+            specialization_constants: SpecializationConstants::default(),
+            name: "compute pipeline",
+        }
+    }
+
+    //Set layouts the shader uses, in set number order
+    pub fn descriptor_set_layouts(
+        mut self,
+        layouts: &[&DescriptorSetLayout],
+    ) -> Self {
+        self.descriptor_set_layouts =
+            layouts.iter().map(|layout| layout.handle()).collect();
+        self
+    }
+
+    //Reserve size bytes of push constants starting at offset. Both have to
+    //be multiples of 4
+    pub fn push_constant_range(mut self, offset: u32, size: u32) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset,
+            size,
+        });
+        self
+    }
+
+    //Set the constant_id = id specialization constant to value
+    pub fn specialization_constant<T: SpecializationValue>(
+        mut self,
+        id: u32,
+        value: T,
+    ) -> Self {
+        self.specialization_constants.set(id, value);
+        self
+    }
+
+    //What the pipeline and its layout are called in validation messages and
+    //graphics debuggers
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn build(
+        self,
+        render_context: &RenderContext,
+    ) -> Result<Pipeline, RenderContextError> {
+        let device = render_context.device();
+        check_push_constants_size(render_context, &self.push_constant_ranges)?;
+
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&self.push_constant_ranges)
+            .build();
+        //SAFETY: layout_info was made with a builder and the set layouts are
+        //alive
+        let layout =
+            unsafe { device.create_pipeline_layout(&layout_info, None) }
+                .map_err(RenderContextError::PipelineCreationFailed)?;
+
+        let specialization_info = self.specialization_constants.info();
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(self.shader.handle())
+            .name(cstr!("main"))
+            .specialization_info(&specialization_info)
+            .build();
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout)
+            .build();
+        //SAFETY: everything create_info points at lives until the end of this
+        //function and the shader module is borrowed for that long too
+        let pipeline = match unsafe {
+            device.create_compute_pipelines(
+                render_context.pipeline_cache().handle(),
+                &[create_info],
+                None,
+            )
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, e)) => {
+                //SAFETY: the layout was made above and nothing uses it
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(RenderContextError::PipelineCreationFailed(e));
+            }
+        };
+
+        render_context.set_object_name(pipeline, self.name);
+        render_context
+            .set_object_name(layout, &format!("{} layout", self.name));
+        Ok(Pipeline {
+            pipeline,
+            layout,
+            bind_point: vk::PipelineBindPoint::COMPUTE,
+            device: device.clone(),
+        })
+    }
+}
@@ -0,0 +1,124 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{fs, path::PathBuf};
+
+use ash::{vk, Device};
+
+use crate::physical_device::PhysicalDeviceInfo;
+
+//Lets the driver skip recompiling pipelines it has seen before. With a path
+//the cache is seeded from that file and written back to it when dropped so
+//the savings carry over to the next run
+pub struct PipelineCache {
+    cache: vk::PipelineCache,
+    path: Option<PathBuf>,
+    device: Device,
+}
+
+impl PipelineCache {
+    pub(crate) fn new(
+        device: &Device,
+        device_info: &PhysicalDeviceInfo,
+        path: Option<PathBuf>,
+    ) -> Result<PipelineCache, vk::Result> {
+        //a missing or stale file just means starting from scratch
+        let initial_data = match path.as_ref().map(fs::read) {
+            Some(Ok(data)) if header_matches(&data, device_info) => {
+                log::debug!("Loaded {} bytes of pipeline cache", data.len());
+                data
+            }
+            Some(Ok(_)) => {
+                log::info!(
+                    "Pipeline cache was made by another device or driver, \
+                    starting fresh"
+                );
+                Vec::new()
+            }
+            Some(Err(e)) => {
+                log::debug!("No pipeline cache loaded: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(&initial_data)
+            .build();
+        //SAFETY: create_info was made with a builder and initial_data was
+        //checked against this device's header above
+        let cache =
+            unsafe { device.create_pipeline_cache(&create_info, None) }?;
+        Ok(PipelineCache {
+            cache,
+            path,
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    //Write the cache out to its path now rather than waiting for drop
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        //SAFETY: the cache is alive and externally synchronized by &self
+        let data =
+            match unsafe { self.device.get_pipeline_cache_data(self.cache) } {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Failed to read back pipeline cache: {:?}", e);
+                    return;
+                }
+            };
+        if let Some(parent) = path.parent() {
+            //write reports the error if this didn't work
+            let _ = fs::create_dir_all(parent);
+        }
+        match fs::write(path, &data) {
+            Ok(()) => log::debug!(
+                "Saved {} bytes of pipeline cache to {}",
+                data.len(),
+                path.display()
+            ),
+            Err(e) => log::warn!(
+                "Failed to save pipeline cache to {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        self.save();
+        //SAFETY: whoever owns us makes sure no pipeline creation is using
+        //the cache
+        unsafe { self.device.destroy_pipeline_cache(self.cache, None) }
+    }
+}
+
+//Drivers are supposed to reject data from other devices themselves but
+//plenty crash instead, so check the header (VkPipelineCacheHeaderVersionOne)
+//before handing it over. Everything in it is little endian
+fn header_matches(data: &[u8], device_info: &PhysicalDeviceInfo) -> bool {
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset + 4).map(|bytes| {
+            u32::from_le_bytes(bytes.try_into().expect("slice is 4 bytes"))
+        })
+    };
+    let header_size = 16 + vk::UUID_SIZE;
+    read_u32(0).is_some_and(|size| size as usize >= header_size)
+        && read_u32(4)
+            == Some(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32)
+        && read_u32(8) == Some(device_info.vendor_id)
+        && read_u32(12) == Some(device_info.device_id)
+        && data.get(16..header_size)
+            == Some(&device_info.pipeline_cache_uuid[..])
+}
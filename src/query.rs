@@ -0,0 +1,274 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+//A pool of queries of one type. Queries have to be reset, outside of any
+//render pass, before they can be written again
+pub struct QueryPool {
+    pool: vk::QueryPool,
+    query_type: vk::QueryType,
+    count: u32,
+    //which bits of a result mean anything. Timestamps only fill in as many
+    //bits as the queue family's timestampValidBits says
+    result_mask: u64,
+    device: Device,
+}
+
+impl QueryPool {
+    //valid_bits is how many low bits of each result are meaningful, 64 for
+    //anything but timestamps
+    pub(crate) fn new(
+        device: &Device,
+        query_type: vk::QueryType,
+        count: u32,
+        valid_bits: u32,
+    ) -> Result<QueryPool, vk::Result> {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(query_type)
+            .query_count(count)
+            .build();
+        //SAFETY: create_info was made with a builder
+        let pool = unsafe { device.create_query_pool(&create_info, None) }?;
+        let result_mask = if valid_bits >= u64::BITS {
+            u64::MAX
+        } else {
+            (1 << valid_bits) - 1
+        };
+        Ok(QueryPool {
+            pool,
+            query_type,
+            count,
+            result_mask,
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::QueryPool {
+        self.pool
+    }
+
+    pub fn query_type(&self) -> vk::QueryType {
+        self.query_type
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    //Record a reset of count queries starting at first. Has to happen
+    //outside of a render pass
+    pub fn reset(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        first: u32,
+        count: u32,
+    ) {
+        assert!(
+            first + count <= self.count,
+            "resetting queries past the end of the pool"
+        );
+        //SAFETY: the command buffer is recording and the range is in bounds
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                first,
+                count,
+            )
+        }
+    }
+
+    //Record writing the GPU's clock into query index once every command
+    //before it has gotten past stage. The query has to have been reset
+    pub fn write_timestamp(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags,
+        index: u32,
+    ) {
+        assert_eq!(
+            self.query_type,
+            vk::QueryType::TIMESTAMP,
+            "timestamps can only go in timestamp pools"
+        );
+        assert!(index < self.count, "query index past the end of the pool");
+        //SAFETY: the command buffer is recording and index is in bounds
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                stage,
+                self.pool,
+                index,
+            )
+        }
+    }
+
+    //Results of count queries starting at first, with any bits the queue
+    //doesn't fill in masked off. None if any of them hasn't finished yet or
+    //hasn't been written since it was last reset. Doesn't block
+    pub fn results(
+        &self,
+        first: u32,
+        count: u32,
+    ) -> Result<Option<Vec<u64>>, vk::Result> {
+        assert!(
+            first + count <= self.count,
+            "reading queries past the end of the pool"
+        );
+        let mut results = vec![0u64; count as usize];
+        //SAFETY: results has room for count 64 bit values
+        match unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                first,
+                count,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        } {
+            Ok(()) => Ok(Some(
+                results
+                    .into_iter()
+                    .map(|result| result & self.result_mask)
+                    .collect(),
+            )),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    //Ticks between two timestamps from this pool, accounting for the
+    //counter wrapping around within its valid bits
+    pub fn ticks_between(&self, start: u64, end: u64) -> u64 {
+        end.wrapping_sub(start) & self.result_mask
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the pool
+        unsafe { self.device.destroy_query_pool(self.pool, None) }
+    }
+}
+
+//Counts the samples that pass the depth and stencil tests between
+//FrameRecorder::begin_query and end_query, e.g. to tell whether something
+//ended up visible. Holds queries_per_frame queries for every frame in
+//flight. Each frame's have to be reset with
+//ComputeRecorder::reset_occlusion_queries before they're begun again
+pub struct OcclusionQueryPool {
+    pool: QueryPool,
+    queries_per_frame: u32,
+    //exact counts rather than just zero or not zero
+    precise: bool,
+}
+
+impl OcclusionQueryPool {
+    pub(crate) fn new(
+        pool: QueryPool,
+        queries_per_frame: u32,
+        precise: bool,
+    ) -> OcclusionQueryPool {
+        OcclusionQueryPool {
+            pool,
+            queries_per_frame,
+            precise,
+        }
+    }
+
+    pub fn pool(&self) -> &QueryPool {
+        &self.pool
+    }
+
+    pub fn queries_per_frame(&self) -> u32 {
+        self.queries_per_frame
+    }
+
+    //Without this a result only says whether any samples passed, anything
+    //but 0 is some implementation defined count
+    pub fn precise(&self) -> bool {
+        self.precise
+    }
+
+    //Where query index of frame frame_index lives in the pool
+    fn query(&self, frame_index: usize, index: u32) -> u32 {
+        assert!(
+            index < self.queries_per_frame,
+            "occlusion query index past the end of the frame's queries"
+        );
+        frame_index as u32 * self.queries_per_frame + index
+    }
+
+    pub(crate) fn reset(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        self.pool.reset(
+            command_buffer,
+            self.query(frame_index, 0),
+            self.queries_per_frame,
+        )
+    }
+
+    pub(crate) fn begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        index: u32,
+    ) {
+        let flags = if self.precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+        //SAFETY: the command buffer is recording and the query is in bounds.
+        //The caller resets it before beginning it
+        unsafe {
+            device.cmd_begin_query(
+                command_buffer,
+                self.pool.handle(),
+                self.query(frame_index, index),
+                flags,
+            )
+        }
+    }
+
+    pub(crate) fn end(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        index: u32,
+    ) {
+        //SAFETY: the command buffer is recording and the query was begun
+        unsafe {
+            device.cmd_end_query(
+                command_buffer,
+                self.pool.handle(),
+                self.query(frame_index, index),
+            )
+        }
+    }
+
+    //Sample counts of the first count queries frame_index wrote the last
+    //time it was submitted. Read them before they're reset for this frame,
+    //e.g. at the start of the draw_frame callbacks, when the last use of
+    //frame_index is known to be done. None if any of them weren't written
+    pub fn results(
+        &self,
+        frame_index: usize,
+        count: u32,
+    ) -> Result<Option<Vec<u64>>, vk::Result> {
+        assert!(
+            count <= self.queries_per_frame,
+            "reading occlusion queries past the end of the frame's queries"
+        );
+        self.pool.results(self.query(frame_index, 0), count)
+    }
+}
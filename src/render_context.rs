@@ -4,257 +4,4459 @@ License, v. 2.0. If a copy of the MPL was not distributed with this
 file, You can obtain one at https://mozilla.org/MPL/2.0/.
 */
 
-use std::{borrow::Cow, ffi::CStr, sync::Arc};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    error::Error,
+    ffi::{c_void, CStr, CString},
+    fmt::{self, Write},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ash::{
+    extensions::{
+        ext::DebugUtils,
+        khr::{self, Surface},
+    },
+    vk::{
+        self, ApplicationInfo, DebugUtilsMessageSeverityFlagsEXT,
+        DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
+        DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, SurfaceKHR,
+    },
+    Device, Entry, Instance,
+};
+use cstr::cstr;
+use log::Level;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use winit::window::{Window, WindowId};
+
+use crate::app::LoopMode;
+#[cfg(feature = "allocator")]
+use crate::attachment::AttachmentImage;
+use crate::barrier::compute_to_graphics_barrier;
+use crate::command::{CommandManager, SecondaryPool};
+use crate::frame::{
+    classify_present_result, present_result_code, ComputeRecorder,
+    FrameRecorder, FrameStats, FrameSync, FrameTimer, Frames, PassInheritance,
+    PresentOutcome, SecondaryRecorder, DEFAULT_FRAMES_IN_FLIGHT,
+};
+use crate::framebuffer::Framebuffers;
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::{PipelineRecipe, ShaderWatcher, WatchedPipeline};
+#[cfg(feature = "allocator")]
+use crate::memory::{
+    Buffer, DrawIndirectBuffer, FrameArena, Image, IndexBuffer, InstanceBuffer,
+    MemoryLocation, SharedAllocator, UniformBuffer, VertexBuffer,
+};
+#[cfg(feature = "gltf")]
+use crate::model::Model;
+use crate::physical_device::{
+    self, default_device_scorer, has_device_local_memory, missing_features,
+    DeviceScorer, HeapBudget, PhysicalDeviceInfo, PresentTarget, QueueFamilies,
+};
+use crate::pipeline_cache::PipelineCache;
+use crate::query::{OcclusionQueryPool, QueryPool};
+use crate::render_pass::{self, RenderPass};
+#[cfg(feature = "dynamic-rendering")]
+use crate::render_pass::{DynamicRenderingFns, RenderingTargets};
+use crate::shader::{spirv_words, ShaderModule, SpirvError};
+use crate::submit::Submission;
+use crate::surface::{SurfaceId, WindowTarget};
+use crate::swapchain::{unsynced_present_mode, SurfaceInfo, Swapchain};
+use crate::timeline::{TimelineFns, TimelineSemaphore};
+#[cfg(feature = "allocator")]
+use crate::upload::{
+    lock_handoffs, record_acquire, SharedHandoffs, UploadManager,
+};
+#[cfg(feature = "textures")]
+use crate::{capture::Capture, texture::Texture};
+#[cfg(feature = "textures")]
+use image::RgbaImage;
+
+#[allow(dead_code)]
+pub struct RenderContext {
+    entry: Entry,
+    instance: Instance,
+    debug_callback: Option<DebugUtilsMessengerEXT>,
+    //both of these are None when validation is turned off
+    debug_utils_loader: Option<DebugUtils>,
+    //ERROR messages the debug messenger has seen. Boxed since the messenger
+    //holds a pointer to it, and it has to outlive destroy_instance
+    validation_errors: Box<AtomicU64>,
+    //what the persistent messenger was made to pass on, see set_debug_filter
+    debug_severity: DebugUtilsMessageSeverityFlagsEXT,
+    debug_message_types: DebugUtilsMessageTypeFlagsEXT,
+    //None for headless contexts
+    surface: Option<SurfaceKHR>,
+    surface_callbacks: Surface,
+    rendering_paused: bool,
+    api_version: u32,
+    enabled_layers: Vec<String>,
+    enabled_extensions: Vec<String>,
+    creation_timings: CreationTimings,
+    physical_device: vk::PhysicalDevice,
+    physical_device_info: Option<PhysicalDeviceInfo>,
+    queue_families: Option<QueueFamilies>,
+    //the features required through the builder, which are all the device
+    //gets created with
+    enabled_features: vk::PhysicalDeviceFeatures,
+    //None only while we're still being constructed
+    device: Option<Device>,
+    //whether VK_EXT_memory_budget was enabled on the device
+    memory_budget_enabled: bool,
+    //None when the device has neither vulkan 1.2 nor
+    //VK_KHR_timeline_semaphore
+    timeline_fns: Option<TimelineFns>,
+    //None when the dynamic-rendering feature is on but the device can't do
+    //it, in which case we fall back to render pass objects
+    #[cfg(feature = "dynamic-rendering")]
+    dynamic_rendering: Option<DynamicRenderingFns>,
+    //whether the descriptor indexing features BindlessTextureArray needs
+    //were enabled on the device
+    #[cfg(feature = "bindless")]
+    descriptor_indexing: bool,
+    pipeline_cache: Option<PipelineCache>,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<ShaderWatcher>,
+    graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+    //the graphics queue unless the device has a dedicated compute family
+    compute_queue: vk::Queue,
+    //same deal for transfer
+    transfer_queue: vk::Queue,
+    //None for headless contexts, which draw to headless_target instead
+    swapchain: Option<Swapchain>,
+    #[cfg(feature = "allocator")]
+    headless_target: Option<AttachmentImage>,
+    render_pass: Option<RenderPass>,
+    framebuffers: Option<Framebuffers>,
+    #[cfg(feature = "allocator")]
+    depth_buffer: Option<AttachmentImage>,
+    //the multisampled color target when msaa is on
+    #[cfg(feature = "allocator")]
+    msaa_target: Option<AttachmentImage>,
+    frames: Option<Frames>,
+    commands: Option<CommandManager>,
+    //one per worker draw_frame_parallel has been asked for, made on first
+    //use
+    secondary_pools: Vec<SecondaryPool>,
+    //a start and end timestamp for each frame in flight. None when the
+    //graphics queue can't write timestamps
+    frame_timestamps: Option<QueryPool>,
+    //how long the GPU took over the most recent frame we got timestamps
+    //back for
+    last_frame_gpu_time_ms: Option<f64>,
+    //signaled to frames_submitted by each frame's submit, when timeline
+    //semaphores are supported
+    frame_timeline: Option<TimelineSemaphore>,
+    frames_submitted: u64,
+    frame_timer: FrameTimer,
+    //only read back by whoever runs the event loop
+    loop_mode: LoopMode,
+    show_after_first_frame: bool,
+    #[cfg(feature = "allocator")]
+    allocator: Option<SharedAllocator>,
+    //batches upload managers have flushed, for draw_frame to wait on
+    #[cfg(feature = "allocator")]
+    upload_handoffs: SharedHandoffs,
+    preferred_present_mode: Option<vk::PresentModeKHR>,
+    //whether FrameRecorder::bind_variant picks wireframe pipelines
+    wireframe: bool,
+    //which of the target images the last draw_frame drew to, None until
+    //the first frame is submitted
+    last_image_index: Option<u32>,
+    //set by request_capture, makes the next draw_frame fill in capture
+    #[cfg(feature = "textures")]
+    capture_requested: bool,
+    #[cfg(feature = "textures")]
+    capture: Option<Capture>,
+    //hold on to the window as we need to make sure it is not dropped under any
+    //circumstances until we drop this Arc. None for headless contexts
+    window: Option<Arc<Window>>,
+    //windows added with add_surface. SurfaceId(i + 1) is at i, and removed
+    //ones leave a None behind so ids stay stable
+    window_targets: Vec<Option<WindowTarget>>,
+}
+
+//What headless contexts render to. Byte order matches what image encoders
+//expect so frames can be read back and saved as is
+#[cfg(feature = "allocator")]
+const HEADLESS_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//How long each phase of RenderContext::new took. Handy for chasing down
+//slow startup on particular drivers
+#[derive(Debug, Clone, Copy)]
+pub struct CreationTimings {
+    //loading the vulkan lib, checking layers/extensions and creating the
+    //instance itself
+    pub instance: Duration,
+    pub surface: Duration,
+    pub device_selection: Duration,
+    pub device_creation: Duration,
+    pub swapchain_creation: Duration,
+}
+
+#[derive(Debug)]
+pub enum RenderContextError {
+    MissingExtension(Vec<String>),
+    MissingLayer(Vec<String>),
+    MissingExtensionAndLayer {
+        extensions: Vec<String>,
+        layers: Vec<String>,
+    },
+    UnableToLoadLib,
+    InstanceCreationFailed(VkError),
+    VersionQueryFailed(vk::Result),
+    ExtensionEnumerationFailed(vk::Result),
+    LayerEnumerationFailed(vk::Result),
+    SurfaceCreationFailed(vk::Result),
+    SurfaceQueryFailed(vk::Result),
+    PhysicalDeviceEnumerationFailed(vk::Result),
+    NoSuitablePhysicalDevice,
+    //the device we'd have picked doesn't have these features the builder
+    //required, and no other device has them all either
+    MissingDeviceFeature(Vec<String>),
+    //the device we'd have picked has less DEVICE_LOCAL memory than the
+    //builder's minimum (in bytes), and so does every other device
+    InsufficientDeviceMemory(vk::DeviceSize),
+    //the selected device has no graphics family or nothing that can
+    //present to our surface
+    MissingQueueFamily,
+    DeviceCreationFailed(vk::Result),
+    SwapchainCreationFailed(vk::Result),
+    FrameResourceCreationFailed(VkError),
+    DrawFrameFailed(VkError),
+    InvalidSpirv(SpirvError),
+    ShaderFileReadFailed(io::Error),
+    ShaderModuleCreationFailed(vk::Result),
+    RenderPassCreationFailed(vk::Result),
+    FramebufferCreationFailed(vk::Result),
+    PipelineCreationFailed(vk::Result),
+    PipelineCacheCreationFailed(vk::Result),
+    QueryPoolCreationFailed(VkError),
+    //the device has neither vulkan 1.2 nor VK_KHR_timeline_semaphore
+    TimelineSemaphoresUnsupported,
+    //the device has neither vulkan 1.2 nor VK_EXT_descriptor_indexing with
+    //everything bindless textures need
+    #[cfg(feature = "bindless")]
+    DescriptorIndexingUnsupported,
+    SemaphoreCreationFailed(vk::Result),
+    //the graphics queue has no timestampValidBits
+    TimestampsUnsupported,
+    //headless contexts were made without the surface extensions so can't
+    //take on windows
+    SurfacesUnsupported,
+    //the present family the context picked can't present to this surface
+    SurfaceNotPresentable,
+    //an added window's swapchain didn't get the primary's format, so the
+    //context's render pass and pipelines can't draw to it
+    IncompatibleSurfaceFormat(vk::Format),
+    //the surface was removed or never added to this context
+    UnknownSurface(SurfaceId),
+    //the primary surface lives as long as the context
+    PrimarySurfaceNotRemovable,
+    #[cfg(feature = "hot-reload")]
+    ShaderWatchFailed(notify::Error),
+    //the push constant ranges given to a pipeline need more space than the
+    //device's maxPushConstantsSize
+    PushConstantsTooLarge {
+        size: u32,
+        max: u32,
+    },
+    DescriptorCreationFailed(vk::Result),
+    #[cfg(feature = "allocator")]
+    AllocatorCreationFailed(gpu_allocator::AllocationError),
+    #[cfg(feature = "allocator")]
+    AllocationFailed(gpu_allocator::AllocationError),
+    BufferCreationFailed(vk::Result),
+    //asked for a 0 byte buffer, e.g. by uploading an empty slice. Vulkan
+    //buffers can't be empty
+    EmptyBuffer,
+    ImageCreationFailed(vk::Result),
+    UploadFailed(VkError),
+    #[cfg(feature = "textures")]
+    TextureLoadFailed(image::ImageError),
+    //this face of a cubemap isn't square or doesn't share the first face's
+    //size and format
+    #[cfg(feature = "textures")]
+    MismatchedCubemapFace(usize),
+    #[cfg(feature = "gltf")]
+    ModelLoadFailed(gltf::Error),
+    //the model has no triangle lists in its scene
+    #[cfg(feature = "gltf")]
+    EmptyModel,
+    SamplerCreationFailed(vk::Result),
+    //capture_frame was called before anything was drawn, or on a windowed
+    //context without request_capture before the last frame
+    NothingToCapture,
+    //the surface doesn't let us copy out of swapchain images
+    CaptureUnsupported,
+    //captures only understand 8 bit RGBA and BGRA
+    UnsupportedCaptureFormat(vk::Format),
+    CaptureFailed(VkError),
+    //frames in flight has to be at least 1 and, for windowed contexts, no
+    //more than the swapchain has images
+    InvalidFramesInFlight {
+        requested: usize,
+        swapchain_images: Option<usize>,
+    },
+    //transition_image_layout doesn't know which accesses and stages go with
+    //one of these layouts
+    UnsupportedLayoutTransition {
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    },
+}
+
+impl fmt::Display for RenderContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RenderContextError::*;
+        match self {
+            MissingExtension(extensions) => write!(
+                f,
+                "missing instance extensions: {}",
+                extensions.join(", ")
+            ),
+            MissingLayer(layers) => {
+                write!(f, "missing instance layers: {}", layers.join(", "))
+            }
+            MissingExtensionAndLayer { extensions, layers } => write!(
+                f,
+                "missing instance extensions: {}; missing instance layers: {}",
+                extensions.join(", "),
+                layers.join(", ")
+            ),
+            UnableToLoadLib => write!(f, "unable to load the vulkan library"),
+            InstanceCreationFailed(e) => {
+                write!(f, "failed to create the vulkan instance: {}", e)
+            }
+            VersionQueryFailed(e) => {
+                write!(f, "failed to query the instance version: {}", e)
+            }
+            ExtensionEnumerationFailed(e) => {
+                write!(f, "failed to enumerate instance extensions: {}", e)
+            }
+            LayerEnumerationFailed(e) => {
+                write!(f, "failed to enumerate instance layers: {}", e)
+            }
+            SurfaceCreationFailed(e) => {
+                write!(f, "failed to create a surface: {}", e)
+            }
+            SurfaceQueryFailed(e) => {
+                write!(f, "failed to query a surface: {}", e)
+            }
+            PhysicalDeviceEnumerationFailed(e) => {
+                write!(f, "failed to enumerate physical devices: {}", e)
+            }
+            NoSuitablePhysicalDevice => {
+                write!(f, "no suitable physical device")
+            }
+            MissingDeviceFeature(features) => write!(
+                f,
+                "no device supports the required features: {}",
+                features.join(", ")
+            ),
+            InsufficientDeviceMemory(min) => write!(
+                f,
+                "no device has the required {} bytes of device local memory",
+                min
+            ),
+            MissingQueueFamily => write!(
+                f,
+                "the device has no graphics queue or can't present to the \
+                 surface"
+            ),
+            DeviceCreationFailed(e) => {
+                write!(f, "failed to create the logical device: {}", e)
+            }
+            SwapchainCreationFailed(e) => {
+                write!(f, "failed to create the swapchain: {}", e)
+            }
+            FrameResourceCreationFailed(e) => {
+                write!(f, "failed to create per frame resources: {}", e)
+            }
+            DrawFrameFailed(e) => write!(f, "failed to draw a frame: {}", e),
+            InvalidSpirv(e) => write!(f, "invalid SPIR-V: {}", e),
+            ShaderFileReadFailed(e) => {
+                write!(f, "failed to read a shader file: {}", e)
+            }
+            ShaderModuleCreationFailed(e) => {
+                write!(f, "failed to create a shader module: {}", e)
+            }
+            RenderPassCreationFailed(e) => {
+                write!(f, "failed to create a render pass: {}", e)
+            }
+            FramebufferCreationFailed(e) => {
+                write!(f, "failed to create framebuffers: {}", e)
+            }
+            PipelineCreationFailed(e) => {
+                write!(f, "failed to create a pipeline: {}", e)
+            }
+            PipelineCacheCreationFailed(e) => {
+                write!(f, "failed to create the pipeline cache: {}", e)
+            }
+            QueryPoolCreationFailed(e) => {
+                write!(f, "failed to create a query pool: {}", e)
+            }
+            TimelineSemaphoresUnsupported => write!(
+                f,
+                "the device supports neither vulkan 1.2 nor \
+                 VK_KHR_timeline_semaphore"
+            ),
+            #[cfg(feature = "bindless")]
+            DescriptorIndexingUnsupported => write!(
+                f,
+                "the device doesn't support the descriptor indexing \
+                 features bindless textures need"
+            ),
+            SemaphoreCreationFailed(e) => {
+                write!(f, "failed to create a semaphore: {}", e)
+            }
+            TimestampsUnsupported => {
+                write!(f, "the graphics queue can't write timestamps")
+            }
+            SurfacesUnsupported => {
+                write!(f, "headless contexts can't draw to windows")
+            }
+            SurfaceNotPresentable => {
+                write!(f, "the device can't present to the surface")
+            }
+            IncompatibleSurfaceFormat(format) => write!(
+                f,
+                "the surface's swapchain got format {:?}, which doesn't \
+                 match the primary surface",
+                format
+            ),
+            UnknownSurface(id) => {
+                write!(f, "surface {:?} was removed or never added", id)
+            }
+            PrimarySurfaceNotRemovable => {
+                write!(f, "the primary surface can't be removed")
+            }
+            #[cfg(feature = "hot-reload")]
+            ShaderWatchFailed(e) => {
+                write!(f, "failed to watch shader files: {}", e)
+            }
+            PushConstantsTooLarge { size, max } => write!(
+                f,
+                "push constants need {} bytes but the device allows {}",
+                size, max
+            ),
+            DescriptorCreationFailed(e) => {
+                write!(f, "failed to create descriptors: {}", e)
+            }
+            #[cfg(feature = "allocator")]
+            AllocatorCreationFailed(e) => {
+                write!(f, "failed to create the allocator: {}", e)
+            }
+            #[cfg(feature = "allocator")]
+            AllocationFailed(e) => {
+                write!(f, "failed to allocate memory: {}", e)
+            }
+            BufferCreationFailed(e) => {
+                write!(f, "failed to create a buffer: {}", e)
+            }
+            EmptyBuffer => write!(f, "can't upload an empty buffer"),
+            ImageCreationFailed(e) => {
+                write!(f, "failed to create an image: {}", e)
+            }
+            UploadFailed(e) => write!(f, "failed to upload data: {}", e),
+            #[cfg(feature = "textures")]
+            TextureLoadFailed(e) => {
+                write!(f, "failed to load a texture: {}", e)
+            }
+            #[cfg(feature = "textures")]
+            MismatchedCubemapFace(face) => write!(
+                f,
+                "cubemap face {} doesn't match the size and format of the \
+                others",
+                face
+            ),
+            #[cfg(feature = "gltf")]
+            ModelLoadFailed(e) => write!(f, "failed to load a model: {}", e),
+            #[cfg(feature = "gltf")]
+            EmptyModel => write!(f, "the model has no triangles to draw"),
+            SamplerCreationFailed(e) => {
+                write!(f, "failed to create a sampler: {}", e)
+            }
+            NothingToCapture => write!(f, "there's no frame to capture"),
+            CaptureUnsupported => {
+                write!(f, "the surface doesn't allow copying out of its images")
+            }
+            UnsupportedCaptureFormat(format) => {
+                write!(f, "can't capture images with format {:?}", format)
+            }
+            CaptureFailed(e) => write!(f, "failed to capture a frame: {}", e),
+            InvalidFramesInFlight {
+                requested,
+                swapchain_images: Some(images),
+            } => write!(
+                f,
+                "can't have {} frames in flight with {} swapchain images, it \
+                has to be between 1 and the image count",
+                requested, images
+            ),
+            InvalidFramesInFlight {
+                requested,
+                swapchain_images: None,
+            } => write!(
+                f,
+                "can't have {} frames in flight, there has to be at least 1",
+                requested
+            ),
+            UnsupportedLayoutTransition {
+                old_layout,
+                new_layout,
+            } => write!(
+                f,
+                "unsupported layout transition from {:?} to {:?}",
+                old_layout, new_layout
+            ),
+        }
+    }
+}
+
+impl Error for RenderContextError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use RenderContextError::*;
+        match self {
+            VersionQueryFailed(e)
+            | ExtensionEnumerationFailed(e)
+            | LayerEnumerationFailed(e)
+            | SurfaceCreationFailed(e)
+            | SurfaceQueryFailed(e)
+            | PhysicalDeviceEnumerationFailed(e)
+            | DeviceCreationFailed(e)
+            | SwapchainCreationFailed(e)
+            | ShaderModuleCreationFailed(e)
+            | RenderPassCreationFailed(e)
+            | FramebufferCreationFailed(e)
+            | PipelineCreationFailed(e)
+            | PipelineCacheCreationFailed(e)
+            | SemaphoreCreationFailed(e)
+            | DescriptorCreationFailed(e)
+            | BufferCreationFailed(e)
+            | ImageCreationFailed(e)
+            | SamplerCreationFailed(e) => Some(e),
+            InstanceCreationFailed(e)
+            | FrameResourceCreationFailed(e)
+            | DrawFrameFailed(e)
+            | QueryPoolCreationFailed(e)
+            | UploadFailed(e)
+            | CaptureFailed(e) => Some(e),
+            InvalidSpirv(e) => Some(e),
+            ShaderFileReadFailed(e) => Some(e),
+            #[cfg(feature = "hot-reload")]
+            ShaderWatchFailed(e) => Some(e),
+            #[cfg(feature = "allocator")]
+            AllocatorCreationFailed(e) | AllocationFailed(e) => Some(e),
+            #[cfg(feature = "textures")]
+            TextureLoadFailed(e) => Some(e),
+            #[cfg(feature = "gltf")]
+            ModelLoadFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+//A failed vulkan call, naming the call so errors that can come from
+//several places in one operation say which one it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VkError {
+    //the vulkan entry point, e.g. "vkQueueSubmit"
+    pub op: &'static str,
+    pub result: vk::Result,
+}
+
+impl VkError {
+    pub fn new(op: &'static str, result: vk::Result) -> VkError {
+        VkError { op, result }
+    }
+
+    //For map_err, wrapping the result in a VkError for op and that in
+    //variant, e.g. VkError::with_op("vkQueueSubmit", DrawFrameFailed)
+    pub(crate) fn with_op<E>(
+        op: &'static str,
+        variant: fn(VkError) -> E,
+    ) -> impl FnOnce(vk::Result) -> E {
+        move |result| variant(VkError::new(op, result))
+    }
+}
+
+impl fmt::Display for VkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed with {}", self.op, self.result)
+    }
+}
+
+impl Error for VkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.result)
+    }
+}
+
+//Configuration for creating a RenderContext. Anything not set falls back to
+//the defaults sufat itself uses
+pub struct RenderContextBuilder {
+    application_name: CString,
+    application_version: u32,
+    engine_name: CString,
+    //None means use whatever the loader says it supports
+    api_version: Option<u32>,
+    enable_validation: bool,
+    //None means derive them from log::max_level and pass on every type
+    debug_severity: Option<DebugUtilsMessageSeverityFlagsEXT>,
+    debug_message_types: Option<DebugUtilsMessageTypeFlagsEXT>,
+    //None means pick the best one we can find
+    physical_device_index: Option<usize>,
+    device_scorer: DeviceScorer,
+    //None lets the swapchain pick
+    present_mode: Option<vk::PresentModeKHR>,
+    prefer_srgb: bool,
+    loop_mode: LoopMode,
+    show_after_first_frame: bool,
+    frames_in_flight: usize,
+    //None keeps the pipeline cache in memory only
+    pipeline_cache_path: Option<PathBuf>,
+    clear_color: [f32; 4],
+    depth_buffer: bool,
+    clear_depth: f32,
+    msaa: vk::SampleCountFlags,
+    //devices without all of these are passed over
+    required_features: vk::PhysicalDeviceFeatures,
+    //devices with less DEVICE_LOCAL memory than this are passed over
+    min_device_local_memory: Option<vk::DeviceSize>,
+    //instance extensions on top of what the window and validation need.
+    //Missing required ones fail creation, missing optional ones are skipped
+    required_extensions: Vec<CString>,
+    optional_extensions: Vec<CString>,
+}
+
+impl Default for RenderContextBuilder {
+    fn default() -> Self {
+        RenderContextBuilder {
+            application_name: CString::from(cstr!("sufat")),
+            application_version: 0,
+            engine_name: CString::from(cstr!("Rageware")),
+            api_version: None,
+            //validation is expensive and needs the SDK installed so only turn
+            //it on by default for debug builds
+            enable_validation: cfg!(all(
+                feature = "validation",
+                debug_assertions
+            )),
+            debug_severity: None,
+            debug_message_types: None,
+            physical_device_index: None,
+            device_scorer: Box::new(default_device_scorer),
+            present_mode: None,
+            prefer_srgb: true,
+            loop_mode: LoopMode::Poll,
+            show_after_first_frame: true,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            pipeline_cache_path: None,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            depth_buffer: true,
+            clear_depth: 1.0,
+            msaa: vk::SampleCountFlags::TYPE_1,
+            required_features: vk::PhysicalDeviceFeatures::default(),
+            min_device_local_memory: None,
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+        }
+    }
+}
+
+//vulkan wants null terminated strings so anything after an interior nul
+//would be cut off anyways. Do it ourselves and complain about it
+fn to_cstring(s: &str) -> CString {
+    match CString::new(s) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("{:?} contains a nul byte, truncating", s);
+            let nul_position = e.nul_position();
+            let mut bytes = e.into_vec();
+            bytes.truncate(nul_position);
+            //SAFETY: we just cut the vec off at the first nul
+            unsafe { CString::from_vec_unchecked(bytes) }
+        }
+    }
+}
+
+//Add name to a list of extensions we're about to enable unless it's already
+//in there
+fn push_extension(extensions: &mut Vec<*const i8>, name: &CStr) {
+    //SAFETY: every pointer in the list comes from a null terminated string
+    //that outlives it
+    let already_enabled = extensions
+        .iter()
+        .any(|&extension| unsafe { CStr::from_ptr(extension) } == name);
+    if !already_enabled {
+        extensions.push(name.as_ptr());
+    }
+}
+
+impl RenderContextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn application_name(mut self, name: &str) -> Self {
+        self.application_name = to_cstring(name);
+        self
+    }
+
+    pub fn application_version(mut self, version: u32) -> Self {
+        self.application_version = version;
+        self
+    }
+
+    pub fn engine_name(mut self, name: &str) -> Self {
+        self.engine_name = to_cstring(name);
+        self
+    }
+
+    //Expects a version made with vk::make_api_version
+    pub fn api_version(mut self, version: u32) -> Self {
+        self.api_version = Some(version);
+        self
+    }
+
+    //Enable an instance extension on top of the ones sufat needs. Creation
+    //fails with MissingExtension if it isn't available
+    pub fn required_extension(mut self, name: &CStr) -> Self {
+        self.required_extensions.push(name.to_owned());
+        self
+    }
+
+    //Enable an instance extension if it's available and carry on without it
+    //if it isn't. Check RenderContext::enabled_extensions to see if it was
+    pub fn optional_extension(mut self, name: &CStr) -> Self {
+        self.optional_extensions.push(name.to_owned());
+        self
+    }
+
+    //Only use devices with the features enable turns on, and enable them on
+    //the device. Can be called more than once, e.g.
+    //.require_feature(|f| f.sampler_anisotropy = vk::TRUE)
+    pub fn require_feature(
+        mut self,
+        enable: fn(&mut vk::PhysicalDeviceFeatures),
+    ) -> Self {
+        enable(&mut self.required_features);
+        self
+    }
+
+    //Only use devices whose DEVICE_LOCAL heaps add up to at least this many
+    //bytes, for enforcing a minimum spec. Creation fails with
+    //InsufficientDeviceMemory if no device has enough
+    pub fn min_device_local_memory(mut self, bytes: vk::DeviceSize) -> Self {
+        self.min_device_local_memory = Some(bytes);
+        self
+    }
+
+    //Request the khronos validation layer and debug messenger. Does nothing
+    //if sufat was built without the validation feature
+    pub fn enable_validation(mut self, enable: bool) -> Self {
+        if enable && !cfg!(feature = "validation") {
+            log::warn!(
+                "validation requested but sufat was built without the \
+                validation feature"
+            );
+            return self;
+        }
+        self.enable_validation = enable;
+        self
+    }
+
+    //Which severities the debug messenger passes on to the log. By default
+    //that's whatever log::max_level lets through when the context is made.
+    //ERROR has to be included for validation_error_count to see anything
+    pub fn debug_severity(
+        mut self,
+        severity: DebugUtilsMessageSeverityFlagsEXT,
+    ) -> Self {
+        self.debug_severity = Some(severity);
+        self
+    }
+
+    //Which kinds of debug message get passed on. Defaults to all of them
+    pub fn debug_message_types(
+        mut self,
+        message_types: DebugUtilsMessageTypeFlagsEXT,
+    ) -> Self {
+        self.debug_message_types = Some(message_types);
+        self
+    }
+
+    //Use the device at this position in the list from
+    //RenderContext::enumerate_physical_devices instead of picking one
+    pub fn physical_device_index(mut self, index: usize) -> Self {
+        self.physical_device_index = Some(index);
+        self
+    }
+
+    //Decide which GPU gets picked. Return None to reject a device, otherwise
+    //the highest score wins. Ignored if physical_device_index is set
+    pub fn device_scorer(mut self, scorer: DeviceScorer) -> Self {
+        self.device_scorer = scorer;
+        self
+    }
+
+    //Ask for a specific present mode, e.g. FIFO to force vsync. Falls back
+    //to the default choice if the surface doesn't support it
+    pub fn present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.present_mode = Some(present_mode);
+        self
+    }
+
+    //Whether the swapchain should be sRGB, which gamma encodes what shaders
+    //write, or UNORM for shaders that do it themselves. Defaults to sRGB.
+    //If the surface has neither the first format it offers is used.
+    //Headless targets are always sRGB
+    pub fn prefer_srgb(mut self, prefer_srgb: bool) -> Self {
+        self.prefer_srgb = prefer_srgb;
+        self
+    }
+
+    //Whether event loops driving the context draw continuously or only on
+    //redraw requests. Defaults to Poll. See RenderContext::loop_mode
+    pub fn loop_mode(mut self, loop_mode: LoopMode) -> Self {
+        self.loop_mode = loop_mode;
+        self
+    }
+
+    //Have RenderContext::run show the window once the first frame has been
+    //presented, so it never shows up blank. The window has to be built
+    //with with_visible(false) for this to do anything. When false run
+    //leaves the window's visibility alone. Defaults to true
+    pub fn show_after_first_frame(mut self, show: bool) -> Self {
+        self.show_after_first_frame = show;
+        self
+    }
+
+    //How many frames the CPU can get ahead of the GPU. More lets the CPU
+    //keep recording while the GPU catches up, so fewer stalls when frame
+    //times are uneven, at the cost of input taking that many more frames
+    //to show up on screen. 1 has the least latency but the CPU and GPU
+    //take turns. Every per frame resource, from command buffers and sync
+    //objects to uniform buffer copies, is made this many times. Has to be
+    //between 1 and the swapchain's image count or build fails with
+    //InvalidFramesInFlight. Defaults to 2
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    //Load the pipeline cache from path at startup and save it back there
+    //when the context is dropped, so pipelines built on earlier runs compile
+    //faster. Data from a different device or driver is ignored
+    pub fn pipeline_cache_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.pipeline_cache_path = Some(path.into());
+        self
+    }
+
+    //What the swapchain gets cleared to at the start of every frame. RGBA,
+    //defaults to opaque black
+    pub fn clear_color(mut self, clear_color: [f32; 4]) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    //Give the swapchain render pass a depth attachment so pipelines can
+    //depth test. On by default. Needs the allocator feature
+    pub fn depth_buffer(mut self, depth_buffer: bool) -> Self {
+        if depth_buffer && !cfg!(feature = "allocator") {
+            log::warn!(
+                "Depth buffer requested but sufat was built without the \
+                allocator feature so there won't be one"
+            );
+        }
+        self.depth_buffer = depth_buffer;
+        self
+    }
+
+    //What the depth buffer gets cleared to every frame. Defaults to 1.0, the
+    //far plane
+    pub fn clear_depth(mut self, clear_depth: f32) -> Self {
+        self.clear_depth = clear_depth;
+        self
+    }
+
+    //Samples per pixel to render the swapchain pass with. Lowered to the
+    //most the device supports if it can't do this many. Defaults to 1, i.e.
+    //no msaa. Needs the allocator feature
+    pub fn msaa(mut self, samples: vk::SampleCountFlags) -> Self {
+        if samples != vk::SampleCountFlags::TYPE_1
+            && !cfg!(feature = "allocator")
+        {
+            log::warn!(
+                "MSAA requested but sufat was built without the allocator \
+                feature so it will be off"
+            );
+        }
+        self.msaa = samples;
+        self
+    }
+
+    pub fn build(
+        self,
+        window: Arc<Window>,
+    ) -> Result<RenderContext, RenderContextError> {
+        RenderContext::from_builder(self, Some(window), vk::Extent2D::default())
+    }
+
+    //A context with no window or surface that draws every frame into a
+    //single width by height image instead of a swapchain. Nothing gets
+    //presented, read the image back with a transfer instead
+    #[cfg(feature = "allocator")]
+    pub fn build_headless(
+        self,
+        width: u32,
+        height: u32,
+    ) -> Result<RenderContext, RenderContextError> {
+        RenderContext::from_builder(
+            self,
+            None,
+            vk::Extent2D {
+                width: width.max(1),
+                height: height.max(1),
+            },
+        )
+    }
+}
+
+impl RenderContext {
+    pub fn new(
+        window: Arc<Window>,
+    ) -> Result<RenderContext, RenderContextError> {
+        RenderContextBuilder::new().build(window)
+    }
+
+    //See RenderContextBuilder::build_headless
+    #[cfg(feature = "allocator")]
+    pub fn new_headless(
+        width: u32,
+        height: u32,
+    ) -> Result<RenderContext, RenderContextError> {
+        RenderContextBuilder::new().build_headless(width, height)
+    }
+
+    //headless_extent is only used when there's no window
+    fn from_builder(
+        builder: RenderContextBuilder,
+        window: Option<Arc<Window>>,
+        headless_extent: vk::Extent2D,
+    ) -> Result<RenderContext, RenderContextError> {
+        //SAFETY: Admittedly not actually safe since someone can make a vulkan
+        //lib that on startup scribbles all over our memory or some nonsense but
+        //this is a game so. Whatever. Risk I can take.
+        let instance_start = Instant::now();
+        match unsafe { Entry::load() } {
+            Err(_) => Err(RenderContextError::UnableToLoadLib),
+
+            Ok(entry) => {
+                let vk_version = match builder.api_version {
+                    Some(api_version) => api_version,
+                    None => match entry
+                        .try_enumerate_instance_version()
+                        .map_err(RenderContextError::VersionQueryFailed)?
+                    {
+                        Some(version) => version,
+                        //only 1.0 loaders lack vkEnumerateInstanceVersion
+                        None => {
+                            log::warn!(
+                                "Vulkan loader predates 1.1, falling back to \
+                                 1.0. Timeline semaphores, dynamic rendering \
+                                 and memory budgets won't be available"
+                            );
+                            vk::make_api_version(0, 1, 0, 0)
+                        }
+                    },
+                };
+                log::info!(
+                    "Using Vulkan API version {}.{}.{}",
+                    vk::api_version_major(vk_version),
+                    vk::api_version_minor(vk_version),
+                    vk::api_version_patch(vk_version)
+                );
+
+                let app_info = ApplicationInfo::builder()
+                    .api_version(vk_version)
+                    .engine_name(&builder.engine_name)
+                    .engine_version(0)
+                    .application_name(&builder.application_name)
+                    .application_version(builder.application_version)
+                    .build();
+                let mut required_extensions = match &window {
+                    Some(window) => ash_window::enumerate_required_extensions(
+                        window.raw_display_handle(),
+                    )
+                    .map_err(RenderContextError::ExtensionEnumerationFailed)?
+                    .to_vec(),
+                    //nothing to present to so no surface extensions either
+                    None => Vec::new(),
+                };
+
+                let validation = builder.enable_validation;
+                for extension in &builder.required_extensions {
+                    push_extension(&mut required_extensions, extension);
+                }
+
+                let ext_props = entry
+                    .enumerate_instance_extension_properties(None)
+                    .map_err(RenderContextError::ExtensionEnumerationFailed)?;
+                //SAFETY: extension_name is always null terminated
+                let has_extension = |name: &CStr| {
+                    ext_props.iter().any(|ext_prop| unsafe {
+                        CStr::from_ptr(ext_prop.extension_name.as_ptr()) == name
+                    })
+                };
+                //the messenger and object names need this, but the
+                //validation layer still works without it so don't make it
+                //a reason to fail
+                let debug_utils =
+                    validation && has_extension(DebugUtils::name());
+                if debug_utils {
+                    push_extension(
+                        &mut required_extensions,
+                        DebugUtils::name(),
+                    );
+                } else if validation {
+                    log::warn!(
+                        "{:?} is missing, validation messages won't be \
+                         logged through sufat and objects won't be named",
+                        DebugUtils::name()
+                    );
+                }
+                //loaders that have this, like the one on macOS, hide
+                //portability subset devices such as MoltenVK unless we ask
+                //for them
+                let portability_enumeration =
+                    has_extension(vk::KhrPortabilityEnumerationFn::name());
+                if portability_enumeration {
+                    push_extension(
+                        &mut required_extensions,
+                        vk::KhrPortabilityEnumerationFn::name(),
+                    );
+                    //portability subset devices need this to be queried
+                    //and 1.0 instances don't have it built in
+                    if vk_version < vk::API_VERSION_1_1
+                        && has_extension(
+                            vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+                        )
+                    {
+                        push_extension(
+                            &mut required_extensions,
+                            vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+                        );
+                    }
+                } else if cfg!(target_vendor = "apple") {
+                    log::warn!(
+                        "{:?} is missing, MoltenVK devices may not show up",
+                        vk::KhrPortabilityEnumerationFn::name()
+                    );
+                }
+                for extension in &builder.optional_extensions {
+                    if has_extension(extension) {
+                        push_extension(&mut required_extensions, extension);
+                    } else {
+                        log::info!(
+                            "Optional extension {:?} isn't available",
+                            extension
+                        );
+                    }
+                }
+                let exts_missing: Vec<*const i8> = required_extensions
+                    .iter()
+                    .map(|needle_extension_name| {
+                        ext_props
+                            .iter()
+                            //SAFETY: Fine because all our strings are null
+                            //terminated
+                            .find_map(|ext_prop| unsafe {
+                                CStr::from_ptr(*needle_extension_name)
+                                    .cmp(CStr::from_ptr(
+                                        ext_prop.extension_name.as_ptr(),
+                                    ))
+                                    .is_eq()
+                                    .then_some(())
+                            })
+                            .ok_or(needle_extension_name)
+                    })
+                    .filter_map(|i| i.err().copied())
+                    .collect();
+
+                let exts_missing: Vec<String> = exts_missing
+                    .into_iter()
+                    .map(|ext| {
+                        //SAFETY: Fine because all these strings are null
+                        //terminated
+                        let ext = unsafe { CStr::from_ptr(ext) }
+                            .to_string_lossy()
+                            .into_owned();
+                        log::error!("Missing extension {:?}", ext);
+                        ext
+                    })
+                    .collect();
+
+                let debug_layer_names = if validation {
+                    vec![cstr!("VK_LAYER_KHRONOS_validation").as_ptr()]
+                } else {
+                    Vec::new()
+                };
+
+                let layer_props =
+                    entry
+                        .enumerate_instance_layer_properties()
+                        .map_err(RenderContextError::LayerEnumerationFailed)?;
+                let layers_missing: Vec<*const i8> = debug_layer_names
+                    .iter()
+                    .map(|needle_layer_name| {
+                        layer_props
+                            .iter()
+                            .find_map(|m| -> Option<()> {
+                                //SAFETY: Legal because m.layer_name is always
+                                //null terminated and we make our needle name
+                                //from cstr!
+                                unsafe {
+                                    CStr::from_ptr(*needle_layer_name)
+                                        .cmp(CStr::from_ptr(
+                                            m.layer_name.as_ptr(),
+                                        ))
+                                        .is_eq()
+                                        .then_some(())
+                                }
+                            })
+                            .ok_or(needle_layer_name)
+                    })
+                    .filter_map(|i| i.err().copied())
+                    .collect();
+
+                let layers_missing: Vec<String> = layers_missing
+                    .into_iter()
+                    .map(|missing_layer_name| {
+                        //SAFETY: We build these off of cstr! so we're fine
+                        let layer =
+                            unsafe { CStr::from_ptr(missing_layer_name) }
+                                .to_string_lossy()
+                                .into_owned();
+                        log::error!("Missing layer {:?}", layer);
+                        layer
+                    })
+                    .collect();
+                if !layers_missing.is_empty() && !exts_missing.is_empty() {
+                    Err(RenderContextError::MissingExtensionAndLayer {
+                        extensions: exts_missing,
+                        layers: layers_missing,
+                    })
+                } else if !layers_missing.is_empty() {
+                    Err(RenderContextError::MissingLayer(layers_missing))
+                } else if !exts_missing.is_empty() {
+                    Err(RenderContextError::MissingExtension(exts_missing))
+                } else {
+                    log::debug!("Successfully found all layers");
+
+                    //the persistent messenger can only be made once we
+                    //have an instance, so chain one in here to also get
+                    //messages from create_instance and destroy_instance
+                    let validation_errors = Box::new(AtomicU64::new(0));
+                    let debug_severity = builder
+                        .debug_severity
+                        .unwrap_or_else(default_debug_severity);
+                    let debug_message_types = builder
+                        .debug_message_types
+                        .unwrap_or_else(default_debug_message_types);
+                    let mut instance_debug_info = debug_messenger_create_info(
+                        debug_severity,
+                        debug_message_types,
+                        &validation_errors,
+                    );
+                    let instance_flags = if portability_enumeration {
+                        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+                    } else {
+                        vk::InstanceCreateFlags::empty()
+                    };
+                    let mut create_info = vk::InstanceCreateInfo::builder()
+                        .flags(instance_flags)
+                        .application_info(&app_info)
+                        .enabled_extension_names(&required_extensions)
+                        .enabled_layer_names(&debug_layer_names);
+                    if debug_utils {
+                        create_info =
+                            create_info.push_next(&mut instance_debug_info);
+                    }
+                    let create_info = create_info.build();
+
+                    //SAFETY: we constructed create_instance from a builder
+                    //using correct parameters so it should be correct too
+                    match unsafe { entry.create_instance(&create_info, None) } {
+                        Err(e) => {
+                            log::error!(
+                                "We got to creating an instance but it failed \
+                                with {}",
+                                e
+                            );
+                            Err(RenderContextError::InstanceCreationFailed(
+                                VkError::new("vkCreateInstance", e),
+                            ))
+                        }
+
+                        Ok(instance) => {
+                            log::info!("Successfully created instance");
+                            let instance_time = instance_start.elapsed();
+                            log::debug!(
+                                "instance creation took {:?}",
+                                instance_time
+                            );
+
+                            let (debug_utils_loader, debug_callback) =
+                                if debug_utils {
+                                    let debug_utils_loader =
+                                        DebugUtils::new(&entry, &instance);
+                                    let debug_callback = create_debug_messenger(
+                                        &debug_utils_loader,
+                                        debug_severity,
+                                        debug_message_types,
+                                        &validation_errors,
+                                    );
+                                    (Some(debug_utils_loader), debug_callback)
+                                } else {
+                                    (None, None)
+                                };
+
+                            //SAFETY: both lists are built from null
+                            //terminated strings
+                            let enabled_extensions = required_extensions
+                                .iter()
+                                .map(|ext| unsafe {
+                                    CStr::from_ptr(*ext)
+                                        .to_string_lossy()
+                                        .into_owned()
+                                })
+                                .collect();
+                            let enabled_layers = debug_layer_names
+                                .iter()
+                                .map(|layer| unsafe {
+                                    CStr::from_ptr(*layer)
+                                        .to_string_lossy()
+                                        .into_owned()
+                                })
+                                .collect();
+                            let surface_callbacks =
+                                Surface::new(&entry, &instance);
+                            let surface_start = Instant::now();
+                            //SAFETY: the window is kept alive by the Arc we
+                            //store alongside the surface
+                            let surface = match window
+                                .as_ref()
+                                .map(|window| unsafe {
+                                    ash_window::create_surface(
+                                        &entry,
+                                        &instance,
+                                        window.raw_display_handle(),
+                                        window.raw_window_handle(),
+                                        None,
+                                    )
+                                })
+                                .transpose()
+                            {
+                                Ok(surface) => surface,
+                                Err(e) => {
+                                    log::error!(
+                                        "Failed to create surface: {:?}",
+                                        e
+                                    );
+                                    //SAFETY: nothing else has been made from
+                                    //the instance yet so we can tear it down
+                                    unsafe {
+                                        if let (
+                                            Some(debug_utils_loader),
+                                            Some(debug_callback),
+                                        ) = (
+                                            &debug_utils_loader,
+                                            debug_callback,
+                                        ) {
+                                            debug_utils_loader
+                                                .destroy_debug_utils_messenger(
+                                                    debug_callback,
+                                                    None,
+                                                );
+                                        }
+                                        instance.destroy_instance(None);
+                                    }
+                                    return Err(
+                                        RenderContextError::SurfaceCreationFailed(
+                                            e,
+                                        ),
+                                    );
+                                }
+                            };
+                            let surface_time = surface_start.elapsed();
+                            log::debug!(
+                                "surface creation took {:?}",
+                                surface_time
+                            );
+                            let mut render_context = RenderContext {
+                                entry,
+                                instance,
+                                debug_callback,
+                                surface,
+                                debug_utils_loader,
+                                validation_errors,
+                                debug_severity,
+                                debug_message_types,
+                                window,
+                                surface_callbacks,
+                                rendering_paused: false,
+                                api_version: vk_version,
+                                enabled_layers,
+                                enabled_extensions,
+                                creation_timings: CreationTimings {
+                                    instance: instance_time,
+                                    surface: surface_time,
+                                    device_selection: Duration::ZERO,
+                                    device_creation: Duration::ZERO,
+                                    swapchain_creation: Duration::ZERO,
+                                },
+                                physical_device: vk::PhysicalDevice::null(),
+                                physical_device_info: None,
+                                queue_families: None,
+                                enabled_features: builder.required_features,
+                                device: None,
+                                memory_budget_enabled: false,
+                                timeline_fns: None,
+                                #[cfg(feature = "dynamic-rendering")]
+                                dynamic_rendering: None,
+                                #[cfg(feature = "bindless")]
+                                descriptor_indexing: false,
+                                pipeline_cache: None,
+                                #[cfg(feature = "hot-reload")]
+                                shader_watcher: None,
+                                graphics_queue: vk::Queue::null(),
+                                present_queue: vk::Queue::null(),
+                                compute_queue: vk::Queue::null(),
+                                transfer_queue: vk::Queue::null(),
+                                swapchain: None,
+                                #[cfg(feature = "allocator")]
+                                headless_target: None,
+                                render_pass: None,
+                                framebuffers: None,
+                                #[cfg(feature = "allocator")]
+                                depth_buffer: None,
+                                #[cfg(feature = "allocator")]
+                                msaa_target: None,
+                                frames: None,
+                                commands: None,
+                                secondary_pools: Vec::new(),
+                                frame_timestamps: None,
+                                last_frame_gpu_time_ms: None,
+                                frame_timeline: None,
+                                frames_submitted: 0,
+                                frame_timer: FrameTimer::default(),
+                                loop_mode: LoopMode::Poll,
+                                show_after_first_frame: true,
+                                #[cfg(feature = "allocator")]
+                                allocator: None,
+                                #[cfg(feature = "allocator")]
+                                upload_handoffs: SharedHandoffs::default(),
+                                preferred_present_mode: None,
+                                wireframe: false,
+                                last_image_index: None,
+                                window_targets: Vec::new(),
+                                #[cfg(feature = "textures")]
+                                capture_requested: false,
+                                #[cfg(feature = "textures")]
+                                capture: None,
+                            };
+                            //from here on anything that fails just drops
+                            //render_context which cleans up after itself
+                            render_context.select_physical_device(
+                                builder.physical_device_index,
+                                &builder.device_scorer,
+                                builder.min_device_local_memory,
+                            )?;
+                            render_context.create_device()?;
+                            render_context.create_pipeline_cache(
+                                builder.pipeline_cache_path,
+                            )?;
+                            #[cfg(feature = "allocator")]
+                            render_context.create_allocator()?;
+                            render_context.preferred_present_mode =
+                                builder.present_mode;
+                            render_context.loop_mode = builder.loop_mode;
+                            render_context.show_after_first_frame =
+                                builder.show_after_first_frame;
+                            render_context.create_render_target(
+                                headless_extent,
+                                builder.prefer_srgb,
+                            )?;
+                            render_context.create_render_pass(
+                                builder.clear_color,
+                                builder.depth_buffer,
+                                builder.clear_depth,
+                                builder.msaa,
+                            )?;
+                            render_context.create_attachments()?;
+                            render_context.create_framebuffers()?;
+                            render_context
+                                .create_frames(builder.frames_in_flight)?;
+                            Ok(render_context)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //Every GPU vulkan can see. Failures are logged and give an empty list
+    pub fn enumerate_physical_devices(&self) -> Vec<PhysicalDeviceInfo> {
+        physical_device::enumerate_physical_devices(
+            &self.instance,
+            self.present_target(),
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Failed to enumerate physical devices: {:?}", e);
+            Vec::new()
+        })
+    }
+
+    fn present_target(&self) -> PresentTarget<'_> {
+        self.surface
+            .map(|surface| (&self.surface_callbacks, surface))
+    }
+
+    fn select_physical_device(
+        &mut self,
+        requested_index: Option<usize>,
+        scorer: &dyn Fn(&PhysicalDeviceInfo) -> Option<u32>,
+        min_device_local_memory: Option<vk::DeviceSize>,
+    ) -> Result<(), RenderContextError> {
+        let selection_start = Instant::now();
+        let devices = physical_device::enumerate_physical_devices(
+            &self.instance,
+            self.present_target(),
+        )
+        .map_err(RenderContextError::PhysicalDeviceEnumerationFailed)?;
+        for device in &devices {
+            log::debug!(
+                "Found device {}: {} ({:?}), can present: {}, device local \
+                 memory: {} bytes",
+                device.index,
+                device.name,
+                device.device_type,
+                device.supports_present,
+                device.device_local_memory
+            );
+        }
+
+        //the environment wins over anything the code asked for so devices
+        //can be swapped without a rebuild
+        let env_request = std::env::var(physical_device::DEVICE_ENV_VAR).ok();
+        let (usable, lacking): (Vec<_>, Vec<_>) =
+            devices.iter().cloned().partition(|device| {
+                missing_features(&device.features, &self.enabled_features)
+                    .is_empty()
+            });
+        for device in &lacking {
+            log::debug!(
+                "Device {} lacks required features {:?}",
+                device.name,
+                missing_features(&device.features, &self.enabled_features)
+            );
+        }
+        let (usable, too_small): (Vec<_>, Vec<_>) =
+            usable.into_iter().partition(|device| {
+                has_device_local_memory(device, min_device_local_memory)
+            });
+        for device in &too_small {
+            log::debug!(
+                "Device {} only has {} bytes of device local memory",
+                device.name,
+                device.device_local_memory
+            );
+        }
+        let Some(device) = physical_device::choose_device(
+            &usable,
+            env_request.as_deref(),
+            requested_index,
+            scorer,
+        ) else {
+            //if we'd have had a device without the feature or memory
+            //requirements those are what the user needs to hear about
+            let Some(device) = physical_device::choose_device(
+                &devices,
+                env_request.as_deref(),
+                requested_index,
+                scorer,
+            ) else {
+                return Err(RenderContextError::NoSuitablePhysicalDevice);
+            };
+            let missing =
+                missing_features(&device.features, &self.enabled_features);
+            return Err(match min_device_local_memory {
+                Some(min) if missing.is_empty() => {
+                    RenderContextError::InsufficientDeviceMemory(min)
+                }
+                _ => RenderContextError::MissingDeviceFeature(
+                    missing.into_iter().map(String::from).collect(),
+                ),
+            });
+        };
+        log::info!("Selected device {}: {}", device.index, device.name);
+        let queue_families = QueueFamilies::find(
+            &self.instance,
+            self.present_target(),
+            device.handle,
+        )
+        .ok_or(RenderContextError::MissingQueueFamily)?;
+        log::debug!("Using queue families {:?}", queue_families);
+        self.queue_families = Some(queue_families);
+        self.physical_device = device.handle;
+        self.physical_device_info = Some(device.clone());
+        self.creation_timings.device_selection = selection_start.elapsed();
+        log::debug!(
+            "device selection took {:?}",
+            self.creation_timings.device_selection
+        );
+        Ok(())
+    }
+
+    fn create_device(&mut self) -> Result<(), RenderContextError> {
+        let creation_start = Instant::now();
+        let queue_families = *self.queue_families();
+        //one queue per family is all we need for now
+        let queue_priorities = [1.0f32];
+        let queue_create_infos: Vec<_> = queue_families
+            .unique_families()
+            .into_iter()
+            .map(|family| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(family)
+                    .queue_priorities(&queue_priorities)
+                    .build()
+            })
+            .collect();
+
+        //headless contexts never make a swapchain
+        let mut device_extensions = if self.surface.is_some() {
+            vec![khr::Swapchain::name().as_ptr()]
+        } else {
+            Vec::new()
+        };
+        //querying the budget goes through
+        //get_physical_device_memory_properties2 which needs 1.1
+        let memory_budget_enabled = self.api_version >= vk::API_VERSION_1_1
+            && self.physical_device_info().api_version >= vk::API_VERSION_1_1
+            && self.device_extension_supported(vk::ExtMemoryBudgetFn::name());
+        if memory_budget_enabled {
+            device_extensions.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+        //portability subset implementations like MoltenVK require us to
+        //enable this to acknowledge they don't do everything vulkan says
+        if self.device_extension_supported(vk::KhrPortabilitySubsetFn::name()) {
+            log::info!("Device is a portability subset implementation");
+            device_extensions.push(vk::KhrPortabilitySubsetFn::name().as_ptr());
+        }
+        let timeline_support = self.timeline_semaphore_support();
+        if timeline_support == Some(false) {
+            device_extensions.push(vk::KhrTimelineSemaphoreFn::name().as_ptr());
+        }
+        #[cfg(feature = "dynamic-rendering")]
+        let dynamic_rendering_support = self.dynamic_rendering_support();
+        #[cfg(feature = "dynamic-rendering")]
+        if dynamic_rendering_support == Some(false) {
+            device_extensions.push(khr::DynamicRendering::name().as_ptr());
+        }
+        #[cfg(feature = "dynamic-rendering")]
+        let mut dynamic_rendering_features =
+            vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+                .dynamic_rendering(true);
+        #[cfg(feature = "bindless")]
+        let descriptor_indexing_support = self.descriptor_indexing_support();
+        #[cfg(feature = "bindless")]
+        if descriptor_indexing_support == Some(false) {
+            device_extensions
+                .push(vk::ExtDescriptorIndexingFn::name().as_ptr());
+        }
+        #[cfg(feature = "bindless")]
+        let mut descriptor_indexing_features =
+            vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+                .shader_sampled_image_array_non_uniform_indexing(true)
+                .descriptor_binding_partially_bound(true)
+                .descriptor_binding_variable_descriptor_count(true)
+                .runtime_descriptor_array(true)
+                .descriptor_binding_sampled_image_update_after_bind(true)
+                .descriptor_binding_update_unused_while_pending(true);
+        let mut timeline_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+                .timeline_semaphore(true);
+        let mut create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_create_infos)
+            .enabled_extension_names(&device_extensions)
+            .enabled_features(&self.enabled_features);
+        if timeline_support.is_some() {
+            create_info = create_info.push_next(&mut timeline_features);
+        }
+        #[cfg(feature = "dynamic-rendering")]
+        if dynamic_rendering_support.is_some() {
+            create_info =
+                create_info.push_next(&mut dynamic_rendering_features);
+        }
+        #[cfg(feature = "bindless")]
+        if descriptor_indexing_support.is_some() {
+            create_info =
+                create_info.push_next(&mut descriptor_indexing_features);
+        }
+        let create_info = create_info.build();
+
+        //SAFETY: physical_device came from our instance and create_info was
+        //made with a builder
+        let device = unsafe {
+            self.instance.create_device(
+                self.physical_device,
+                &create_info,
+                None,
+            )
+        }
+        .map_err(RenderContextError::DeviceCreationFailed)?;
+
+        //SAFETY: we asked for one queue on each of these families above
+        unsafe {
+            self.graphics_queue =
+                device.get_device_queue(queue_families.graphics, 0);
+            self.present_queue =
+                device.get_device_queue(queue_families.present, 0);
+            self.compute_queue = match queue_families.compute {
+                Some(family) => device.get_device_queue(family, 0),
+                None => self.graphics_queue,
+            };
+            self.transfer_queue = match queue_families.transfer {
+                Some(family) => device.get_device_queue(family, 0),
+                None => self.graphics_queue,
+            };
+        }
+        self.timeline_fns = match timeline_support {
+            Some(true) => Some(TimelineFns::Core),
+            Some(false) => Some(TimelineFns::Khr(khr::TimelineSemaphore::new(
+                &self.instance,
+                &device,
+            ))),
+            None => None,
+        };
+        #[cfg(feature = "dynamic-rendering")]
+        {
+            self.dynamic_rendering = match dynamic_rendering_support {
+                Some(true) => Some(DynamicRenderingFns::Core),
+                Some(false) => Some(DynamicRenderingFns::Khr(
+                    khr::DynamicRendering::new(&self.instance, &device),
+                )),
+                None => None,
+            };
+        }
+        #[cfg(feature = "bindless")]
+        {
+            self.descriptor_indexing = descriptor_indexing_support.is_some();
+        }
+        self.device = Some(device);
+        self.memory_budget_enabled = memory_budget_enabled;
+        self.creation_timings.device_creation = creation_start.elapsed();
+        log::info!("Successfully created device");
+        log::debug!(
+            "device creation took {:?}",
+            self.creation_timings.device_creation
+        );
+        Ok(())
+    }
+
+    //Whether the selected device can do timeline semaphores. Some(true)
+    //when they're core, Some(false) when they need the extension enabled
+    fn timeline_semaphore_support(&self) -> Option<bool> {
+        self.promoted_feature_support(
+            vk::API_VERSION_1_2,
+            vk::KhrTimelineSemaphoreFn::name(),
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::default(),
+            |features| features.timeline_semaphore,
+        )
+    }
+
+    //Same deal for dynamic rendering. The extension builds on renderpass2
+    //and depth stencil resolve so we only use it where those are core
+    #[cfg(feature = "dynamic-rendering")]
+    fn dynamic_rendering_support(&self) -> Option<bool> {
+        let core = self.promoted_feature_support(
+            vk::API_VERSION_1_3,
+            khr::DynamicRendering::name(),
+            vk::PhysicalDeviceDynamicRenderingFeatures::default(),
+            |features| features.dynamic_rendering,
+        )?;
+        (core
+            || (self.api_version >= vk::API_VERSION_1_2
+                && self.physical_device_info().api_version
+                    >= vk::API_VERSION_1_2))
+            .then_some(core)
+    }
+
+    //Same deal for the descriptor indexing features BindlessTextureArray
+    //needs, which all have to be there
+    #[cfg(feature = "bindless")]
+    fn descriptor_indexing_support(&self) -> Option<bool> {
+        self.promoted_feature_support(
+            vk::API_VERSION_1_2,
+            vk::ExtDescriptorIndexingFn::name(),
+            vk::PhysicalDeviceDescriptorIndexingFeatures::default(),
+            |features| {
+                [
+                    features.shader_sampled_image_array_non_uniform_indexing,
+                    features.descriptor_binding_partially_bound,
+                    features.descriptor_binding_variable_descriptor_count,
+                    features.runtime_descriptor_array,
+                    features.descriptor_binding_sampled_image_update_after_bind,
+                    features.descriptor_binding_update_unused_while_pending,
+                ]
+                .iter()
+                .all(|&feature| feature == vk::TRUE)
+                    as vk::Bool32
+            },
+        )
+    }
+
+    //Whether the selected device has a feature that became core in
+    //core_version, asked for through features. Some(true) when both the
+    //instance and device are at least core_version, Some(false) when the
+    //extension it came from has to be enabled instead
+    fn promoted_feature_support<T: vk::ExtendsPhysicalDeviceFeatures2>(
+        &self,
+        core_version: u32,
+        extension: &CStr,
+        mut features: T,
+        enabled: impl FnOnce(&T) -> vk::Bool32,
+    ) -> Option<bool> {
+        //the feature query goes through get_physical_device_features2
+        //which needs 1.1
+        let device_version = self.physical_device_info().api_version;
+        if self.api_version < vk::API_VERSION_1_1
+            || device_version < vk::API_VERSION_1_1
+        {
+            return None;
+        }
+        let core =
+            self.api_version >= core_version && device_version >= core_version;
+        if !core && !self.device_extension_supported(extension) {
+            return None;
+        }
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut features)
+            .build();
+        //SAFETY: the physical device came from this instance, which is at
+        //least 1.1, and features outlives the call
+        unsafe {
+            self.instance.get_physical_device_features2(
+                self.physical_device,
+                &mut features2,
+            )
+        };
+        (enabled(&features) == vk::TRUE).then_some(core)
+    }
+
+    //Whether the selected physical device has the extension. Failing to
+    //ask counts as not having it
+    fn device_extension_supported(&self, name: &CStr) -> bool {
+        //SAFETY: the physical device came from this instance
+        match unsafe {
+            self.instance
+                .enumerate_device_extension_properties(self.physical_device)
+        } {
+            //SAFETY: extension_name is always null terminated
+            Ok(properties) => properties.iter().any(|property| unsafe {
+                CStr::from_ptr(property.extension_name.as_ptr()) == name
+            }),
+            Err(e) => {
+                log::warn!("Failed to enumerate device extensions: {:?}", e);
+                false
+            }
+        }
+    }
+
+    //Budget and usage of every memory heap, in the order the device lists
+    //them. Without VK_EXT_memory_budget the budget is the heap's size and
+    //usage is unknown
+    pub fn memory_budget(&self) -> Vec<HeapBudget> {
+        let mut budget_properties =
+            vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let memory_properties = if self.memory_budget_enabled {
+            let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+                .push_next(&mut budget_properties)
+                .build();
+            //SAFETY: the physical device came from this instance, both are
+            //at least 1.1 and budget_properties outlives the call
+            unsafe {
+                self.instance.get_physical_device_memory_properties2(
+                    self.physical_device,
+                    &mut properties,
+                )
+            };
+            properties.memory_properties
+        } else {
+            //SAFETY: the physical device came from this instance
+            unsafe {
+                self.instance
+                    .get_physical_device_memory_properties(self.physical_device)
+            }
+        };
+        memory_properties.memory_heaps
+            [..memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(i, heap)| {
+                if self.memory_budget_enabled {
+                    HeapBudget {
+                        flags: heap.flags,
+                        size: heap.size,
+                        budget: budget_properties.heap_budget[i],
+                        usage: Some(budget_properties.heap_usage[i]),
+                    }
+                } else {
+                    HeapBudget {
+                        flags: heap.flags,
+                        size: heap.size,
+                        budget: heap.size,
+                        usage: None,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn create_pipeline_cache(
+        &mut self,
+        path: Option<PathBuf>,
+    ) -> Result<(), RenderContextError> {
+        let pipeline_cache = PipelineCache::new(
+            self.device(),
+            self.physical_device_info(),
+            path,
+        )
+        .map_err(RenderContextError::PipelineCacheCreationFailed)?;
+        self.pipeline_cache = Some(pipeline_cache);
+        Ok(())
+    }
+
+    //Passed to every pipeline sufat builds
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        self.pipeline_cache
+            .as_ref()
+            .expect("pipeline cache is created while constructing the context")
+    }
+
+    #[cfg(feature = "allocator")]
+    fn create_allocator(&mut self) -> Result<(), RenderContextError> {
+        use gpu_allocator::{
+            vulkan::{Allocator, AllocatorCreateDesc},
+            AllocatorDebugSettings,
+        };
+        use std::sync::Mutex;
+        let allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: self.instance.clone(),
+            device: self.device().clone(),
+            physical_device: self.physical_device,
+            debug_settings: AllocatorDebugSettings {
+                log_memory_information: cfg!(debug_assertions),
+                log_leaks_on_shutdown: true,
+                ..Default::default()
+            },
+            buffer_device_address: false,
+        })
+        .map_err(RenderContextError::AllocatorCreationFailed)?;
+        self.allocator = Some(Arc::new(Mutex::new(allocator)));
+        Ok(())
+    }
+
+    #[cfg(feature = "allocator")]
+    fn allocator(&self) -> &SharedAllocator {
+        self.allocator
+            .as_ref()
+            .expect("allocator is created while constructing the context")
+    }
+
+    //A buffer with memory bound from the context's allocator. The memory is
+    //freed when the buffer is dropped. A size of 0 is an EmptyBuffer error
+    #[cfg(feature = "allocator")]
+    pub fn create_buffer(
+        &self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<Buffer, RenderContextError> {
+        Buffer::new(self.device(), self.allocator(), size, usage, location)
+    }
+
+    //An image with memory bound from the context's allocator. The memory is
+    //freed when the image is dropped
+    #[cfg(feature = "allocator")]
+    pub fn create_image(
+        &self,
+        create_info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<Image, RenderContextError> {
+        Image::new(self.device(), self.allocator(), create_info, location)
+    }
+
+    //Copy data into a new device local buffer by way of a host visible
+    //staging buffer. Blocks until the copy is done. Empty data is an
+    //EmptyBuffer error
+    #[cfg(feature = "allocator")]
+    pub fn create_vertex_buffer<T: Copy>(
+        &self,
+        data: &[T],
+    ) -> Result<VertexBuffer, RenderContextError> {
+        let buffer = self.upload_buffer(
+            data,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        )?;
+        self.set_object_name(buffer.handle(), "vertex buffer");
+        Ok(VertexBuffer {
+            buffer,
+            count: data.len() as u32,
+        })
+    }
+
+    //Same as create_vertex_buffer but for 32 bit indices
+    #[cfg(feature = "allocator")]
+    pub fn create_index_buffer(
+        &self,
+        indices: &[u32],
+    ) -> Result<IndexBuffer, RenderContextError> {
+        self.create_index_buffer_typed(indices, vk::IndexType::UINT32)
+    }
+
+    //16 bit indices. Half the bandwidth of 32 bit ones so prefer these for
+    //meshes with fewer than 65536 vertices
+    #[cfg(feature = "allocator")]
+    pub fn create_index_buffer_u16(
+        &self,
+        indices: &[u16],
+    ) -> Result<IndexBuffer, RenderContextError> {
+        self.create_index_buffer_typed(indices, vk::IndexType::UINT16)
+    }
+
+    #[cfg(feature = "allocator")]
+    fn create_index_buffer_typed<T: Copy>(
+        &self,
+        indices: &[T],
+        index_type: vk::IndexType,
+    ) -> Result<IndexBuffer, RenderContextError> {
+        let buffer = self.upload_buffer(
+            indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::INDEX_READ,
+        )?;
+        self.set_object_name(buffer.handle(), "index buffer");
+        Ok(IndexBuffer {
+            buffer,
+            count: indices.len() as u32,
+            index_type,
+        })
+    }
+
+    //One host visible copy of T per frame in flight, starting out as
+    //initial. Update it every frame with FrameRecorder::frame_index
+    #[cfg(feature = "allocator")]
+    pub fn create_uniform_buffer<T: Copy>(
+        &self,
+        initial: &T,
+    ) -> Result<UniformBuffer<T>, RenderContextError> {
+        if std::mem::size_of::<T>() == 0 {
+            return Err(RenderContextError::EmptyBuffer);
+        }
+        let buffers = (0..self.frames_in_flight())
+            .map(|frame_index| {
+                let mut buffer = self.create_buffer(
+                    std::mem::size_of::<T>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                )?;
+                self.set_object_name(
+                    buffer.handle(),
+                    &format!("uniform buffer {}", frame_index),
+                );
+                buffer.write(std::slice::from_ref(initial));
+                Ok(buffer)
+            })
+            .collect::<Result<Vec<_>, RenderContextError>>()?;
+        Ok(UniformBuffer {
+            buffers,
+            _marker: std::marker::PhantomData,
+        })
+    }
+
+    //A device local buffer starting out with commands, which compute
+    //shaders can overwrite. Commands with a first_instance other than 0
+    //need the drawIndirectFirstInstance feature, see
+    //RenderContextBuilder::require_feature
+    #[cfg(feature = "allocator")]
+    pub fn create_draw_indirect_buffer(
+        &self,
+        commands: &[vk::DrawIndexedIndirectCommand],
+    ) -> Result<DrawIndirectBuffer, RenderContextError> {
+        //catch this here rather than as a validation error at draw time
+        if commands.iter().any(|command| command.first_instance != 0)
+            && self.enabled_features.draw_indirect_first_instance != vk::TRUE
+        {
+            return Err(RenderContextError::MissingDeviceFeature(vec![
+                "draw_indirect_first_instance".to_string(),
+            ]));
+        }
+        let buffer = self.upload_buffer(
+            commands,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::PipelineStageFlags::DRAW_INDIRECT
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::INDIRECT_COMMAND_READ
+                | vk::AccessFlags::SHADER_READ
+                | vk::AccessFlags::SHADER_WRITE,
+        )?;
+        self.set_object_name(buffer.handle(), "draw indirect buffer");
+        Ok(DrawIndirectBuffer {
+            buffer,
+            count: commands.len() as u32,
+        })
+    }
+
+    //Room for capacity instances in every frame in flight's copy, all
+    //starting out empty. Zero sized instance types are an EmptyBuffer error
+    #[cfg(feature = "allocator")]
+    pub fn create_instance_buffer<T: Copy>(
+        &self,
+        capacity: u32,
+    ) -> Result<InstanceBuffer<T>, RenderContextError> {
+        if std::mem::size_of::<T>() == 0 {
+            return Err(RenderContextError::EmptyBuffer);
+        }
+        let buffers = (0..self.frames_in_flight())
+            .map(|frame_index| {
+                let buffer = self.create_buffer(
+                    (capacity.max(1) as usize * std::mem::size_of::<T>())
+                        as vk::DeviceSize,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                )?;
+                self.set_object_name(
+                    buffer.handle(),
+                    &format!("instance buffer {}", frame_index),
+                );
+                Ok(buffer)
+            })
+            .collect::<Result<Vec<_>, RenderContextError>>()?;
+        Ok(InstanceBuffer {
+            counts: vec![0; buffers.len()],
+            buffers,
+            capacity,
+            _marker: std::marker::PhantomData,
+        })
+    }
+
+    //A scratch arena with capacity bytes for every frame in flight. See
+    //FrameArena
+    #[cfg(feature = "allocator")]
+    pub fn create_frame_arena(
+        &self,
+        capacity: vk::DeviceSize,
+    ) -> Result<FrameArena, RenderContextError> {
+        let alignment = self
+            .min_uniform_buffer_offset_alignment()
+            .max(self.device_limits().min_storage_buffer_offset_alignment)
+            .max(1);
+        let buffers = (0..self.frames_in_flight())
+            .map(|frame_index| {
+                let buffer = self.create_buffer(
+                    capacity.max(1),
+                    vk::BufferUsageFlags::UNIFORM_BUFFER
+                        | vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::VERTEX_BUFFER
+                        | vk::BufferUsageFlags::INDEX_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                )?;
+                self.set_object_name(
+                    buffer.handle(),
+                    &format!("frame arena {}", frame_index),
+                );
+                Ok(buffer)
+            })
+            .collect::<Result<Vec<_>, RenderContextError>>()?;
+        Ok(FrameArena {
+            buffers,
+            capacity,
+            alignment,
+            frame_index: 0,
+            head: 0,
+        })
+    }
+
+    //An upload manager copying on the dedicated transfer queue if the
+    //device has one, otherwise the graphics queue
+    #[cfg(feature = "allocator")]
+    pub fn create_upload_manager(
+        &self,
+    ) -> Result<UploadManager, RenderContextError> {
+        let queue_families = self.queue_families();
+        UploadManager::new(
+            self.device(),
+            self.allocator(),
+            &self.upload_handoffs,
+            queue_families.transfer.unwrap_or(queue_families.graphics),
+            self.transfer_queue,
+            queue_families.graphics,
+            self.timeline_fns.as_ref(),
+        )
+        .map_err(RenderContextError::FrameResourceCreationFailed)
+    }
+
+    //Decode an image file and upload it as a mipmapped, sampled texture.
+    //Blocks until the upload is done
+    #[cfg(feature = "textures")]
+    pub fn load_texture<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Texture, RenderContextError> {
+        Texture::load(self, path.as_ref())
+    }
+
+    //Decode six square images of the same size and upload them as the
+    //faces of a cubemap, in +X, -X, +Y, -Y, +Z, -Z order. For skyboxes and
+    //environment maps. Blocks until the upload is done
+    #[cfg(feature = "textures")]
+    pub fn load_cubemap<P: AsRef<Path>>(
+        &self,
+        paths: [P; 6],
+    ) -> Result<Texture, RenderContextError> {
+        Texture::load_cubemap(self, paths.each_ref().map(|path| path.as_ref()))
+    }
+
+    //Load the triangle meshes in a glTF file's scene into one vertex and
+    //index buffer. Missing normals are generated flat and missing texture
+    //coordinates are zero. Textures aren't loaded, materials only point at
+    //them
+    #[cfg(feature = "gltf")]
+    pub fn load_model<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Model, RenderContextError> {
+        Model::load(self, path.as_ref())
+    }
+
+    //Staging upload shared by the typed buffer helpers. The copy is followed
+    //by a barrier making it visible to dst_access at dst_stage for anything
+    //submitted to the graphics queue afterwards
+    #[cfg(feature = "allocator")]
+    fn upload_buffer<T: Copy>(
+        &self,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> Result<Buffer, RenderContextError> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return Err(RenderContextError::EmptyBuffer);
+        }
+        let mut staging = self.create_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        self.set_object_name(staging.handle(), "staging buffer");
+        staging.write(data);
+        let buffer = self.create_buffer(
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+
+        let device = self.device();
+        //uploads go on the graphics queue so the buffer never has to change
+        //queue family ownership before it gets used for drawing
+        self.commands()
+            .submit_one_time(self.graphics_queue, |command_buffer| {
+                let region = vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size,
+                };
+                let barrier = vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer.handle())
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build();
+                //SAFETY: both buffers are alive and at least size bytes
+                unsafe {
+                    device.cmd_copy_buffer(
+                        command_buffer,
+                        staging.handle(),
+                        buffer.handle(),
+                        &[region],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        dst_stage,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[barrier],
+                        &[],
+                    );
+                }
+            })
+            .map_err(RenderContextError::UploadFailed)?;
+        //submit_one_time waited for the copy so the staging buffer is free to
+        //go when it drops here
+        Ok(buffer)
+    }
+
+    //Only for contexts with a window
+    fn surface_info(&self) -> SurfaceInfo<'_> {
+        let window_size = self
+            .window
+            .as_ref()
+            .expect("only windowed contexts have a surface")
+            .inner_size();
+        SurfaceInfo {
+            surface_callbacks: &self.surface_callbacks,
+            surface: self.windowed_surface(),
+            physical_device: self.physical_device,
+            queue_families: self.queue_families(),
+            window_extent: vk::Extent2D {
+                width: window_size.width,
+                height: window_size.height,
+            },
+        }
+    }
+
+    //The swapchain for windowed contexts, otherwise the headless target
+    fn create_render_target(
+        &mut self,
+        headless_extent: vk::Extent2D,
+        prefer_srgb: bool,
+    ) -> Result<(), RenderContextError> {
+        if self.window.is_some() {
+            return self.create_swapchain(prefer_srgb);
+        }
+        #[cfg(feature = "allocator")]
+        {
+            let creation_start = Instant::now();
+            let target = AttachmentImage::render_target(
+                self,
+                HEADLESS_FORMAT,
+                headless_extent,
+            )?;
+            self.set_object_name(target.image().handle(), "headless target");
+            self.headless_target = Some(target);
+            self.creation_timings.swapchain_creation = creation_start.elapsed();
+            log::debug!(
+                "headless target creation took {:?}",
+                self.creation_timings.swapchain_creation
+            );
+            Ok(())
+        }
+        #[cfg(not(feature = "allocator"))]
+        {
+            let _ = headless_extent;
+            unreachable!("headless contexts need the allocator feature")
+        }
+    }
+
+    //Format of the images we draw to every frame
+    fn target_format(&self) -> vk::Format {
+        #[cfg(feature = "allocator")]
+        if let Some(target) = &self.headless_target {
+            return target.image().format();
+        }
+        self.swapchain().format().format
+    }
+
+    //Size of the images we draw to every frame
+    pub fn target_extent(&self) -> vk::Extent2D {
+        #[cfg(feature = "allocator")]
+        if let Some(target) = &self.headless_target {
+            let extent = target.image().extent();
+            return vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            };
+        }
+        self.swapchain().extent()
+    }
+
+    //The image and view at image_index out of the ones we draw to
+    fn target_image(&self, image_index: u32) -> (vk::Image, vk::ImageView) {
+        #[cfg(feature = "allocator")]
+        if let Some(target) = &self.headless_target {
+            return (target.image().handle(), target.view());
+        }
+        let swapchain = self.swapchain();
+        (
+            swapchain.images()[image_index as usize],
+            swapchain.image_views()[image_index as usize],
+        )
+    }
+
+    fn target_views(&self) -> Vec<vk::ImageView> {
+        #[cfg(feature = "allocator")]
+        if let Some(target) = &self.headless_target {
+            return vec![target.view()];
+        }
+        self.swapchain().image_views().to_vec()
+    }
+
+    //The image a headless context draws to. After draw_frame it's left in
+    //TRANSFER_SRC_OPTIMAL ready to be copied out. None for windowed contexts
+    #[cfg(feature = "allocator")]
+    pub fn headless_target(&self) -> Option<&AttachmentImage> {
+        self.headless_target.as_ref()
+    }
+
+    fn create_swapchain(
+        &mut self,
+        prefer_srgb: bool,
+    ) -> Result<(), RenderContextError> {
+        let creation_start = Instant::now();
+        let swapchain = Swapchain::new(
+            &self.instance,
+            self.device(),
+            &self.surface_info(),
+            self.preferred_present_mode,
+            prefer_srgb,
+            vk::SwapchainKHR::null(),
+        )
+        .map_err(RenderContextError::SwapchainCreationFailed)?;
+        self.swapchain = Some(swapchain);
+        self.creation_timings.swapchain_creation = creation_start.elapsed();
+        self.name_swapchain_images();
+        log::debug!(
+            "swapchain creation took {:?}",
+            self.creation_timings.swapchain_creation
+        );
+        Ok(())
+    }
+
+    fn create_render_pass(
+        &mut self,
+        clear_color: [f32; 4],
+        depth_buffer: bool,
+        clear_depth: f32,
+        msaa: vk::SampleCountFlags,
+    ) -> Result<(), RenderContextError> {
+        let format = self.target_format();
+        //depth images come from the allocator so without it there's no depth
+        let depth_format = if depth_buffer && cfg!(feature = "allocator") {
+            let depth_format = render_pass::choose_depth_format(
+                &self.instance,
+                self.physical_device,
+            );
+            if depth_format.is_none() {
+                log::warn!("No usable depth format, rendering without depth");
+            }
+            depth_format
+        } else {
+            None
+        };
+        let samples = if cfg!(feature = "allocator") {
+            self.clamp_sample_count(msaa, depth_format.is_some())
+        } else {
+            vk::SampleCountFlags::TYPE_1
+        };
+        let depth =
+            depth_format.map(|depth_format| (depth_format, clear_depth));
+        #[cfg(feature = "dynamic-rendering")]
+        if self.dynamic_rendering.is_some() {
+            log::info!("Using dynamic rendering");
+            let final_layout = if self.swapchain.is_none() {
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            } else {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            };
+            self.render_pass = Some(RenderPass::dynamic(
+                self.device(),
+                format,
+                depth,
+                samples,
+                clear_color,
+                final_layout,
+            ));
+            return Ok(());
+        }
+        let render_pass = if self.swapchain.is_none() {
+            //headless frames get copied out rather than presented
+            RenderPass::offscreen(
+                self.device(),
+                format,
+                depth,
+                samples,
+                clear_color,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        } else if samples != vk::SampleCountFlags::TYPE_1 {
+            RenderPass::multisampled(
+                self.device(),
+                format,
+                depth,
+                samples,
+                clear_color,
+            )
+        } else {
+            match depth {
+                Some((depth_format, clear_depth)) => {
+                    RenderPass::color_and_depth(
+                        self.device(),
+                        format,
+                        depth_format,
+                        clear_color,
+                        clear_depth,
+                    )
+                }
+                None => {
+                    RenderPass::color_only(self.device(), format, clear_color)
+                }
+            }
+        }
+        .map_err(RenderContextError::RenderPassCreationFailed)?;
+        self.render_pass = Some(render_pass);
+        Ok(())
+    }
+
+    //The most samples up to requested that every attachment in the pass can
+    //have
+    fn clamp_sample_count(
+        &self,
+        requested: vk::SampleCountFlags,
+        depth: bool,
+    ) -> vk::SampleCountFlags {
+        let limits = self.device_limits();
+        let mut supported = limits.framebuffer_color_sample_counts;
+        if depth {
+            supported &= limits.framebuffer_depth_sample_counts;
+        }
+        let samples = [
+            vk::SampleCountFlags::TYPE_64,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|&samples| {
+            samples.as_raw() <= requested.as_raw()
+                && supported.contains(samples)
+        })
+        .unwrap_or(vk::SampleCountFlags::TYPE_1);
+        if samples != requested {
+            log::warn!(
+                "{:?} samples requested but using {:?}",
+                requested,
+                samples
+            );
+        }
+        samples
+    }
+
+    //Make the depth buffer and msaa target the render pass wants, sized to
+    //the swapchain or headless target
+    fn create_attachments(&mut self) -> Result<(), RenderContextError> {
+        #[cfg(feature = "allocator")]
+        {
+            let extent = self.target_extent();
+            let samples = self.render_pass().samples();
+            if let Some(depth_format) = self.render_pass().depth_format() {
+                let depth_buffer = AttachmentImage::depth(
+                    self,
+                    depth_format,
+                    extent,
+                    samples,
+                )?;
+                self.depth_buffer = Some(depth_buffer);
+            }
+            if samples != vk::SampleCountFlags::TYPE_1 {
+                let msaa_target = AttachmentImage::color(
+                    self,
+                    self.target_format(),
+                    extent,
+                    samples,
+                )?;
+                self.msaa_target = Some(msaa_target);
+            }
+        }
+        Ok(())
+    }
+
+    fn depth_view(&self) -> Option<vk::ImageView> {
+        #[cfg(feature = "allocator")]
+        return self.depth_buffer.as_ref().map(|depth| depth.view());
+        #[cfg(not(feature = "allocator"))]
+        None
+    }
+
+    fn msaa_view(&self) -> Option<vk::ImageView> {
+        #[cfg(feature = "allocator")]
+        return self.msaa_target.as_ref().map(|target| target.view());
+        #[cfg(not(feature = "allocator"))]
+        None
+    }
+
+    //Begin the context's pass on image_index of window, or of the primary
+    //target for None, through a render pass object or dynamic rendering
+    //depending on which kind of pass it is. contents says whether draws get
+    //recorded inline or in secondary command buffers
+    fn begin_target_pass(
+        &self,
+        window: Option<&WindowTarget>,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        contents: vk::SubpassContents,
+    ) {
+        let extent = self.draw_extent(window);
+        let render_pass = self.render_pass();
+        #[cfg(feature = "dynamic-rendering")]
+        if let Some(fns) = &self.dynamic_rendering {
+            #[cfg(feature = "allocator")]
+            let (msaa_target, depth_buffer) = match window {
+                Some(window) => (&window.msaa_target, &window.depth_buffer),
+                None => (&self.msaa_target, &self.depth_buffer),
+            };
+            #[cfg(feature = "allocator")]
+            let (msaa, depth) = (
+                msaa_target
+                    .as_ref()
+                    .map(|target| (target.image().handle(), target.view())),
+                depth_buffer
+                    .as_ref()
+                    .map(|depth| (depth.image().handle(), depth.view())),
+            );
+            #[cfg(not(feature = "allocator"))]
+            let (msaa, depth) = (None, None);
+            let targets = RenderingTargets {
+                color: self.draw_image(window, image_index),
+                msaa,
+                depth,
+            };
+            render_pass.begin_rendering(
+                fns,
+                command_buffer,
+                &targets,
+                extent,
+                contents,
+            );
+            return;
+        }
+        let framebuffers = match window {
+            Some(window) => window.framebuffers.as_ref(),
+            None => self.framebuffers.as_ref(),
+        };
+        let framebuffer = framebuffers
+            .expect("framebuffers are made along with the target")
+            .framebuffer(image_index);
+        let render_pass_begin = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass.handle())
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            })
+            .clear_values(render_pass.clear_values())
+            .build();
+        //SAFETY: the command buffer has been begun and the framebuffer was
+        //made for this render pass and the current target
+        unsafe {
+            self.device().cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin,
+                contents,
+            )
+        };
+    }
+
+    //End what begin_target_pass began, leaving image in the pass's final
+    //layout. Render pass objects do that part themselves
+    #[cfg_attr(not(feature = "dynamic-rendering"), allow(unused_variables))]
+    fn end_target_pass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+    ) {
+        #[cfg(feature = "dynamic-rendering")]
+        if let Some(fns) = &self.dynamic_rendering {
+            self.render_pass()
+                .end_rendering(fns, command_buffer, image)
+                .expect("dynamic passes only use final layouts we can enter");
+            return;
+        }
+        //SAFETY: begin_target_pass began the render pass
+        unsafe { self.device().cmd_end_render_pass(command_buffer) };
+    }
+
+    //Size of what draw_frame draws to for window, or for the primary target
+    //when it's None
+    fn draw_extent(&self, window: Option<&WindowTarget>) -> vk::Extent2D {
+        match window {
+            Some(window) => window.swapchain().extent(),
+            None => self.target_extent(),
+        }
+    }
+
+    //The image and view draw_frame draws to for window, or for the primary
+    //target when it's None
+    fn draw_image(
+        &self,
+        window: Option<&WindowTarget>,
+        image_index: u32,
+    ) -> (vk::Image, vk::ImageView) {
+        match window {
+            Some(window) => {
+                let swapchain = window.swapchain();
+                (
+                    swapchain.images()[image_index as usize],
+                    swapchain.image_views()[image_index as usize],
+                )
+            }
+            None => self.target_image(image_index),
+        }
+    }
+
+    fn create_framebuffers(&mut self) -> Result<(), RenderContextError> {
+        //dynamic passes draw straight to the image views
+        if self.render_pass().is_dynamic() {
+            return Ok(());
+        }
+        let framebuffers = Framebuffers::new(
+            self.device(),
+            self.render_pass(),
+            &self.target_views(),
+            self.target_extent(),
+            self.depth_view(),
+            self.msaa_view(),
+        )
+        .map_err(RenderContextError::FramebufferCreationFailed)?;
+        self.framebuffers = Some(framebuffers);
+        Ok(())
+    }
+
+    fn create_frames(
+        &mut self,
+        frames_in_flight: usize,
+    ) -> Result<(), RenderContextError> {
+        //more frames than images would have frames waiting on each other's
+        //images rather than being in flight
+        let swapchain_images = self
+            .swapchain
+            .as_ref()
+            .map(|swapchain| swapchain.images().len());
+        if frames_in_flight == 0
+            || swapchain_images.is_some_and(|images| frames_in_flight > images)
+        {
+            return Err(RenderContextError::InvalidFramesInFlight {
+                requested: frames_in_flight,
+                swapchain_images,
+            });
+        }
+        let commands = CommandManager::new(
+            self.device(),
+            self.queue_families().graphics,
+            frames_in_flight,
+        )
+        .map_err(RenderContextError::FrameResourceCreationFailed)?;
+        self.commands = Some(commands);
+        let frames = Frames::new(self.device(), frames_in_flight)
+            .map_err(RenderContextError::FrameResourceCreationFailed)?;
+        self.frames = Some(frames);
+        self.frame_timeline = match &self.timeline_fns {
+            Some(fns) => {
+                Some(TimelineSemaphore::new(self.device(), fns, 0).map_err(
+                    VkError::with_op(
+                        "vkCreateSemaphore",
+                        RenderContextError::FrameResourceCreationFailed,
+                    ),
+                )?)
+            }
+            None => None,
+        };
+        //frame timing is a nice to have so the context works without it
+        self.frame_timestamps = match self
+            .create_timestamp_query_pool(2 * frames_in_flight as u32)
+        {
+            Ok(timestamps) => Some(timestamps),
+            Err(RenderContextError::TimestampsUnsupported) => {
+                log::info!("Graphics queue can't write timestamps");
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+
+    //How many low bits of a timestamp the graphics queue fills in. 0 if it
+    //can't write timestamps at all
+    pub fn timestamp_valid_bits(&self) -> u32 {
+        //SAFETY: the physical device came from this instance
+        let families = unsafe {
+            self.instance.get_physical_device_queue_family_properties(
+                self.physical_device,
+            )
+        };
+        let valid_bits = families
+            .get(self.queue_families().graphics as usize)
+            .map_or(0, |family| family.timestamp_valid_bits);
+        //timestampComputeAndGraphics promises every graphics queue supports
+        //them so a driver reporting 0 anyway is lying to one of us
+        if valid_bits == 0
+            && self.device_limits().timestamp_compute_and_graphics == vk::TRUE
+        {
+            log::warn!(
+                "Device claims timestampComputeAndGraphics but the graphics \
+                family has no timestampValidBits"
+            );
+        }
+        valid_bits
+    }
+
+    //How many nanoseconds one timestamp tick takes
+    pub fn timestamp_period(&self) -> f32 {
+        self.device_limits().timestamp_period
+    }
+
+    //A pool of count timestamp queries for the graphics queue, already reset
+    //so they can be written straight away
+    pub fn create_timestamp_query_pool(
+        &self,
+        count: u32,
+    ) -> Result<QueryPool, RenderContextError> {
+        let valid_bits = self.timestamp_valid_bits();
+        if valid_bits == 0 {
+            return Err(RenderContextError::TimestampsUnsupported);
+        }
+        let pool = QueryPool::new(
+            self.device(),
+            vk::QueryType::TIMESTAMP,
+            count,
+            valid_bits,
+        )
+        .map_err(VkError::with_op(
+            "vkCreateQueryPool",
+            RenderContextError::QueryPoolCreationFailed,
+        ))?;
+        self.commands()
+            .submit_one_time(self.graphics_queue, |command_buffer| {
+                pool.reset(command_buffer, 0, count)
+            })
+            .map_err(RenderContextError::QueryPoolCreationFailed)?;
+        Ok(pool)
+    }
+
+    //queries_per_frame occlusion queries for each frame in flight, already
+    //reset. precise needs the occlusionQueryPrecise feature, see
+    //RenderContextBuilder::require_feature
+    pub fn create_occlusion_query_pool(
+        &self,
+        queries_per_frame: u32,
+        precise: bool,
+    ) -> Result<OcclusionQueryPool, RenderContextError> {
+        if precise && self.enabled_features.occlusion_query_precise != vk::TRUE
+        {
+            return Err(RenderContextError::MissingDeviceFeature(vec![
+                "occlusion_query_precise".to_string(),
+            ]));
+        }
+        let count = queries_per_frame * self.frames_in_flight() as u32;
+        let pool = QueryPool::new(
+            self.device(),
+            vk::QueryType::OCCLUSION,
+            count,
+            u64::BITS,
+        )
+        .map_err(VkError::with_op(
+            "vkCreateQueryPool",
+            RenderContextError::QueryPoolCreationFailed,
+        ))?;
+        self.commands()
+            .submit_one_time(self.graphics_queue, |command_buffer| {
+                pool.reset(command_buffer, 0, count)
+            })
+            .map_err(RenderContextError::QueryPoolCreationFailed)?;
+        self.set_object_name(pool.handle(), "occlusion queries");
+        Ok(OcclusionQueryPool::new(pool, queries_per_frame, precise))
+    }
+
+    //Milliseconds the GPU spent on the most recent frame it has finished,
+    //from the start of its command buffer to the end. This trails the frame
+    //being drawn by up to frames in flight frames. None until a frame has
+    //finished or if the graphics queue can't write timestamps
+    pub fn last_frame_gpu_time_ms(&self) -> Option<f64> {
+        self.last_frame_gpu_time_ms
+    }
+
+    //FPS and frame times of the primary surface, measured on the CPU around
+    //draw_frame
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_timer.stats()
+    }
+
+    //Acquire the next swapchain image, let record fill in this frame's
+    //command buffer, then submit it and present. Out of date or suboptimal
+    //swapchains get recreated and the frame is skipped. Headless contexts
+    //draw to their target and skip acquiring and presenting. Does nothing
+    //while rendering is paused
+    pub fn draw_frame<F>(&mut self, record: F) -> Result<(), RenderContextError>
+    where
+        F: FnOnce(&FrameRecorder, u32),
+    {
+        self.draw_frame_with_compute(|_| {}, record)
+    }
+
+    //draw_frame with a chance to dispatch compute work or draw to
+    //OffscreenTargets first. compute records into the same command buffer
+    //before the render pass begins, and anything it writes is made visible
+    //to the draws record makes
+    pub fn draw_frame_with_compute<C, F>(
+        &mut self,
+        compute: C,
+        record: F,
+    ) -> Result<(), RenderContextError>
+    where
+        C: FnOnce(&ComputeRecorder),
+        F: FnOnce(&FrameRecorder, u32),
+    {
+        if self.rendering_paused {
+            return Ok(());
+        }
+        self.draw_to(None, compute, vk::SubpassContents::INLINE, record)
+    }
+
+    //draw_frame with the pass recorded in parallel. record gets one
+    //SecondaryRecorder per worker, which can each go to their own thread,
+    //e.g. with std::thread::scope, and must be done recording by the time it
+    //returns. Everything they recorded is then executed in worker order.
+    //Each worker has its own command pools, kept around for the next call
+    pub fn draw_frame_parallel<F>(
+        &mut self,
+        workers: usize,
+        record: F,
+    ) -> Result<(), RenderContextError>
+    where
+        F: FnOnce(&mut [SecondaryRecorder], u32),
+    {
+        if self.rendering_paused {
+            return Ok(());
+        }
+        while self.secondary_pools.len() < workers {
+            let pool = SecondaryPool::new(
+                self.device(),
+                self.queue_families().graphics,
+                self.frames_in_flight(),
+            )
+            .map_err(RenderContextError::FrameResourceCreationFailed)?;
+            self.secondary_pools.push(pool);
+        }
+        let render_pass = self.render_pass();
+        let inheritance = PassInheritance {
+            render_pass: render_pass.handle(),
+            color_format: render_pass.color_format(),
+            depth_format: render_pass
+                .depth_format()
+                .unwrap_or(vk::Format::UNDEFINED),
+            samples: render_pass.samples(),
+        };
+        //taken out so the frame can borrow them while draw_to has self
+        let mut pools = std::mem::take(&mut self.secondary_pools);
+        let mut recorded = Ok(());
+        let drawn = self.draw_to(
+            None,
+            |_| {},
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            |frame, image_index| {
+                recorded = frame.record_secondaries(
+                    &mut pools[..workers],
+                    inheritance,
+                    image_index,
+                    record,
+                );
+            },
+        );
+        self.secondary_pools = pools;
+        drawn.and(recorded)
+    }
+
+    //draw_frame for one of the context's surfaces. Frame timing, captures
+    //and the frame timeline only follow the primary surface. Pausing
+    //rendering pauses every surface
+    pub fn draw_frame_to<F>(
+        &mut self,
+        id: SurfaceId,
+        record: F,
+    ) -> Result<(), RenderContextError>
+    where
+        F: FnOnce(&FrameRecorder, u32),
+    {
+        if id == SurfaceId::PRIMARY {
+            return self.draw_frame(record);
+        }
+        if self.rendering_paused {
+            return Ok(());
+        }
+        let mut target = self
+            .take_window_target(id)
+            .ok_or(RenderContextError::UnknownSurface(id))?;
+        let result = self.draw_to(
+            Some(&mut target),
+            |_| {},
+            vk::SubpassContents::INLINE,
+            record,
+        );
+        self.window_targets[id.0 - 1] = Some(target);
+        result
+    }
+
+    //The body of draw_frame, drawing to window or to the primary target
+    //for None
+    fn draw_to<C, F>(
+        &mut self,
+        mut window: Option<&mut WindowTarget>,
+        compute: C,
+        contents: vk::SubpassContents,
+        record: F,
+    ) -> Result<(), RenderContextError>
+    where
+        C: FnOnce(&ComputeRecorder),
+        F: FnOnce(&FrameRecorder, u32),
+    {
+        let primary = window.is_none();
+        let device = self.device();
+        let (frames, swapchain, commands) = match window.as_deref() {
+            Some(window) => {
+                (&window.frames, Some(window.swapchain()), &window.commands)
+            }
+            None => (
+                self.frames.as_ref().expect(
+                    "frames are created while constructing the context",
+                ),
+                self.swapchain.as_ref(),
+                self.commands.as_ref().expect(
+                    "commands are created while constructing the context",
+                ),
+            ),
+        };
+        let frame = frames.current();
+
+        //SAFETY: the fence is ours and alive. Waiting on it makes sure the
+        //GPU is done with this frame's command buffer before we reuse it
+        unsafe { device.wait_for_fences(&[frame.in_flight], true, u64::MAX) }
+            .map_err(VkError::with_op(
+            "vkWaitForFences",
+            RenderContextError::DrawFrameFailed,
+        ))?;
+        //headless contexts only ever have the one image to draw to
+        let (acquire_outcome, image_index) = match swapchain {
+            Some(swapchain) => {
+                //SAFETY: all the handles here are owned by us and alive
+                let acquire_result = unsafe {
+                    swapchain.loader().acquire_next_image(
+                        swapchain.handle(),
+                        u64::MAX,
+                        frame.image_available,
+                        vk::Fence::null(),
+                    )
+                };
+                let acquire_outcome = classify_present_result(
+                    present_result_code(&acquire_result, |&(_, suboptimal)| {
+                        suboptimal
+                    }),
+                );
+                match (acquire_outcome, acquire_result) {
+                    //suboptimal still gives us an image we can use, we'll
+                    //recreate after presenting
+                    (
+                        PresentOutcome::Ok | PresentOutcome::Suboptimal,
+                        Ok((i, _)),
+                    ) => (acquire_outcome, i),
+                    (PresentOutcome::Failed(e), _) => {
+                        return Err(RenderContextError::DrawFrameFailed(
+                            VkError::new("vkAcquireNextImageKHR", e),
+                        ))
+                    }
+                    _ => {
+                        match window {
+                            Some(window) => {
+                                self.recreate_window_target(window)?
+                            }
+                            None => self.recreate_swapchain()?,
+                        };
+                        return Ok(());
+                    }
+                }
+            }
+            None => (PresentOutcome::Ok, 0),
+        };
+
+        //from here on the acquire semaphore is signaled, so anything that
+        //fails before the submit has to hand the frame back first
+        let acquired = swapchain.is_some();
+        let abandon = |e| {
+            self.abandon_frame(frame, acquired);
+            e
+        };
+
+        #[cfg(feature = "textures")]
+        let capture = if primary && self.capture_requested {
+            Some(
+                Capture::new(self, self.target_extent(), self.target_format())
+                    .map_err(abandon)?,
+            )
+        } else {
+            None
+        };
+
+        //the fence wait above means the command buffer isn't in use
+        let command_buffer = commands
+            .begin_frame_commands(frames.current_index())
+            .map_err(RenderContextError::DrawFrameFailed)
+            .map_err(abandon)?;
+        //the fence wait above also means this frame's timestamps from last
+        //time around are done, so read them before reusing the queries
+        let first_timestamp = 2 * frames.current_index() as u32;
+        let mut gpu_time_ms = None;
+        //frame timestamps only follow the primary surface
+        let frame_timestamps = match primary {
+            true => self.frame_timestamps.as_ref(),
+            false => None,
+        };
+        if let Some(timestamps) = frame_timestamps {
+            match timestamps.results(first_timestamp, 2) {
+                Ok(Some(ticks)) => {
+                    gpu_time_ms = Some(
+                        timestamps.ticks_between(ticks[0], ticks[1]) as f64
+                            * self.timestamp_period() as f64
+                            / 1_000_000.0,
+                    )
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("Failed to read frame timestamps: {:?}", e)
+                }
+            }
+            timestamps.reset(command_buffer, first_timestamp, 2);
+            timestamps.write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                first_timestamp,
+            );
+        }
+
+        //uploads flushed since the last frame have to land before anything
+        //in this one reads them
+        let mut submission = Submission::default()
+            .command_buffer(command_buffer)
+            .fence(frame.in_flight);
+        if swapchain.is_some() {
+            submission = submission.wait(
+                frame.image_available,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            );
+        }
+        #[cfg(feature = "allocator")]
+        let mut upload_ids = Vec::new();
+        #[cfg(feature = "allocator")]
+        for handoff in lock_handoffs(&self.upload_handoffs)
+            .iter()
+            .filter(|handoff| handoff.consumed_by.is_none())
+        {
+            record_acquire(device, command_buffer, handoff);
+            //upload managers use timeline semaphores whenever we have them
+            submission = match self.timeline_fns {
+                Some(_) => submission.wait_timeline(
+                    handoff.semaphore,
+                    handoff.wait_value,
+                    handoff.wait_stage,
+                ),
+                None => submission.wait(handoff.semaphore, handoff.wait_stage),
+            };
+            upload_ids.push(handoff.id);
+        }
+
+        let compute_recorder = ComputeRecorder {
+            device,
+            command_buffer,
+            bound_layout: Cell::new(vk::PipelineLayout::null()),
+            dispatched: Cell::new(false),
+            frame_index: frames.current_index(),
+            wireframe: self.wireframe,
+            multi_draw_indirect: self.enabled_features.multi_draw_indirect
+                == vk::TRUE,
+            debug_utils: self.debug_utils_loader.as_ref(),
+        };
+        compute(&compute_recorder);
+        if compute_recorder.dispatched.get() {
+            compute_to_graphics_barrier(device, command_buffer);
+        }
+
+        let extent = self.draw_extent(window.as_deref());
+        self.begin_target_pass(
+            window.as_deref(),
+            command_buffer,
+            image_index,
+            contents,
+        );
+        let framebuffers = match window.as_deref() {
+            Some(window) => window.framebuffers.as_ref(),
+            None => self.framebuffers.as_ref(),
+        };
+        //dynamic passes have no framebuffers
+        let framebuffer = framebuffers
+            .map_or(vk::Framebuffer::null(), |framebuffers| {
+                framebuffers.framebuffer(image_index)
+            });
+        let (image, image_view) =
+            self.draw_image(window.as_deref(), image_index);
+        let recorder = FrameRecorder {
+            device,
+            command_buffer,
+            image,
+            image_view,
+            framebuffer,
+            bound_layout: Cell::new(vk::PipelineLayout::null()),
+            extent,
+            frame_index: frames.current_index(),
+            wireframe: self.wireframe,
+            multi_draw_indirect: self.enabled_features.multi_draw_indirect
+                == vk::TRUE,
+            debug_utils: self.debug_utils_loader.as_ref(),
+        };
+        //pipelines have a dynamic viewport and scissor by default so start
+        //them off covering the whole target. Secondary command buffers set
+        //their own, and nothing else may be recorded around them
+        if contents == vk::SubpassContents::INLINE {
+            recorder.reset_viewport();
+        }
+        record(&recorder, image_index);
+
+        //without a swapchain there's no present to signal
+        let render_finished = [frame.render_finished];
+        let frame_number = self.frames_submitted + 1;
+        if swapchain.is_some() {
+            submission = submission.signal(frame.render_finished);
+        }
+        let frame_timeline = match primary {
+            true => self.frame_timeline.as_ref(),
+            false => None,
+        };
+        if let Some(frame_timeline) = frame_timeline {
+            submission = submission
+                .signal_timeline(frame_timeline.handle(), frame_number);
+        }
+
+        self.end_target_pass(command_buffer, image);
+        #[cfg(feature = "textures")]
+        if let Some(capture) = &capture {
+            //the render pass leaves the image ready to present, or to be
+            //copied from for headless contexts
+            let layout = if swapchain.is_some() {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            } else {
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            };
+            capture
+                .record(device, command_buffer, image, layout)
+                .expect("captures only make supported layout transitions");
+        }
+        if let Some(timestamps) = frame_timestamps {
+            timestamps.write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                first_timestamp + 1,
+            );
+        }
+        //SAFETY: the command buffer was begun above. The fence is only reset
+        //once nothing but the submit itself can fail
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .map_err(VkError::with_op(
+                    "vkEndCommandBuffer",
+                    RenderContextError::DrawFrameFailed,
+                ))
+                .map_err(abandon)?;
+            device
+                .reset_fences(&[frame.in_flight])
+                .map_err(VkError::with_op(
+                    "vkResetFences",
+                    RenderContextError::DrawFrameFailed,
+                ))
+                .map_err(abandon)?;
+        }
+        submission
+            .submit(device, self.graphics_queue)
+            .map_err(RenderContextError::DrawFrameFailed)
+            .map_err(abandon)?;
+        //the semaphores have been waited on now so the upload managers can
+        //free them once this frame is done
+        #[cfg(feature = "allocator")]
+        for handoff in lock_handoffs(&self.upload_handoffs)
+            .iter_mut()
+            .filter(|handoff| upload_ids.contains(&handoff.id))
+        {
+            handoff.consumed_by = Some(frame.in_flight);
+        }
+        let present_result = match swapchain {
+            Some(swapchain) => {
+                let swapchains = [swapchain.handle()];
+                let image_indices = [image_index];
+                let present_info = vk::PresentInfoKHR::builder()
+                    .wait_semaphores(&render_finished)
+                    .swapchains(&swapchains)
+                    .image_indices(&image_indices)
+                    .build();
+                //SAFETY: everything referenced by present_info lives until
+                //the end of this function
+                unsafe {
+                    swapchain
+                        .loader()
+                        .queue_present(self.present_queue, &present_info)
+                }
+            }
+            None => Ok(false),
+        };
+
+        match window.as_deref_mut() {
+            Some(window) => window.frames.advance(),
+            None => {
+                self.frames
+                    .as_mut()
+                    .expect("frames are created while constructing the context")
+                    .advance();
+                self.last_image_index = Some(image_index);
+                self.frames_submitted = frame_number;
+                self.frame_timer.frame_done();
+                if gpu_time_ms.is_some() {
+                    self.last_frame_gpu_time_ms = gpu_time_ms;
+                }
+            }
+        }
+        #[cfg(feature = "textures")]
+        if capture.is_some() {
+            self.capture = capture;
+            self.capture_requested = false;
+        }
+
+        let present_outcome = classify_present_result(present_result_code(
+            &present_result,
+            |&suboptimal| suboptimal,
+        ));
+        match (acquire_outcome, present_outcome) {
+            (_, PresentOutcome::Failed(e)) => {
+                Err(RenderContextError::DrawFrameFailed(VkError::new(
+                    "vkQueuePresentKHR",
+                    e,
+                )))
+            }
+            (PresentOutcome::Ok, PresentOutcome::Ok) => Ok(()),
+            _ => {
+                match window {
+                    Some(window) => self.recreate_window_target(window)?,
+                    None => self.recreate_swapchain()?,
+                };
+                Ok(())
+            }
+        }
+    }
+
+    //Puts a frame that draw_to gave up on after acquiring back the way the
+    //next draw_frame expects it: the acquire semaphore waited on and the
+    //fence signaled. An empty submit does both
+    fn abandon_frame(&self, frame: &FrameSync, acquired: bool) {
+        let device = self.device();
+        let mut submission = Submission::default().fence(frame.in_flight);
+        if acquired {
+            submission = submission.wait(
+                frame.image_available,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            );
+        }
+        //SAFETY: the fence isn't in use, either it's still signaled from the
+        //wait in draw_to or the submit meant to signal it failed
+        let result = unsafe { device.reset_fences(&[frame.in_flight]) }
+            .map_err(|e| VkError::new("vkResetFences", e))
+            .and_then(|_| submission.submit(device, self.graphics_queue));
+        if let Err(e) = result {
+            log::error!("Failed to recover from a failed frame: {:?}", e);
+        }
+    }
+
+    //Have the next draw_frame copy what it draws back to the CPU so
+    //capture_frame can hand it out. Windowed contexts need this since
+    //swapchain images can only be touched between acquiring and presenting
+    #[cfg(feature = "textures")]
+    pub fn request_capture(&mut self) -> Result<(), RenderContextError> {
+        if let Some(swapchain) = &self.swapchain {
+            if !swapchain
+                .usage()
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            {
+                return Err(RenderContextError::CaptureUnsupported);
+            }
+        }
+        self.capture_requested = true;
+        Ok(())
+    }
+
+    //Read a frame back as RGBA8, swizzling BGRA swapchains as needed. For
+    //windowed contexts that's the frame drawn after the last request_capture.
+    //Headless contexts without a pending capture just copy their target,
+    //which holds the last frame drawn. Waits for the GPU to go idle
+    #[cfg(feature = "textures")]
+    pub fn capture_frame(&mut self) -> Result<RgbaImage, RenderContextError> {
+        //a request that hasn't been drawn yet is used up either way, otherwise
+        //a failed capture would quietly tag along with a later frame
+        self.capture_requested = false;
+        //SAFETY: the device is alive
+        unsafe { self.device().device_wait_idle() }.map_err(
+            VkError::with_op(
+                "vkDeviceWaitIdle",
+                RenderContextError::CaptureFailed,
+            ),
+        )?;
+        if let Some(capture) = self.capture.take() {
+            return Ok(capture.to_rgba_image());
+        }
+        let image = match &self.headless_target {
+            Some(target) if self.last_image_index.is_some() => {
+                target.image().handle()
+            }
+            _ => return Err(RenderContextError::NothingToCapture),
+        };
+        let capture =
+            Capture::new(self, self.target_extent(), self.target_format())?;
+        let device = self.device();
+        let mut recorded = Ok(());
+        self.commands()
+            .submit_one_time(self.graphics_queue, |command_buffer| {
+                recorded = capture.record(
+                    device,
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                );
+            })
+            .map_err(RenderContextError::CaptureFailed)?;
+        recorded?;
+        Ok(capture.to_rgba_image())
+    }
+
+    pub fn load_shader_bytes(
+        &self,
+        bytes: &[u8],
+    ) -> Result<ShaderModule, RenderContextError> {
+        let words =
+            spirv_words(bytes).map_err(RenderContextError::InvalidSpirv)?;
+        ShaderModule::new(self.device(), &words)
+            .map_err(RenderContextError::ShaderModuleCreationFailed)
+    }
+
+    pub fn load_shader_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<ShaderModule, RenderContextError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| {
+            log::error!("Failed to read shader {}: {}", path.display(), e);
+            RenderContextError::ShaderFileReadFailed(e)
+        })?;
+        self.load_shader_bytes(&bytes).inspect_err(|e| {
+            log::error!("Failed to load shader {}: {:?}", path.display(), e)
+        })
+    }
+
+    //Start watching directory for changes to .spv files. Pipelines made
+    //with create_watched_pipeline from shaders in there get rebuilt by
+    //reload_shaders. Replaces any directory watched before, along with the
+    //pipelines registered with it
+    #[cfg(feature = "hot-reload")]
+    pub fn watch_shaders<P: AsRef<Path>>(
+        &mut self,
+        directory: P,
+    ) -> Result<(), RenderContextError> {
+        let watcher = ShaderWatcher::new(directory.as_ref())
+            .map_err(RenderContextError::ShaderWatchFailed)?;
+        self.shader_watcher = Some(watcher);
+        Ok(())
+    }
+
+    //Load both shaders, build a pipeline from them with recipe and keep
+    //track of it so reload_shaders can do the same again when either file
+    //changes. Call watch_shaders first
+    #[cfg(feature = "hot-reload")]
+    pub fn create_watched_pipeline<P: AsRef<Path>>(
+        &mut self,
+        vertex_path: P,
+        fragment_path: P,
+        recipe: PipelineRecipe,
+    ) -> Result<WatchedPipeline, RenderContextError> {
+        let vertex_path = vertex_path.as_ref();
+        let fragment_path = fragment_path.as_ref();
+        let vertex_shader = self.load_shader_file(vertex_path)?;
+        let fragment_shader = self.load_shader_file(fragment_path)?;
+        let pipeline = recipe(self, &vertex_shader, &fragment_shader)?;
+        Ok(self
+            .shader_watcher
+            .as_mut()
+            .expect("watch_shaders has to be called before watching pipelines")
+            .add(vertex_path, fragment_path, recipe, pipeline))
+    }
+
+    //Rebuild every watched pipeline whose shaders changed since the last
+    //call, waiting for the GPU to go idle first if there are any. Call it
+    //once a frame before draw_frame. Shaders that fail to load keep their
+    //old pipeline. Returns how many pipelines were rebuilt
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self) -> Result<usize, RenderContextError> {
+        let Some(mut watcher) = self.shader_watcher.take() else {
+            return Ok(0);
+        };
+        let mut result = Ok(0);
+        if watcher.poll() {
+            //SAFETY: the device is alive
+            result = unsafe { self.device().device_wait_idle() }
+                .map_err(VkError::with_op(
+                    "vkDeviceWaitIdle",
+                    RenderContextError::DrawFrameFailed,
+                ))
+                .map(|()| watcher.rebuild(self));
+        }
+        self.shader_watcher = Some(watcher);
+        result
+    }
+
+    //Rebuild the swapchain to match the window, e.g. after a resize. A
+    //minimized window has a zero sized surface which we can't make a
+    //swapchain for, so in that case nothing happens and we return false.
+    //Call this again once the window has a size. Headless contexts always
+    //return false
+    pub fn recreate_swapchain(&mut self) -> Result<bool, RenderContextError> {
+        let Some(window) = &self.window else {
+            log::debug!("headless contexts have no swapchain to recreate");
+            return Ok(false);
+        };
+        let window_size = window.inner_size();
+        if window_size.width == 0 || window_size.height == 0 {
+            log::debug!("window has no area, deferring swapchain recreation");
+            return Ok(false);
+        }
+        //the framebuffers point at the swapchain's image views and the depth
+        //buffer so they have to go first, and none of it can go while the GPU
+        //might be using it
+        //SAFETY: the device is alive
+        unsafe { self.device().device_wait_idle() }
+            .map_err(RenderContextError::SwapchainCreationFailed)?;
+        self.framebuffers = None;
+        #[cfg(feature = "allocator")]
+        {
+            self.depth_buffer = None;
+            self.msaa_target = None;
+        }
+        let mut swapchain = self
+            .swapchain
+            .take()
+            .expect("swapchain is created while constructing the context");
+        let result = swapchain.recreate(
+            &self.instance,
+            &self.surface_info(),
+            self.preferred_present_mode,
+        );
+        self.swapchain = Some(swapchain);
+        result.map_err(RenderContextError::SwapchainCreationFailed)?;
+        self.name_swapchain_images();
+        self.create_attachments()?;
+        self.create_framebuffers()?;
+        Ok(true)
+    }
+
+    //Switch between FIFO and the least synced present mode the surface
+    //has, recreating the swapchain if that changes anything. The choice
+    //sticks across later recreations. Headless contexts ignore this
+    pub fn set_vsync(&mut self, vsync: bool) -> Result<(), RenderContextError> {
+        let Some(swapchain) = &self.swapchain else {
+            log::debug!("headless contexts don't present so have no vsync");
+            return Ok(());
+        };
+        let present_mode = if vsync {
+            vk::PresentModeKHR::FIFO
+        } else {
+            unsynced_present_mode(&self.surface_present_modes()?)
+        };
+        self.preferred_present_mode = Some(present_mode);
+        if swapchain.present_mode() != present_mode {
+            log::info!("Switching present mode to {:?}", present_mode);
+            self.recreate_swapchain()?;
+        }
+        Ok(())
+    }
+
+    //Whether presenting waits for vertical blank. Always false for headless
+    //contexts
+    pub fn vsync(&self) -> bool {
+        self.swapchain.as_ref().is_some_and(|swapchain| {
+            swapchain.present_mode() == vk::PresentModeKHR::FIFO
+        })
+    }
+
+    //Panics for headless contexts, which don't have one
+    pub fn swapchain(&self) -> &Swapchain {
+        self.swapchain
+            .as_ref()
+            .expect("only windowed contexts have a swapchain")
+    }
+
+    //What the surface allows for image counts, extents, transforms and
+    //usage right now. The current extent follows the window so ask again
+    //after a resize. Panics for headless contexts
+    pub fn surface_capabilities(
+        &self,
+    ) -> Result<vk::SurfaceCapabilitiesKHR, RenderContextError> {
+        //SAFETY: the physical device and surface both came from our
+        //instance
+        unsafe {
+            self.surface_callbacks
+                .get_physical_device_surface_capabilities(
+                    self.physical_device,
+                    self.windowed_surface(),
+                )
+        }
+        .map_err(RenderContextError::SurfaceQueryFailed)
+    }
+
+    //Every format and color space the swapchain could use. Panics for
+    //headless contexts
+    pub fn surface_formats(
+        &self,
+    ) -> Result<Vec<vk::SurfaceFormatKHR>, RenderContextError> {
+        //SAFETY: the physical device and surface both came from our
+        //instance
+        unsafe {
+            self.surface_callbacks.get_physical_device_surface_formats(
+                self.physical_device,
+                self.windowed_surface(),
+            )
+        }
+        .map_err(RenderContextError::SurfaceQueryFailed)
+    }
+
+    //Every present mode the surface supports, for picking one to hand to
+    //the builder. FIFO is always among them. Panics for headless contexts
+    pub fn surface_present_modes(
+        &self,
+    ) -> Result<Vec<vk::PresentModeKHR>, RenderContextError> {
+        //SAFETY: the physical device and surface both came from our
+        //instance
+        unsafe {
+            self.surface_callbacks
+                .get_physical_device_surface_present_modes(
+                    self.physical_device,
+                    self.windowed_surface(),
+                )
+        }
+        .map_err(RenderContextError::SurfaceQueryFailed)
+    }
+
+    fn windowed_surface(&self) -> SurfaceKHR {
+        self.surface.expect("only windowed contexts have a surface")
+    }
+
+    //Start drawing to another window. It gets its own surface, swapchain
+    //and frames but shares the device, render pass and pipelines with
+    //everything else, so its swapchain has to end up with the same format
+    //as the primary one. Draw to it with draw_frame_to
+    pub fn add_surface(
+        &mut self,
+        window: Arc<Window>,
+    ) -> Result<SurfaceId, RenderContextError> {
+        if self.surface.is_none() {
+            return Err(RenderContextError::SurfacesUnsupported);
+        }
+        //SAFETY: the window is kept alive by the target for as long as the
+        //surface exists
+        let surface = unsafe {
+            ash_window::create_surface(
+                &self.entry,
+                &self.instance,
+                window.raw_display_handle(),
+                window.raw_window_handle(),
+                None,
+            )
+        }
+        .map_err(RenderContextError::SurfaceCreationFailed)?;
+        //the device was picked before this window existed so all we can do
+        //is check it can present here
+        //SAFETY: the physical device and surface both came from our
+        //instance
+        let presentable = unsafe {
+            self.surface_callbacks.get_physical_device_surface_support(
+                self.physical_device,
+                self.queue_families().present,
+                surface,
+            )
+        };
+        if !matches!(presentable, Ok(true)) {
+            //SAFETY: nothing has been made from the surface yet
+            unsafe { self.surface_callbacks.destroy_surface(surface, None) };
+            return Err(match presentable {
+                Err(e) => RenderContextError::SurfaceQueryFailed(e),
+                _ => RenderContextError::SurfaceNotPresentable,
+            });
+        }
+        let frames_in_flight = self.frames_in_flight();
+        //from here on dropping the target cleans up the surface
+        let mut target = WindowTarget {
+            swapchain: None,
+            framebuffers: None,
+            #[cfg(feature = "allocator")]
+            depth_buffer: None,
+            #[cfg(feature = "allocator")]
+            msaa_target: None,
+            frames: Frames::new(self.device(), frames_in_flight)
+                .map_err(RenderContextError::FrameResourceCreationFailed)?,
+            commands: CommandManager::new(
+                self.device(),
+                self.queue_families().graphics,
+                frames_in_flight,
+            )
+            .map_err(RenderContextError::FrameResourceCreationFailed)?,
+            surface,
+            surface_callbacks: self.surface_callbacks.clone(),
+            window,
+        };
+        let swapchain = Swapchain::new(
+            &self.instance,
+            self.device(),
+            &self.window_surface_info(&target),
+            self.preferred_present_mode,
+            self.swapchain().prefer_srgb(),
+            vk::SwapchainKHR::null(),
+        )
+        .map_err(RenderContextError::SwapchainCreationFailed)?;
+        let format = swapchain.format().format;
+        target.swapchain = Some(swapchain);
+        if format != self.target_format() {
+            return Err(RenderContextError::IncompatibleSurfaceFormat(format));
+        }
+        self.create_window_attachments(&mut target)?;
+        self.window_targets.push(Some(target));
+        Ok(SurfaceId(self.window_targets.len()))
+    }
+
+    //Stop drawing to a window added with add_surface, destroying its
+    //swapchain and surface. Does nothing if it was already removed. Fails
+    //for SurfaceId::PRIMARY, which lives as long as the context
+    pub fn remove_surface(
+        &mut self,
+        id: SurfaceId,
+    ) -> Result<(), RenderContextError> {
+        if id == SurfaceId::PRIMARY {
+            return Err(RenderContextError::PrimarySurfaceNotRemovable);
+        }
+        if self.window_target(id).is_none() {
+            return Ok(());
+        }
+        //SAFETY: the device is alive
+        unsafe { self.device().device_wait_idle() }.map_err(
+            VkError::with_op(
+                "vkDeviceWaitIdle",
+                RenderContextError::DrawFrameFailed,
+            ),
+        )?;
+        self.window_targets[id.0 - 1] = None;
+        Ok(())
+    }
+
+    //Which surface draws to the window with this id, handy for routing
+    //window events. None if the context doesn't draw to that window
+    pub fn surface_for_window(&self, window_id: WindowId) -> Option<SurfaceId> {
+        if self
+            .window
+            .as_ref()
+            .is_some_and(|window| window.id() == window_id)
+        {
+            return Some(SurfaceId::PRIMARY);
+        }
+        self.window_targets
+            .iter()
+            .position(|target| {
+                target
+                    .as_ref()
+                    .is_some_and(|target| target.window.id() == window_id)
+            })
+            .map(|i| SurfaceId(i + 1))
+    }
+
+    //Swapchain of one of the surfaces. Panics for headless contexts, and
+    //fails for surfaces that were removed
+    pub fn surface_swapchain(
+        &self,
+        id: SurfaceId,
+    ) -> Result<&Swapchain, RenderContextError> {
+        if id == SurfaceId::PRIMARY {
+            return Ok(self.swapchain());
+        }
+        self.window_target(id)
+            .map(WindowTarget::swapchain)
+            .ok_or(RenderContextError::UnknownSurface(id))
+    }
+
+    //recreate_swapchain for any surface, e.g. after one of the added
+    //windows is resized
+    pub fn recreate_surface_swapchain(
+        &mut self,
+        id: SurfaceId,
+    ) -> Result<bool, RenderContextError> {
+        if id == SurfaceId::PRIMARY {
+            return self.recreate_swapchain();
+        }
+        let mut target = self
+            .take_window_target(id)
+            .ok_or(RenderContextError::UnknownSurface(id))?;
+        let result = self.recreate_window_target(&mut target);
+        self.window_targets[id.0 - 1] = Some(target);
+        result
+    }
+
+    fn window_target(&self, id: SurfaceId) -> Option<&WindowTarget> {
+        self.window_targets.get(id.0.checked_sub(1)?)?.as_ref()
+    }
+
+    //Moved out while drawing or recreating so the rest of the context can
+    //still be borrowed, and put back after
+    fn take_window_target(&mut self, id: SurfaceId) -> Option<WindowTarget> {
+        self.window_targets.get_mut(id.0.checked_sub(1)?)?.take()
+    }
+
+    fn window_surface_info<'a>(
+        &'a self,
+        target: &WindowTarget,
+    ) -> SurfaceInfo<'a> {
+        let window_size = target.window.inner_size();
+        SurfaceInfo {
+            surface_callbacks: &self.surface_callbacks,
+            surface: target.surface,
+            physical_device: self.physical_device,
+            queue_families: self.queue_families(),
+            window_extent: vk::Extent2D {
+                width: window_size.width,
+                height: window_size.height,
+            },
+        }
+    }
+
+    //recreate_swapchain for a window added with add_surface
+    fn recreate_window_target(
+        &self,
+        target: &mut WindowTarget,
+    ) -> Result<bool, RenderContextError> {
+        let window_size = target.window.inner_size();
+        if window_size.width == 0 || window_size.height == 0 {
+            log::debug!("window has no area, deferring swapchain recreation");
+            return Ok(false);
+        }
+        //SAFETY: the device is alive
+        unsafe { self.device().device_wait_idle() }
+            .map_err(RenderContextError::SwapchainCreationFailed)?;
+        target.framebuffers = None;
+        #[cfg(feature = "allocator")]
+        {
+            target.depth_buffer = None;
+            target.msaa_target = None;
+        }
+        let mut swapchain = target
+            .swapchain
+            .take()
+            .expect("window targets always have a swapchain outside of drop");
+        let result = swapchain.recreate(
+            &self.instance,
+            &self.window_surface_info(target),
+            self.preferred_present_mode,
+        );
+        let format = swapchain.format().format;
+        target.swapchain = Some(swapchain);
+        result.map_err(RenderContextError::SwapchainCreationFailed)?;
+        if format != self.target_format() {
+            return Err(RenderContextError::IncompatibleSurfaceFormat(format));
+        }
+        self.create_window_attachments(target)?;
+        Ok(true)
+    }
+
+    //create_attachments and create_framebuffers for a window added with
+    //add_surface
+    fn create_window_attachments(
+        &self,
+        target: &mut WindowTarget,
+    ) -> Result<(), RenderContextError> {
+        let extent = target.swapchain().extent();
+        #[cfg(feature = "allocator")]
+        {
+            let samples = self.render_pass().samples();
+            if let Some(depth_format) = self.render_pass().depth_format() {
+                target.depth_buffer = Some(AttachmentImage::depth(
+                    self,
+                    depth_format,
+                    extent,
+                    samples,
+                )?);
+            }
+            if samples != vk::SampleCountFlags::TYPE_1 {
+                target.msaa_target = Some(AttachmentImage::color(
+                    self,
+                    self.target_format(),
+                    extent,
+                    samples,
+                )?);
+            }
+        }
+        //dynamic passes draw straight to the image views
+        if self.render_pass().is_dynamic() {
+            return Ok(());
+        }
+        #[cfg(feature = "allocator")]
+        let (depth_view, msaa_view) = (
+            target.depth_buffer.as_ref().map(|depth| depth.view()),
+            target.msaa_target.as_ref().map(|msaa| msaa.view()),
+        );
+        #[cfg(not(feature = "allocator"))]
+        let (depth_view, msaa_view) = (None, None);
+        let framebuffers = Framebuffers::new(
+            self.device(),
+            self.render_pass(),
+            target.swapchain().image_views(),
+            extent,
+            depth_view,
+            msaa_view,
+        )
+        .map_err(RenderContextError::FramebufferCreationFailed)?;
+        target.framebuffers = Some(framebuffers);
+        Ok(())
+    }
+
+    //The pass that draws to the swapchain. Pipelines that draw to the screen
+    //should be built against this
+    pub fn render_pass(&self) -> &RenderPass {
+        self.render_pass
+            .as_ref()
+            .expect("render pass is created while constructing the context")
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.render_pass
+            .as_mut()
+            .expect("render pass is created while constructing the context")
+            .set_clear_color(clear_color);
+    }
+
+    //How many frames the CPU can get ahead of the GPU, as chosen on the
+    //builder
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames
+            .as_ref()
+            .expect("frames are created while constructing the context")
+            .count()
+    }
+
+    //Whether BindlessTextureArrays can be made on this device
+    #[cfg(feature = "bindless")]
+    pub fn descriptor_indexing_supported(&self) -> bool {
+        self.descriptor_indexing
+    }
+
+    //Whether create_timeline_semaphore works on this device
+    pub fn timeline_semaphores_supported(&self) -> bool {
+        self.timeline_fns.is_some()
+    }
+
+    pub fn create_timeline_semaphore(
+        &self,
+        initial_value: u64,
+    ) -> Result<TimelineSemaphore, RenderContextError> {
+        let fns = self
+            .timeline_fns
+            .as_ref()
+            .ok_or(RenderContextError::TimelineSemaphoresUnsupported)?;
+        TimelineSemaphore::new(self.device(), fns, initial_value)
+            .map_err(RenderContextError::SemaphoreCreationFailed)
+    }
+
+    //How many frames draw_frame has submitted. Frame n is the nth submit,
+    //counting from 1
+    pub fn frames_submitted(&self) -> u64 {
+        self.frames_submitted
+    }
+
+    //The semaphore each frame's submit signals to its frame number, for
+    //other queues to wait on. None when timeline semaphores aren't
+    //supported
+    pub fn frame_timeline(&self) -> Option<&TimelineSemaphore> {
+        self.frame_timeline.as_ref()
+    }
+
+    //Block until the GPU is done with frame, or timeout nanoseconds pass.
+    //Ok(false) means it timed out, or that frame hasn't been submitted yet.
+    //Waits on the frame timeline when there is one, otherwise on the fence
+    //of the frame in flight slot frame went through
+    pub fn wait_for_frame(
+        &self,
+        frame: u64,
+        timeout: u64,
+    ) -> Result<bool, RenderContextError> {
+        if frame > self.frames_submitted {
+            return Ok(false);
+        }
+        if let Some(frame_timeline) = &self.frame_timeline {
+            return frame_timeline.wait(frame, timeout).map_err(
+                VkError::with_op(
+                    "vkWaitSemaphores",
+                    RenderContextError::DrawFrameFailed,
+                ),
+            );
+        }
+        let frames = self
+            .frames
+            .as_ref()
+            .expect("frames are created while constructing the context");
+        let in_flight = frames.count() as u64;
+        //draw_frame waits on a slot's fence before reusing it, so anything
+        //older than the last few frames is known to be done
+        if frame == 0 || frame + in_flight <= self.frames_submitted {
+            return Ok(true);
+        }
+        //frames advance once per submit, so frame n went through slot n - 1
+        //mod the frame count and nothing has reused it since
+        let fence = frames.get(((frame - 1) % in_flight) as usize).in_flight;
+        //SAFETY: the fence is ours and alive
+        match unsafe { self.device().wait_for_fences(&[fence], true, timeout) }
+        {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(e) => Err(RenderContextError::DrawFrameFailed(VkError::new(
+                "vkWaitForFences",
+                e,
+            ))),
+        }
+    }
 
-use ash::{
-    extensions::{ext::DebugUtils, khr::Surface},
-    vk::{
-        self, ApplicationInfo, DebugUtilsMessageSeverityFlagsEXT,
-        DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
-        DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, SurfaceKHR,
-    },
-    Entry, Instance,
-};
-use cstr::cstr;
-use log::Level;
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use winit::window::Window;
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
 
-#[allow(dead_code)]
-pub struct RenderContext {
-    entry: Entry,
-    instance: Instance,
-    debug_callback: Option<DebugUtilsMessengerEXT>,
-    debug_utils_loader: DebugUtils,
-    surface: SurfaceKHR,
-    surface_callbacks: Surface,
-    //hold on to the window as we need to make sure it is not dropped under any
-    //circumstances until we drop this Arc
-    _window: Arc<Window>,
-}
+    pub fn commands(&self) -> &CommandManager {
+        self.commands
+            .as_ref()
+            .expect("commands are created while constructing the context")
+    }
 
-#[derive(Debug)]
-pub enum RenderContextError {
-    MissingExtension,
-    MissingLayer,
-    MissingExtensionAndLayer,
-    UnableToLoadLib,
-    InstanceCreationFailed,
-}
+    //Give a Vulkan object a name that shows up in validation messages and
+    //graphics debuggers like RenderDoc. Does nothing unless validation is
+    //enabled
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        #[cfg(feature = "validation")]
+        if let Some(debug_utils_loader) = &self.debug_utils_loader {
+            let object_name = match CString::new(name) {
+                Ok(object_name) => object_name,
+                Err(_) => {
+                    log::warn!("Can't use {:?} as an object name", name);
+                    return;
+                }
+            };
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(H::TYPE)
+                .object_handle(handle.as_raw())
+                .object_name(&object_name)
+                .build();
+            //SAFETY: the handle belongs to our device and object_name
+            //outlives the call
+            if let Err(e) = unsafe {
+                debug_utils_loader.set_debug_utils_object_name(
+                    self.device().handle(),
+                    &name_info,
+                )
+            } {
+                log::warn!("Failed to name {:?} {}: {:?}", H::TYPE, name, e);
+            }
+        }
+        #[cfg(not(feature = "validation"))]
+        let _ = (handle, name);
+    }
 
-impl RenderContext {
-    pub fn new(
-        window: Arc<Window>,
-    ) -> Result<RenderContext, RenderContextError> {
-        //SAFETY: Admittedly not actually safe since someone can make a vulkan
-        //lib that on startup scribbles all over our memory or some nonsense but
-        //this is a game so. Whatever. Risk I can take.
-        match unsafe { Entry::load() } {
-            Err(_) => Err(RenderContextError::UnableToLoadLib),
+    //Send one synthetic message at each severity through the debug
+    //messenger to check it's hooked up. Returns whether the callback got the
+    //ERROR one, which is false without sending anything if there's no
+    //messenger to send to
+    pub fn emit_test_messages(&self) -> bool {
+        match (&self.debug_utils_loader, self.debug_callback) {
+            (Some(debug_utils_loader), Some(_)) => {
+                let errors_before = self.validation_error_count();
+                test_debug_callback(debug_utils_loader);
+                //the test ERROR isn't a real validation error so take it back
+                //off the count once we know it arrived
+                let arrived = self.validation_error_count() > errors_before;
+                if arrived {
+                    self.validation_errors.fetch_sub(1, Ordering::Relaxed);
+                }
+                arrived
+            }
+            _ => false,
+        }
+    }
 
-            Ok(entry) => {
-                let vk_version = entry
-                    .try_enumerate_instance_version()
-                    .unwrap()
-                    .unwrap_or(vk::make_api_version(0, 1, 0, 0));
+    //Swap the debug messenger for one passing on severity and message_types,
+    //e.g. to quiet VERBOSE spam without touching the logger. Pass None for
+    //either to go back to the default. Returns false if validation is off
+    //and there's no messenger to change
+    pub fn set_debug_filter(
+        &mut self,
+        severity: Option<DebugUtilsMessageSeverityFlagsEXT>,
+        message_types: Option<DebugUtilsMessageTypeFlagsEXT>,
+    ) -> bool {
+        let Some(debug_utils_loader) = &self.debug_utils_loader else {
+            return false;
+        };
+        self.debug_severity = severity.unwrap_or_else(default_debug_severity);
+        self.debug_message_types =
+            message_types.unwrap_or_else(default_debug_message_types);
+        if let Some(debug_callback) = self.debug_callback.take() {
+            //SAFETY: the messenger was made from this loader and nothing
+            //else refers to it
+            unsafe {
+                debug_utils_loader
+                    .destroy_debug_utils_messenger(debug_callback, None)
+            }
+        }
+        self.debug_callback = create_debug_messenger(
+            debug_utils_loader,
+            self.debug_severity,
+            self.debug_message_types,
+            &self.validation_errors,
+        );
+        if self.debug_callback.is_none() {
+            log::warn!("Failed to recreate the debug messenger");
+        }
+        self.debug_callback.is_some()
+    }
 
-                let app_info = ApplicationInfo::builder()
-                    .api_version(vk_version)
-                    .engine_name(cstr!("Rageware"))
-                    .engine_version(0)
-                    .application_name(cstr!("sufat"))
-                    .application_version(0)
-                    .build();
-                let mut required_extensions =
-                    ash_window::enumerate_required_extensions(
-                        window.raw_display_handle(),
-                    )
-                    .unwrap()
-                    .to_vec();
+    //The severities and message types the debug messenger passes on
+    pub fn debug_filter(
+        &self,
+    ) -> (
+        DebugUtilsMessageSeverityFlagsEXT,
+        DebugUtilsMessageTypeFlagsEXT,
+    ) {
+        (self.debug_severity, self.debug_message_types)
+    }
 
-                required_extensions.push(DebugUtils::name().as_ptr());
+    //How many ERROR messages the debug messenger has passed on so far.
+    //Always 0 when validation is off. Handy for failing a test or CI run that
+    //tripped the validation layers
+    pub fn validation_error_count(&self) -> u64 {
+        self.validation_errors.load(Ordering::Relaxed)
+    }
 
-                let ext_props = entry
-                    .enumerate_instance_extension_properties(None)
-                    .unwrap();
-                let exts_missing: Vec<*const i8> = required_extensions
-                    .iter()
-                    .map(|needle_extension_name| {
-                        ext_props
-                            .iter()
-                            //SAFETY: Fine because all our strings are null
-                            //terminated
-                            .find_map(|ext_prop| unsafe {
-                                CStr::from_ptr(*needle_extension_name)
-                                    .cmp(CStr::from_ptr(
-                                        ext_prop.extension_name.as_ptr(),
-                                    ))
-                                    .is_eq()
-                                    .then_some(())
-                            })
-                            .ok_or(needle_extension_name)
-                    })
-                    .filter_map(|i| i.err().copied())
-                    .collect();
+    fn name_swapchain_images(&self) {
+        let Some(swapchain) = &self.swapchain else {
+            return;
+        };
+        for (i, (&image, &view)) in swapchain
+            .images()
+            .iter()
+            .zip(swapchain.image_views())
+            .enumerate()
+        {
+            self.set_object_name(image, &format!("swapchain image {}", i));
+            self.set_object_name(view, &format!("swapchain image view {}", i));
+        }
+    }
 
-                let exts_missing = if exts_missing.len() > 0 {
-                    for ext in exts_missing {
-                        //SAFETY: Fine because all these strings are null
-                        //terminated
-                        log::error!("Missing extension {:?}", unsafe {
-                            CStr::from_ptr(ext)
-                        });
-                    }
-                    true
-                } else {
-                    false
-                };
+    pub(crate) fn physical_device_info(&self) -> &PhysicalDeviceInfo {
+        self.physical_device_info.as_ref().expect(
+            "physical device is selected while constructing the context",
+        )
+    }
 
-                let debug_layer_names =
-                    [cstr!("VK_LAYER_KHRONOS_validation").as_ptr()];
+    pub fn device(&self) -> &Device {
+        self.device
+            .as_ref()
+            .expect("device is created while constructing the context")
+    }
 
-                let layer_props =
-                    entry.enumerate_instance_layer_properties().unwrap();
-                let layers_missing: Vec<*const i8> = debug_layer_names
-                    .iter()
-                    .map(|needle_layer_name| {
-                        layer_props
-                            .iter()
-                            .find_map(|m| -> Option<()> {
-                                //SAFETY: Legal because m.layer_name is always
-                                //null terminated and we make our needle name
-                                //from cstr!
-                                unsafe {
-                                    CStr::from_ptr(*needle_layer_name)
-                                        .cmp(CStr::from_ptr(
-                                            m.layer_name.as_ptr(),
-                                        ))
-                                        .is_eq()
-                                        .then_some(())
-                                }
-                            })
-                            .ok_or(needle_layer_name)
-                    })
-                    .filter_map(|i| i.err().copied())
-                    .collect();
+    pub fn graphics_queue(&self) -> vk::Queue {
+        self.graphics_queue
+    }
 
-                let layers_missing = if layers_missing.len() != 0 {
-                    for missing_layer_name in layers_missing {
-                        //SAFETY: We build these off of cstr! so we're fine
-                        log::error!("Missing layer {:?}", unsafe {
-                            CStr::from_ptr(missing_layer_name)
-                        })
-                    }
-                    true
-                } else {
-                    false
-                };
-                if layers_missing && exts_missing {
-                    Err(RenderContextError::MissingExtensionAndLayer)
-                } else if layers_missing {
-                    Err(RenderContextError::MissingLayer)
-                } else if exts_missing {
-                    Err(RenderContextError::MissingExtension)
-                } else {
-                    log::debug!("Successfully found all layers");
+    pub fn present_queue(&self) -> vk::Queue {
+        self.present_queue
+    }
 
-                    let create_info = vk::InstanceCreateInfo::builder()
-                        .application_info(&app_info)
-                        .enabled_extension_names(&required_extensions)
-                        .enabled_layer_names(&debug_layer_names)
-                        .build();
+    //A queue on the dedicated compute family if the device has one, for
+    //compute work that runs alongside rendering. Otherwise the graphics
+    //queue. Resources shared with graphics work on a different family need
+    //their queue family ownership transferred
+    pub fn compute_queue(&self) -> vk::Queue {
+        self.compute_queue
+    }
 
-                    //SAFETY: we constructed create_instance from a builder
-                    //using correct parameters so it should be correct too
-                    match unsafe { entry.create_instance(&create_info, None) } {
-                        Err(_) => {
-                            log::error!(
-                                "We got to creating an instance but it failed\
-                                for some reason"
-                            );
-                            Err(RenderContextError::InstanceCreationFailed)
-                        }
+    pub fn queue_families(&self) -> &QueueFamilies {
+        self.queue_families
+            .as_ref()
+            .expect("queue families are found while constructing the context")
+    }
 
-                        Ok(instance) => {
-                            log::info!("Successfully created instance");
+    //The window the context draws to. None for headless contexts
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        self.window.as_ref()
+    }
 
-                            let mut debug_messenger_log_level =
-                                DebugUtilsMessageSeverityFlagsEXT::empty();
-                            let log_level = log::max_level();
-                            if log_level >= Level::Error {
-                                debug_messenger_log_level |=
-                                    DebugUtilsMessageSeverityFlagsEXT::ERROR
-                            }
-                            if log_level >= Level::Warn {
-                                debug_messenger_log_level |=
-                                    DebugUtilsMessageSeverityFlagsEXT::WARNING
-                            }
-                            if log_level >= Level::Info {
-                                debug_messenger_log_level |=
-                                    DebugUtilsMessageSeverityFlagsEXT::INFO
-                            }
-                            if log_level >= Level::Trace {
-                                debug_messenger_log_level |=
-                                    DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                            }
+    //How the event loop should drive draw_frame. The context itself draws
+    //whenever it's asked to, this is for the code running the loop
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
 
-                            let debug_utils_loader =
-                                DebugUtils::new(&entry, &instance);
-
-                            let debug_info =
-                                DebugUtilsMessengerCreateInfoEXT::builder()
-                                    .message_severity(
-                                        debug_messenger_log_level,
-                                    ).message_type(DebugUtilsMessageTypeFlagsEXT::GENERAL
-                                         | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE |
-                                             DebugUtilsMessageTypeFlagsEXT::VALIDATION)
-                                             .pfn_user_callback(Some(vulkan_debug_callback))
-                                             .build();
-
-                            let debug_callback = unsafe {
-                                debug_utils_loader
-                                    .create_debug_utils_messenger(
-                                        &debug_info,
-                                        None,
-                                    )
-                                    .ok()
-                            };
+    pub fn set_loop_mode(&mut self, loop_mode: LoopMode) {
+        self.loop_mode = loop_mode;
+    }
 
-                            test_debug_callback(&debug_utils_loader);
-                            let surface_callbacks =
-                                Surface::new(&entry, &instance);
-                            let surface = unsafe {
-                                ash_window::create_surface(
-                                    &entry,
-                                    &instance,
-                                    window.raw_display_handle(),
-                                    window.raw_window_handle(),
-                                    None,
-                                )
-                            }
-                            .unwrap();
-                            Ok(RenderContext {
-                                entry,
-                                instance,
-                                debug_callback,
-                                surface,
-                                debug_utils_loader,
-                                _window: window,
-                                surface_callbacks,
-                            })
-                        }
-                    }
+    //Whether RenderContext::run shows the window after the first frame. See
+    //RenderContextBuilder::show_after_first_frame
+    pub fn show_after_first_frame(&self) -> bool {
+        self.show_after_first_frame
+    }
+
+    //Stop (or resume) doing any rendering work. Events still need to be
+    //pumped by the caller while paused so the window stays responsive.
+    pub fn set_rendering_paused(&mut self, paused: bool) {
+        if paused != self.rendering_paused {
+            log::debug!("rendering paused: {}", paused);
+            self.frame_timer.restart();
+        }
+        self.rendering_paused = paused;
+    }
+
+    //Draw PipelineVariants as wireframes from the next frame on. Needs the
+    //fillModeNonSolid feature, without it everything stays filled
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && self.enabled_features.fill_mode_non_solid != vk::TRUE {
+            log::warn!(
+                "wireframe requested but fillModeNonSolid isn't enabled, \
+                pipelines will stay filled"
+            );
+        }
+        self.wireframe = wireframe;
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    //Human readable dump of how the context was set up. Meant to be pasted
+    //into bug reports so only things we actually know go in here
+    pub fn diagnostic_report(&self) -> String {
+        let mut report = String::new();
+        //writing into a String can't fail so the results are ignored
+        let _ = writeln!(report, "sufat render context");
+        let _ = writeln!(report, "instance:");
+        let _ = writeln!(
+            report,
+            "\tapi version: {}.{}.{}",
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version)
+        );
+        let _ = writeln!(report, "\tlayers:");
+        for layer in &self.enabled_layers {
+            let _ = writeln!(report, "\t\t{}", layer);
+        }
+        let _ = writeln!(report, "\textensions:");
+        for ext in &self.enabled_extensions {
+            let _ = writeln!(report, "\t\t{}", ext);
+        }
+        let _ = writeln!(
+            report,
+            "\tdebug messenger: {}",
+            if self.debug_callback.is_some() {
+                "enabled"
+            } else if self.debug_utils_loader.is_some() {
+                "unavailable"
+            } else {
+                "disabled"
+            }
+        );
+        let _ = writeln!(
+            report,
+            "\tvalidation errors: {}",
+            self.validation_error_count()
+        );
+        let _ = writeln!(report, "device:");
+        match &self.physical_device_info {
+            Some(info) => {
+                let _ = writeln!(report, "\tname: {}", info.name);
+                let _ = writeln!(report, "\ttype: {:?}", info.device_type);
+                let _ = writeln!(
+                    report,
+                    "\tapi version: {}.{}.{}",
+                    vk::api_version_major(info.api_version),
+                    vk::api_version_minor(info.api_version),
+                    vk::api_version_patch(info.api_version)
+                );
+            }
+            None => {
+                let _ = writeln!(report, "\tnone selected");
+            }
+        }
+        if self.device.is_some() {
+            let _ = writeln!(
+                report,
+                "\ttimeline semaphores: {}",
+                match &self.timeline_fns {
+                    Some(TimelineFns::Core) => "core",
+                    Some(TimelineFns::Khr(_)) => "extension",
+                    None => "unsupported",
+                }
+            );
+            let _ = writeln!(report, "memory heaps:");
+            for (i, heap) in self.memory_budget().iter().enumerate() {
+                let _ = write!(
+                    report,
+                    "\t{}: {} MiB budget",
+                    i,
+                    heap.budget / (1024 * 1024)
+                );
+                if let Some(usage) = heap.usage {
+                    let _ =
+                        write!(report, ", {} MiB used", usage / (1024 * 1024));
                 }
+                let _ = writeln!(report, " ({:?})", heap.flags);
             }
         }
+        if let Some(queue_families) = &self.queue_families {
+            let _ = writeln!(report, "queue families:");
+            let _ = writeln!(report, "\tgraphics: {}", queue_families.graphics);
+            let _ = writeln!(report, "\tpresent: {}", queue_families.present);
+            let _ = writeln!(report, "\tcompute: {:?}", queue_families.compute);
+            let _ =
+                writeln!(report, "\ttransfer: {:?}", queue_families.transfer);
+        }
+        if let Some(swapchain) = &self.swapchain {
+            let extent = swapchain.extent();
+            let _ = writeln!(report, "swapchain:");
+            let _ = writeln!(
+                report,
+                "\textent: {}x{}",
+                extent.width, extent.height
+            );
+            let _ = writeln!(report, "\tformat: {:?}", swapchain.format());
+            let _ = writeln!(
+                report,
+                "\tpresent mode: {:?}",
+                swapchain.present_mode()
+            );
+            let _ = writeln!(report, "\timages: {}", swapchain.images().len());
+        }
+        #[cfg(feature = "allocator")]
+        if let Some(target) = &self.headless_target {
+            let extent = target.image().extent();
+            let _ = writeln!(report, "headless target:");
+            let _ = writeln!(
+                report,
+                "\textent: {}x{}",
+                extent.width, extent.height
+            );
+            let _ = writeln!(report, "\tformat: {:?}", target.image().format());
+        }
+        let timings = self.creation_timings();
+        let _ = writeln!(report, "creation timings:");
+        let _ = writeln!(report, "\tinstance: {:?}", timings.instance);
+        let _ = writeln!(report, "\tsurface: {:?}", timings.surface);
+        let _ = writeln!(
+            report,
+            "\tdevice selection: {:?}",
+            timings.device_selection
+        );
+        let _ = writeln!(
+            report,
+            "\tdevice creation: {:?}",
+            timings.device_creation
+        );
+        let _ = writeln!(
+            report,
+            "\tswapchain creation: {:?}",
+            timings.swapchain_creation
+        );
+        report
+    }
+
+    //The device features that were enabled, which are the ones the builder
+    //required
+    pub fn enabled_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
+    //Everything the device reports it can handle. Check these up front
+    //rather than finding out from validation errors
+    pub fn device_limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.physical_device_info().limits
+    }
+
+    //How many bytes of push constants a pipeline can use. At least 128
+    pub fn max_push_constants_size(&self) -> u32 {
+        self.device_limits().max_push_constants_size
+    }
+
+    //What the offset of a uniform buffer descriptor has to be a multiple of
+    pub fn min_uniform_buffer_offset_alignment(&self) -> vk::DeviceSize {
+        self.device_limits().min_uniform_buffer_offset_alignment
+    }
+
+    //The most anisotropy samplers can use, when the samplerAnisotropy
+    //feature is enabled
+    pub fn max_sampler_anisotropy(&self) -> f32 {
+        self.device_limits().max_sampler_anisotropy
+    }
+
+    //The widest or tallest a 2D image can be
+    pub fn max_image_dimension_2d(&self) -> u32 {
+        self.device_limits().max_image_dimension2_d
+    }
+
+    //What the physical device can do with images of this format and tiling.
+    //Check this before creating an image so an unsupported format/usage
+    //combo doesn't fail at creation time. Tilings other than LINEAR and
+    //OPTIMAL (e.g. DRM modifiers) report nothing
+    pub fn format_features(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+    ) -> vk::FormatFeatureFlags {
+        //SAFETY: physical_device came from this instance
+        let properties = unsafe {
+            self.instance.get_physical_device_format_properties(
+                self.physical_device,
+                format,
+            )
+        };
+        match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features,
+            vk::ImageTiling::OPTIMAL => properties.optimal_tiling_features,
+            _ => vk::FormatFeatureFlags::empty(),
+        }
+    }
+
+    //Every instance extension we enabled, whether sufat needed it or it was
+    //asked for on the builder
+    pub fn enabled_extensions(&self) -> &[String] {
+        &self.enabled_extensions
+    }
+
+    //The instance's API version as (major, minor, patch). Either what the
+    //builder asked for or the newest the loader supports
+    pub fn api_version(&self) -> (u32, u32, u32) {
+        (
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version),
+        )
+    }
+
+    pub fn creation_timings(&self) -> CreationTimings {
+        self.creation_timings
+    }
+}
+//The severities log::max_level currently lets through
+fn default_debug_severity() -> DebugUtilsMessageSeverityFlagsEXT {
+    //errors always come through so validation_errors counts them even when
+    //logging is off
+    let mut debug_messenger_log_level =
+        DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    let log_level = log::max_level();
+    if log_level >= Level::Warn {
+        debug_messenger_log_level |= DebugUtilsMessageSeverityFlagsEXT::WARNING
+    }
+    if log_level >= Level::Info {
+        debug_messenger_log_level |= DebugUtilsMessageSeverityFlagsEXT::INFO
+    }
+    if log_level >= Level::Trace {
+        debug_messenger_log_level |= DebugUtilsMessageSeverityFlagsEXT::VERBOSE
     }
+    debug_messenger_log_level
 }
+
+fn default_debug_message_types() -> DebugUtilsMessageTypeFlagsEXT {
+    DebugUtilsMessageTypeFlagsEXT::GENERAL
+        | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+        | DebugUtilsMessageTypeFlagsEXT::VALIDATION
+}
+
+//validation_errors gets handed to the callback as user data and must outlive
+//whatever the create info is used for
+fn debug_messenger_create_info(
+    severity: DebugUtilsMessageSeverityFlagsEXT,
+    message_types: DebugUtilsMessageTypeFlagsEXT,
+    validation_errors: &AtomicU64,
+) -> DebugUtilsMessengerCreateInfoEXT {
+    DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(severity)
+        .message_type(message_types)
+        .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(validation_errors as *const AtomicU64 as *mut c_void)
+        .build()
+}
+
+fn create_debug_messenger(
+    debug_utils_loader: &DebugUtils,
+    severity: DebugUtilsMessageSeverityFlagsEXT,
+    message_types: DebugUtilsMessageTypeFlagsEXT,
+    validation_errors: &AtomicU64,
+) -> Option<DebugUtilsMessengerEXT> {
+    let debug_info =
+        debug_messenger_create_info(severity, message_types, validation_errors);
+
+    //SAFETY: debug_info was built with a builder and points at a valid
+    //callback
+    unsafe {
+        debug_utils_loader
+            .create_debug_utils_messenger(&debug_info, None)
+            .ok()
+    }
+}
+
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
+    if message_severity == DebugUtilsMessageSeverityFlagsEXT::ERROR
+        && !user_data.is_null()
+    {
+        //SAFETY: the only user data we hand out is the context's
+        //validation_errors, which outlives every messenger
+        let validation_errors = unsafe { &*(user_data as *const AtomicU64) };
+        validation_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
     //pointer guaranteed to be valid by API rules since this isn't exported
     let callback_data = unsafe { *p_callback_data };
     let message_id_number = callback_data.message_id_number;
@@ -371,14 +4573,103 @@ fn test_debug_callback(debug_utils_loader: &DebugUtils) {
 impl Drop for RenderContext {
     fn drop(&mut self) {
         log::info!("Destroying render context");
+        if let Some(device) = &self.device {
+            //SAFETY: nothing is allowed to outlive the device so wait for the
+            //GPU to be done with everything before tearing anything down
+            if let Err(e) = unsafe { device.device_wait_idle() } {
+                log::error!("Failed to wait for device idle: {:?}", e);
+            }
+        }
+        //every submitted frame's fence is signaled once the device is idle,
+        //so one that isn't means the wait failed or a frame never got
+        //submitted after resetting its fence
+        let unfinished_frames: usize = self
+            .frames
+            .iter()
+            .chain(
+                self.window_targets
+                    .iter()
+                    .flatten()
+                    .map(|target| &target.frames),
+            )
+            .map(|frames| frames.unfinished())
+            .sum();
+        if unfinished_frames > 0 {
+            log::warn!(
+                "{} frames still unfinished after waiting for the device",
+                unfinished_frames
+            );
+        }
+
+        //everything made from the device has to go before it does
+        #[cfg(feature = "hot-reload")]
+        {
+            self.shader_watcher = None;
+        }
+        self.window_targets.clear();
+        self.frames = None;
+        self.frame_timeline = None;
+        self.frame_timestamps = None;
+        self.secondary_pools.clear();
+        self.commands = None;
+        self.framebuffers = None;
+        #[cfg(feature = "allocator")]
+        {
+            self.depth_buffer = None;
+            self.msaa_target = None;
+        }
+        self.render_pass = None;
+        self.swapchain = None;
+        //saves itself to disk on the way out
+        self.pipeline_cache = None;
+        #[cfg(feature = "allocator")]
+        {
+            self.headless_target = None;
+        }
+        //a capture nobody collected still holds a buffer
+        #[cfg(feature = "textures")]
+        {
+            self.capture = None;
+            self.capture_requested = false;
+        }
+
+        #[cfg(feature = "allocator")]
+        if let Some(allocator) = self.allocator.take() {
+            //every buffer and image holds on to the allocator and frees
+            //itself through the device when dropped. If we aren't the last
+            //one the device, and the instance it came from, have to stay
+            //alive for them, so leak both rather than hand them a dead device
+            let outstanding = Arc::strong_count(&allocator) - 1;
+            if outstanding > 0 {
+                log::error!(
+                    "{} buffers or images outlived the render context, \
+                    leaking the device",
+                    outstanding
+                );
+                return;
+            }
+        }
+
+        if let Some(device) = &self.device {
+            //SAFETY: we're idle and everything made from it is gone
+            unsafe {
+                device.destroy_device(None);
+            }
+        }
+
         //SAFETY: We correctly construct this in new
-        self.debug_callback.map(|debug_callback| unsafe {
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(debug_callback, None)
-        });
+        if let (Some(debug_utils_loader), Some(debug_callback)) =
+            (&self.debug_utils_loader, self.debug_callback)
+        {
+            unsafe {
+                debug_utils_loader
+                    .destroy_debug_utils_messenger(debug_callback, None)
+            }
+        }
 
-        unsafe {
-            self.surface_callbacks.destroy_surface(self.surface, None);
+        if let Some(surface) = self.surface {
+            //SAFETY: the swapchain made from it is already gone
+            unsafe { self.surface_callbacks.destroy_surface(surface, None) }
         }
 
         //SAFETY: We correctly construct this in new
@@ -387,3 +4678,78 @@ impl Drop for RenderContext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Needs a vulkan loader with the validation layers installed, and
+    //passes without checking anything when there isn't one
+    #[test]
+    fn chained_messenger_reports_instance_teardown() {
+        //SAFETY: nothing made from the entry outlives this test
+        let Ok(entry) = (unsafe { Entry::load() }) else {
+            eprintln!("no vulkan loader, skipping");
+            return;
+        };
+        let validation_layer = cstr!("VK_LAYER_KHRONOS_validation");
+        let has_layer = entry
+            .enumerate_instance_layer_properties()
+            .unwrap_or_default()
+            .iter()
+            //SAFETY: layer names from vulkan are null terminated
+            .any(|layer| unsafe {
+                CStr::from_ptr(layer.layer_name.as_ptr()) == validation_layer
+            });
+        if !has_layer {
+            eprintln!("no validation layers, skipping");
+            return;
+        }
+
+        let errors = AtomicU64::new(0);
+        let mut debug_info = debug_messenger_create_info(
+            DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            default_debug_message_types(),
+            &errors,
+        );
+        let layers = [validation_layer.as_ptr()];
+        let extensions = [DebugUtils::name().as_ptr()];
+        let app_info = ApplicationInfo::builder()
+            .api_version(vk::API_VERSION_1_0)
+            .build();
+        let create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&extensions)
+            .push_next(&mut debug_info)
+            .build();
+        //SAFETY: everything create_info points at outlives the call
+        let instance = unsafe { entry.create_instance(&create_info, None) }
+            .expect("failed to create an instance with validation on");
+
+        //leaving a child object alive is a validation error in
+        //destroy_instance. This messenger only takes info messages, which
+        //aren't counted, so only the chained one can count the error
+        let other_errors = AtomicU64::new(0);
+        let leaked_info = debug_messenger_create_info(
+            DebugUtilsMessageSeverityFlagsEXT::INFO,
+            DebugUtilsMessageTypeFlagsEXT::GENERAL,
+            &other_errors,
+        );
+        let debug_utils_loader = DebugUtils::new(&entry, &instance);
+        //SAFETY: leaked_info was made with a builder and other_errors
+        //outlives the instance
+        unsafe {
+            debug_utils_loader
+                .create_debug_utils_messenger(&leaked_info, None)
+                .expect("failed to create a debug messenger");
+        }
+        let errors_before = errors.load(Ordering::Relaxed);
+        //SAFETY: the leaked messenger is the point of the test
+        unsafe { instance.destroy_instance(None) };
+        assert!(
+            errors.load(Ordering::Relaxed) > errors_before,
+            "destroy_instance didn't report the leaked messenger"
+        );
+    }
+}
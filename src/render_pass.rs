@@ -0,0 +1,628 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+#[cfg(feature = "dynamic-rendering")]
+use ash::extensions::khr;
+use ash::{vk, Device, Instance};
+
+#[cfg(feature = "dynamic-rendering")]
+use crate::{
+    barrier::transition_image_layout, render_context::RenderContextError,
+};
+
+//Dynamic rendering is core in 1.3 and an extension before that, with the
+//same entry points behind a different loader
+#[cfg(feature = "dynamic-rendering")]
+#[derive(Clone)]
+pub(crate) enum DynamicRenderingFns {
+    Core,
+    Khr(khr::DynamicRendering),
+}
+
+//The images a dynamic render pass draws to for one frame, as (image, view)
+//pairs
+#[cfg(feature = "dynamic-rendering")]
+pub(crate) struct RenderingTargets {
+    //what ends up in final_layout, the resolve target when multisampling
+    pub color: (vk::Image, vk::ImageView),
+    pub msaa: Option<(vk::Image, vk::ImageView)>,
+    pub depth: Option<(vk::Image, vk::ImageView)>,
+}
+
+pub struct RenderPass {
+    //null for dynamic passes, which have no render pass object
+    render_pass: vk::RenderPass,
+    //one per attachment, in attachment order, ready to hand to
+    //RenderPassBeginInfo
+    clear_values: Vec<vk::ClearValue>,
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+    samples: vk::SampleCountFlags,
+    final_layout: vk::ImageLayout,
+    device: Device,
+}
+
+impl RenderPass {
+    //One subpass drawing to a single color attachment that gets cleared to
+    //clear_color and is left ready to present. Enough for anything that
+    //draws straight to the swapchain
+    pub fn color_only(
+        device: &Device,
+        format: vk::Format,
+        clear_color: [f32; 4],
+    ) -> Result<RenderPass, vk::Result> {
+        RenderPass::new(
+            device,
+            format,
+            None,
+            vk::SampleCountFlags::TYPE_1,
+            clear_color,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    //Same as color_only plus a depth attachment in depth_format that gets
+    //cleared to clear_depth every frame and thrown away afterwards
+    pub fn color_and_depth(
+        device: &Device,
+        format: vk::Format,
+        depth_format: vk::Format,
+        clear_color: [f32; 4],
+        clear_depth: f32,
+    ) -> Result<RenderPass, vk::Result> {
+        RenderPass::new(
+            device,
+            format,
+            Some((depth_format, clear_depth)),
+            vk::SampleCountFlags::TYPE_1,
+            clear_color,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    //Draws to a color attachment (and depth attachment if depth is Some)
+    //with samples samples per pixel, then resolves the color into a single
+    //sampled attachment that's left ready to present. Framebuffers take the
+    //attachments in the order multisampled color, depth, resolve target
+    pub fn multisampled(
+        device: &Device,
+        format: vk::Format,
+        depth: Option<(vk::Format, f32)>,
+        samples: vk::SampleCountFlags,
+        clear_color: [f32; 4],
+    ) -> Result<RenderPass, vk::Result> {
+        RenderPass::new(
+            device,
+            format,
+            depth,
+            samples,
+            clear_color,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    //Like multisampled, but the final color image is left in final_layout
+    //for whatever reads it next instead of being presented. Only
+    //TRANSFER_SRC_OPTIMAL, SHADER_READ_ONLY_OPTIMAL and
+    //COLOR_ATTACHMENT_OPTIMAL are waited on correctly when the pass is begun
+    //again. SHADER_READ_ONLY_OPTIMAL images are also ready for fragment
+    //shaders recorded after the pass ends
+    pub fn offscreen(
+        device: &Device,
+        format: vk::Format,
+        depth: Option<(vk::Format, f32)>,
+        samples: vk::SampleCountFlags,
+        clear_color: [f32; 4],
+        final_layout: vk::ImageLayout,
+    ) -> Result<RenderPass, vk::Result> {
+        RenderPass::new(
+            device,
+            format,
+            depth,
+            samples,
+            clear_color,
+            final_layout,
+        )
+    }
+
+    fn new(
+        device: &Device,
+        format: vk::Format,
+        depth: Option<(vk::Format, f32)>,
+        samples: vk::SampleCountFlags,
+        clear_color: [f32; 4],
+        final_layout: vk::ImageLayout,
+    ) -> Result<RenderPass, vk::Result> {
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+        //a multisampled color attachment only lives until it's resolved so
+        //it never needs storing or presenting
+        let mut attachments = vec![vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(samples)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(if multisampled {
+                vk::AttachmentStoreOp::DONT_CARE
+            } else {
+                vk::AttachmentStoreOp::STORE
+            })
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(if multisampled {
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            } else {
+                final_layout
+            })
+            .build()];
+        let mut clear_values = vec![vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: clear_color,
+            },
+        }];
+        let color_attachments = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let depth_attachment = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        //the image we acquire is only guaranteed to be free once the acquire
+        //semaphore signals, which we wait on at color attachment output. Hold
+        //the layout transition and clear back until then
+        let mut stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        //an offscreen image may still be getting read from by whatever was
+        //waiting on the last frame's final layout
+        let reader_stage = match final_layout {
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => {
+                vk::PipelineStageFlags::TRANSFER
+            }
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+            }
+            _ => vk::PipelineStageFlags::empty(),
+        };
+        let mut dst_access = vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachments);
+        if let Some((depth_format, clear_depth)) = depth {
+            //depth only matters while the frame is being drawn so it's never
+            //stored
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .format(depth_format)
+                    .samples(samples)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    )
+                    .build(),
+            );
+            clear_values.push(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: clear_depth,
+                    stencil: 0,
+                },
+            });
+            subpass = subpass.depth_stencil_attachment(&depth_attachment);
+            //the previous frame may still be testing against the shared
+            //depth image so its clear has to wait for that too
+            stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+            dst_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        }
+        //the resolve target goes after everything else so the clear values
+        //above still line up with their attachments
+        let resolve_attachments = [vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        if multisampled {
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .format(format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(final_layout)
+                    .build(),
+            );
+            subpass = subpass.resolve_attachments(&resolve_attachments);
+        }
+        //unlike swapchain images an offscreen image is shared by every
+        //frame, so the last frame's color writes have to land first too
+        let mut src_access = vk::AccessFlags::empty();
+        if depth.is_some() {
+            src_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        }
+        if final_layout != vk::ImageLayout::PRESENT_SRC_KHR {
+            src_access |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+        }
+        let subpasses = [subpass.build()];
+        let mut dependencies = vec![vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(stages | reader_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(stages)
+            .dst_access_mask(dst_access)
+            .build()];
+        //passes that get sampled later in the same command buffer have to
+        //finish writing before those fragment shaders read
+        if final_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL {
+            dependencies.push(
+                vk::SubpassDependency::builder()
+                    .src_subpass(0)
+                    .dst_subpass(vk::SUBPASS_EXTERNAL)
+                    .src_stage_mask(
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    )
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build(),
+            );
+        }
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies)
+            .build();
+
+        //SAFETY: create_info was made with a builder and everything it points
+        //at lives until the end of this function
+        let render_pass =
+            unsafe { device.create_render_pass(&create_info, None) }?;
+
+        Ok(RenderPass {
+            render_pass,
+            clear_values,
+            format,
+            depth_format: depth.map(|(depth_format, _)| depth_format),
+            samples,
+            final_layout,
+            device: device.clone(),
+        })
+    }
+
+    //The same attachments as offscreen, drawn with begin_rendering instead
+    //of a render pass object and framebuffers. Needs dynamic rendering
+    //enabled on the device
+    #[cfg(feature = "dynamic-rendering")]
+    pub(crate) fn dynamic(
+        device: &Device,
+        format: vk::Format,
+        depth: Option<(vk::Format, f32)>,
+        samples: vk::SampleCountFlags,
+        clear_color: [f32; 4],
+        final_layout: vk::ImageLayout,
+    ) -> RenderPass {
+        let mut clear_values = vec![vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: clear_color,
+            },
+        }];
+        if let Some((_, clear_depth)) = depth {
+            clear_values.push(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: clear_depth,
+                    stencil: 0,
+                },
+            });
+        }
+        RenderPass {
+            render_pass: vk::RenderPass::null(),
+            clear_values,
+            format,
+            depth_format: depth.map(|(depth_format, _)| depth_format),
+            samples,
+            final_layout,
+            device: device.clone(),
+        }
+    }
+
+    //Null for dynamic passes
+    pub fn handle(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    //Whether this pass is drawn with dynamic rendering, in which case
+    //pipelines get built against its formats instead of a render pass
+    //object
+    pub fn is_dynamic(&self) -> bool {
+        self.render_pass == vk::RenderPass::null()
+    }
+
+    //Format of the color attachment
+    pub fn color_format(&self) -> vk::Format {
+        self.format
+    }
+
+    //The layout the final color image is left in once the pass is done
+    pub fn final_layout(&self) -> vk::ImageLayout {
+        self.final_layout
+    }
+
+    pub fn clear_values(&self) -> &[vk::ClearValue] {
+        &self.clear_values
+    }
+
+    //Format of the depth attachment if this pass has one
+    pub fn depth_format(&self) -> Option<vk::Format> {
+        self.depth_format
+    }
+
+    //Samples per pixel of the attachments pipelines draw to. Pipelines used
+    //with this pass have to match it
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    //Change what the color attachment gets cleared to. Takes effect the next
+    //time the pass is begun
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_values[0] = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: clear_color,
+            },
+        };
+    }
+
+    //What beginning a render pass object does for us: move the attachments
+    //into attachment layouts once whatever used them last is done, then
+    //start rendering to them with the same clears
+    #[cfg(feature = "dynamic-rendering")]
+    pub(crate) fn begin_rendering(
+        &self,
+        fns: &DynamicRenderingFns,
+        command_buffer: vk::CommandBuffer,
+        targets: &RenderingTargets,
+        extent: vk::Extent2D,
+        contents: vk::SubpassContents,
+    ) {
+        //same dependency RenderPass::new sets up, see there for why
+        let mut stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        let reader_stage = match self.final_layout {
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => {
+                vk::PipelineStageFlags::TRANSFER
+            }
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+            }
+            _ => vk::PipelineStageFlags::empty(),
+        };
+        let color_src_access =
+            if self.final_layout == vk::ImageLayout::PRESENT_SRC_KHR {
+                vk::AccessFlags::empty()
+            } else {
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            };
+        let color_barrier = |image, src_access| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .src_access_mask(src_access)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(subresource_range(
+                    vk::ImageAspectFlags::COLOR,
+                ))
+                .build()
+        };
+        let mut barriers =
+            vec![color_barrier(targets.color.0, color_src_access)];
+        //the msaa and depth images are shared by every frame
+        if let Some((image, _)) = targets.msaa {
+            barriers.push(color_barrier(
+                image,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ));
+        }
+        if let (Some((image, _)), Some(depth_format)) =
+            (targets.depth, self.depth_format)
+        {
+            barriers.push(
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    )
+                    .src_access_mask(
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .dst_access_mask(
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(subresource_range(depth_aspect(
+                        depth_format,
+                    )))
+                    .build(),
+            );
+            stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        }
+
+        let color_view = targets.color.1;
+        let mut color_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .clear_value(self.clear_values[0]);
+        color_attachment = match targets.msaa {
+            //only the resolved image outlives the pass
+            Some((_, msaa_view)) => color_attachment
+                .image_view(msaa_view)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(color_view)
+                .resolve_image_layout(
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ),
+            None => color_attachment
+                .image_view(color_view)
+                .store_op(vk::AttachmentStoreOp::STORE),
+        };
+        let color_attachments = [color_attachment.build()];
+        let depth_attachment = targets.depth.map(|(_, depth_view)| {
+            vk::RenderingAttachmentInfo::builder()
+                .image_view(depth_view)
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .clear_value(self.clear_values[1])
+                .build()
+        });
+        let flags = match contents {
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS => {
+                vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+            }
+            _ => vk::RenderingFlags::empty(),
+        };
+        let mut rendering_info = vk::RenderingInfo::builder()
+            .flags(flags)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
+        let rendering_info = rendering_info.build();
+
+        //SAFETY: the caller hands us a recording command buffer outside of
+        //any render pass, with live images made for this pass's formats and
+        //samples. Everything rendering_info points at outlives the call
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                stages | reader_stage,
+                stages,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+            match fns {
+                DynamicRenderingFns::Core => self
+                    .device
+                    .cmd_begin_rendering(command_buffer, &rendering_info),
+                DynamicRenderingFns::Khr(loader) => {
+                    loader.cmd_begin_rendering(command_buffer, &rendering_info)
+                }
+            }
+        }
+    }
+
+    //Finish what begin_rendering started and leave the color image in the
+    //pass's final layout
+    #[cfg(feature = "dynamic-rendering")]
+    pub(crate) fn end_rendering(
+        &self,
+        fns: &DynamicRenderingFns,
+        command_buffer: vk::CommandBuffer,
+        color_image: vk::Image,
+    ) -> Result<(), RenderContextError> {
+        //SAFETY: the caller began rendering on this command buffer
+        unsafe {
+            match fns {
+                DynamicRenderingFns::Core => {
+                    self.device.cmd_end_rendering(command_buffer)
+                }
+                DynamicRenderingFns::Khr(loader) => {
+                    loader.cmd_end_rendering(command_buffer)
+                }
+            }
+        }
+        transition_image_layout(
+            &self.device,
+            command_buffer,
+            color_image,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            self.final_layout,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        )
+    }
+}
+
+#[cfg(feature = "dynamic-rendering")]
+fn subresource_range(
+    aspect: vk::ImageAspectFlags,
+) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: aspect,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+impl Drop for RenderPass {
+    fn drop(&mut self) {
+        if self.is_dynamic() {
+            return;
+        }
+        //SAFETY: whoever owns us makes sure the GPU is done with the render
+        //pass before dropping it
+        unsafe { self.device.destroy_render_pass(self.render_pass, None) }
+    }
+}
+
+//Views of combined depth/stencil formats used as attachments have to
+//include both aspects
+#[cfg(any(feature = "allocator", feature = "dynamic-rendering"))]
+pub(crate) fn depth_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+//The first depth format the device can use as a depth attachment. 32 bit
+//float is the most precise, with 24 bit plus stencil as the widely supported
+//fallback
+pub(crate) fn choose_depth_format(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<vk::Format> {
+    [
+        vk::Format::D32_SFLOAT,
+        vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D32_SFLOAT_S8_UINT,
+    ]
+    .into_iter()
+    .find(|&format| {
+        //SAFETY: physical_device came from this instance
+        let properties = unsafe {
+            instance
+                .get_physical_device_format_properties(physical_device, format)
+        };
+        properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    })
+}
@@ -0,0 +1,136 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::render_context::{RenderContext, RenderContextError};
+
+//A sampler that gets destroyed on drop
+pub struct Sampler {
+    sampler: vk::Sampler,
+    device: Device,
+}
+
+impl Sampler {
+    pub fn handle(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the sampler
+        //before dropping it
+        unsafe { self.device.destroy_sampler(self.sampler, None) }
+    }
+}
+
+//Builds a sampler. Defaults to trilinear filtering, repeating in every
+//direction, every mip level and as much anisotropy as the device allows
+pub struct SamplerBuilder {
+    mag_filter: vk::Filter,
+    min_filter: vk::Filter,
+    mipmap_mode: vk::SamplerMipmapMode,
+    address_mode: vk::SamplerAddressMode,
+    //None means the device's maxSamplerAnisotropy
+    anisotropy: Option<f32>,
+    min_lod: f32,
+    max_lod: f32,
+}
+
+impl Default for SamplerBuilder {
+    fn default() -> Self {
+        SamplerBuilder {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            anisotropy: None,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl SamplerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Used for both magnification and minification
+    pub fn filter(mut self, filter: vk::Filter) -> Self {
+        self.mag_filter = filter;
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+
+    //Used for all three texture coordinates
+    pub fn address_mode(
+        mut self,
+        address_mode: vk::SamplerAddressMode,
+    ) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    //Samples taken along the direction a surface is viewed at, clamped to
+    //the device's maxSamplerAnisotropy. 1 or less turns it off. Also off
+    //unless the samplerAnisotropy feature was required when creating the
+    //context
+    pub fn anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+
+    //Range of mip levels that can be sampled
+    pub fn lod_range(mut self, min_lod: f32, max_lod: f32) -> Self {
+        self.min_lod = min_lod;
+        self.max_lod = max_lod;
+        self
+    }
+
+    pub fn build(
+        self,
+        render_context: &RenderContext,
+    ) -> Result<Sampler, RenderContextError> {
+        let device = render_context.device();
+        let max_anisotropy = render_context.max_sampler_anisotropy();
+        let anisotropy = self
+            .anisotropy
+            .unwrap_or(max_anisotropy)
+            .min(max_anisotropy);
+        //using anisotropy without the feature is a validation error so
+        //quietly go without
+        let anisotropy_enable =
+            render_context.enabled_features().sampler_anisotropy == vk::TRUE
+                && anisotropy > 1.0;
+        let create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .anisotropy_enable(anisotropy_enable)
+            .max_anisotropy(if anisotropy_enable { anisotropy } else { 1.0 })
+            .min_lod(self.min_lod)
+            .max_lod(self.max_lod)
+            .build();
+        //SAFETY: create_info was made with a builder and anisotropy is only
+        //on when the feature is enabled
+        let sampler = unsafe { device.create_sampler(&create_info, None) }
+            .map_err(RenderContextError::SamplerCreationFailed)?;
+        Ok(Sampler {
+            sampler,
+            device: device.clone(),
+        })
+    }
+}
@@ -0,0 +1,90 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{error::Error, fmt};
+
+use ash::{vk, Device};
+
+pub const SPIRV_MAGIC: u32 = 0x07230203;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpirvError {
+    //vulkan consumes SPIR-V as 32 bit words
+    LengthNotMultipleOfFour,
+    Empty,
+    BadMagic(u32),
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpirvError::LengthNotMultipleOfFour => {
+                write!(f, "SPIR-V length isn't a multiple of 4 bytes")
+            }
+            SpirvError::Empty => write!(f, "SPIR-V is empty"),
+            SpirvError::BadMagic(magic) => {
+                write!(f, "bad SPIR-V magic number {:#010x}", magic)
+            }
+        }
+    }
+}
+
+impl Error for SpirvError {}
+
+//Turn raw bytes into SPIR-V words. The bytes don't need to be aligned since
+//we copy them out word by word, which is what makes passing something like
+//include_bytes! straight in safe
+pub fn spirv_words(bytes: &[u8]) -> Result<Vec<u32>, SpirvError> {
+    if bytes.is_empty() {
+        return Err(SpirvError::Empty);
+    }
+    if !bytes.len().is_multiple_of(4) {
+        return Err(SpirvError::LengthNotMultipleOfFour);
+    }
+    let words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    if words[0] != SPIRV_MAGIC {
+        return Err(SpirvError::BadMagic(words[0]));
+    }
+    Ok(words)
+}
+
+pub struct ShaderModule {
+    module: vk::ShaderModule,
+    device: Device,
+}
+
+impl ShaderModule {
+    pub(crate) fn new(
+        device: &Device,
+        words: &[u32],
+    ) -> Result<ShaderModule, vk::Result> {
+        let create_info =
+            vk::ShaderModuleCreateInfo::builder().code(words).build();
+        //SAFETY: words has been checked to at least look like SPIR-V and
+        //create_info was made with a builder
+        let module =
+            unsafe { device.create_shader_module(&create_info, None) }?;
+        Ok(ShaderModule {
+            module,
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::ShaderModule {
+        self.module
+    }
+}
+
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        //SAFETY: pipelines don't need their shader modules once they're built
+        //so nothing can be using this
+        unsafe { self.device.destroy_shader_module(self.module, None) }
+    }
+}
@@ -0,0 +1,227 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{vk, Device};
+
+use crate::render_context::VkError;
+
+//A queue submission put together one piece at a time. Every wait semaphore
+//goes in with the stage that waits on it and every semaphore with its
+//timeline value, so the parallel arrays vk::SubmitInfo wants can't end up
+//out of step
+#[derive(Default)]
+pub struct Submission {
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    //binary semaphores ignore their value but every semaphore needs one as
+    //soon as a timeline semaphore is in the mix
+    wait_values: Vec<u64>,
+    command_buffers: Vec<vk::CommandBuffer>,
+    signal_semaphores: Vec<vk::Semaphore>,
+    signal_values: Vec<u64>,
+    fence: vk::Fence,
+    //whether any of the semaphores are timeline ones, which means chaining
+    //the values on. That needs timeline semaphore support so only do it
+    //when we have to
+    timeline: bool,
+}
+
+impl Submission {
+    //Don't start stage until the binary semaphore is signaled
+    pub fn wait(
+        mut self,
+        semaphore: vk::Semaphore,
+        stage: vk::PipelineStageFlags,
+    ) -> Self {
+        self.wait_semaphores.push(semaphore);
+        self.wait_stages.push(stage);
+        self.wait_values.push(0);
+        self
+    }
+
+    //Don't start stage until the timeline semaphore reaches value
+    pub fn wait_timeline(
+        mut self,
+        semaphore: vk::Semaphore,
+        value: u64,
+        stage: vk::PipelineStageFlags,
+    ) -> Self {
+        self.wait_semaphores.push(semaphore);
+        self.wait_stages.push(stage);
+        self.wait_values.push(value);
+        self.timeline = true;
+        self
+    }
+
+    //Command buffers run in the order they're added
+    pub fn command_buffer(mut self, command_buffer: vk::CommandBuffer) -> Self {
+        self.command_buffers.push(command_buffer);
+        self
+    }
+
+    //Signal the binary semaphore once the command buffers are done
+    pub fn signal(mut self, semaphore: vk::Semaphore) -> Self {
+        self.signal_semaphores.push(semaphore);
+        self.signal_values.push(0);
+        self
+    }
+
+    //Set the timeline semaphore to value once the command buffers are done
+    pub fn signal_timeline(
+        mut self,
+        semaphore: vk::Semaphore,
+        value: u64,
+    ) -> Self {
+        self.signal_semaphores.push(semaphore);
+        self.signal_values.push(value);
+        self.timeline = true;
+        self
+    }
+
+    //Signal fence once the command buffers are done. It has to be unsignaled
+    //by the time submit is called
+    pub fn fence(mut self, fence: vk::Fence) -> Self {
+        self.fence = fence;
+        self
+    }
+
+    //Hands f the vk::SubmitInfo this describes. It points into self so it
+    //can't outlive the call
+    fn with_submit_info<R>(&self, f: impl FnOnce(&vk::SubmitInfo) -> R) -> R {
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&self.wait_values)
+            .signal_semaphore_values(&self.signal_values);
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&self.wait_semaphores)
+            .wait_dst_stage_mask(&self.wait_stages)
+            .command_buffers(&self.command_buffers)
+            .signal_semaphores(&self.signal_semaphores);
+        if self.timeline {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+        f(&submit_info)
+    }
+
+    //Submit to queue. Everything handed to the builder has to have come
+    //from device, and the command buffers have to be fully recorded
+    pub fn submit(
+        &self,
+        device: &Device,
+        queue: vk::Queue,
+    ) -> Result<(), VkError> {
+        self.with_submit_info(|submit_info| {
+            //SAFETY: the caller gave us handles from device and every array
+            //submit_info points at lives in self
+            unsafe {
+                device.queue_submit(
+                    queue,
+                    std::slice::from_ref(submit_info),
+                    self.fence,
+                )
+            }
+        })
+        .map_err(|e| VkError::new("vkQueueSubmit", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::Handle;
+
+    fn semaphore(raw: u64) -> vk::Semaphore {
+        vk::Semaphore::from_raw(raw)
+    }
+
+    //SAFETY: ptr has to point at count valid elements, or count is 0
+    unsafe fn slice<'a, T>(ptr: *const T, count: u32) -> &'a [T] {
+        if count == 0 {
+            return &[];
+        }
+        //SAFETY: as above
+        unsafe { std::slice::from_raw_parts(ptr, count as usize) }
+    }
+
+    #[test]
+    fn waits_stay_paired_with_their_stages() {
+        let submission = Submission::default()
+            .wait(
+                semaphore(1),
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .command_buffer(vk::CommandBuffer::from_raw(10))
+            .wait_timeline(
+                semaphore(2),
+                5,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+            )
+            .signal(semaphore(3))
+            .signal_timeline(semaphore(4), 7);
+        submission.with_submit_info(|info| {
+            //SAFETY: the builder set every pointer and count together and
+            //submission outlives the closure
+            unsafe {
+                assert_eq!(
+                    slice(info.p_wait_semaphores, info.wait_semaphore_count),
+                    [semaphore(1), semaphore(2)]
+                );
+                assert_eq!(
+                    slice(
+                        info.p_wait_dst_stage_mask,
+                        info.wait_semaphore_count
+                    ),
+                    [
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                        vk::PipelineStageFlags::VERTEX_INPUT
+                    ]
+                );
+                assert_eq!(
+                    slice(
+                        info.p_signal_semaphores,
+                        info.signal_semaphore_count
+                    ),
+                    [semaphore(3), semaphore(4)]
+                );
+                assert_eq!(info.command_buffer_count, 1);
+
+                assert!(!info.p_next.is_null());
+                let timeline_info =
+                    &*(info.p_next as *const vk::TimelineSemaphoreSubmitInfo);
+                assert_eq!(
+                    timeline_info.s_type,
+                    vk::StructureType::TIMELINE_SEMAPHORE_SUBMIT_INFO
+                );
+                assert_eq!(
+                    slice(
+                        timeline_info.p_wait_semaphore_values,
+                        timeline_info.wait_semaphore_value_count
+                    ),
+                    [0, 5]
+                );
+                assert_eq!(
+                    slice(
+                        timeline_info.p_signal_semaphore_values,
+                        timeline_info.signal_semaphore_value_count
+                    ),
+                    [0, 7]
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn binary_only_submissions_skip_timeline_values() {
+        let submission = Submission::default()
+            .wait(semaphore(1), vk::PipelineStageFlags::TRANSFER)
+            .signal(semaphore(2));
+        submission.with_submit_info(|info| {
+            assert!(info.p_next.is_null());
+            assert_eq!(info.wait_semaphore_count, 1);
+            assert_eq!(info.signal_semaphore_count, 1);
+        });
+    }
+}
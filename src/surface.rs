@@ -0,0 +1,69 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::sync::Arc;
+
+use ash::{extensions::khr::Surface, vk::SurfaceKHR};
+use winit::window::Window;
+
+#[cfg(feature = "allocator")]
+use crate::attachment::AttachmentImage;
+use crate::{
+    command::CommandManager, frame::Frames, framebuffer::Framebuffers,
+    swapchain::Swapchain,
+};
+
+//One of the windows a context draws to. PRIMARY is the window the context
+//was made with, the rest come from RenderContext::add_surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SurfaceId(pub(crate) usize);
+
+impl SurfaceId {
+    pub const PRIMARY: SurfaceId = SurfaceId(0);
+}
+
+//Everything a window added after the context was made needs of its own.
+//It shares the context's device, queues and render pass, so the same
+//pipelines draw to every window
+pub(crate) struct WindowTarget {
+    //None only while being dropped
+    pub swapchain: Option<Swapchain>,
+    pub framebuffers: Option<Framebuffers>,
+    #[cfg(feature = "allocator")]
+    pub depth_buffer: Option<AttachmentImage>,
+    #[cfg(feature = "allocator")]
+    pub msaa_target: Option<AttachmentImage>,
+    pub frames: Frames,
+    pub commands: CommandManager,
+    pub surface: SurfaceKHR,
+    pub surface_callbacks: Surface,
+    pub window: Arc<Window>,
+}
+
+impl WindowTarget {
+    pub fn swapchain(&self) -> &Swapchain {
+        self.swapchain
+            .as_ref()
+            .expect("window targets always have a swapchain outside of drop")
+    }
+}
+
+impl Drop for WindowTarget {
+    fn drop(&mut self) {
+        //the framebuffers point at the swapchain's views, and the surface
+        //has to outlive the swapchain made from it
+        self.framebuffers = None;
+        #[cfg(feature = "allocator")]
+        {
+            self.depth_buffer = None;
+            self.msaa_target = None;
+        }
+        self.swapchain = None;
+        //SAFETY: our owner waits for the device to go idle before dropping
+        //us and the swapchain made from the surface is gone
+        unsafe { self.surface_callbacks.destroy_surface(self.surface, None) }
+    }
+}
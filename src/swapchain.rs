@@ -0,0 +1,481 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{
+    extensions::khr::{self, Surface},
+    vk::{self, PhysicalDevice, SurfaceKHR},
+    Device, Instance,
+};
+
+use crate::physical_device::QueueFamilies;
+
+//Everything we need to know about the surface to build a swapchain for it
+pub(crate) struct SurfaceInfo<'a> {
+    pub surface_callbacks: &'a Surface,
+    pub surface: SurfaceKHR,
+    pub physical_device: PhysicalDevice,
+    pub queue_families: &'a QueueFamilies,
+    //size of the window in physical pixels. Only used if the surface lets
+    //us pick the extent
+    pub window_extent: vk::Extent2D,
+}
+
+pub struct Swapchain {
+    loader: khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    extent: vk::Extent2D,
+    format: vk::SurfaceFormatKHR,
+    present_mode: vk::PresentModeKHR,
+    usage: vk::ImageUsageFlags,
+    //kept so recreating picks the same kind of format
+    prefer_srgb: bool,
+    device: Device,
+}
+
+impl Swapchain {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        surface_info: &SurfaceInfo,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+        prefer_srgb: bool,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<Swapchain, vk::Result> {
+        let SurfaceInfo {
+            surface_callbacks,
+            surface,
+            physical_device,
+            queue_families,
+            window_extent,
+        } = *surface_info;
+        //SAFETY: physical_device and surface both belong to the instance the
+        //callbacks were loaded from
+        let (capabilities, formats, present_modes) = unsafe {
+            (
+                surface_callbacks.get_physical_device_surface_capabilities(
+                    physical_device,
+                    surface,
+                )?,
+                surface_callbacks.get_physical_device_surface_formats(
+                    physical_device,
+                    surface,
+                )?,
+                surface_callbacks.get_physical_device_surface_present_modes(
+                    physical_device,
+                    surface,
+                )?,
+            )
+        };
+
+        let format = choose_surface_format(&formats, prefer_srgb);
+        let present_mode =
+            choose_present_mode(&present_modes, preferred_present_mode);
+        let extent = choose_extent(&capabilities, window_extent);
+        let image_count = choose_image_count(&capabilities);
+        //being able to copy out of the images lets frames be captured, but
+        //surfaces don't have to allow it
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (capabilities.supported_usage_flags
+                & vk::ImageUsageFlags::TRANSFER_SRC);
+
+        let family_indices = [queue_families.graphics, queue_families.present];
+        let create_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(surface)
+            .min_image_count(image_count)
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(usage)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(choose_composite_alpha(&capabilities))
+            .present_mode(present_mode)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        //if we present from a different family than we draw on the images
+        //need to be shared between them
+        let create_info =
+            if queue_families.graphics != queue_families.present {
+                create_info
+                    .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(&family_indices)
+            } else {
+                create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            }
+            .build();
+
+        let loader = khr::Swapchain::new(instance, device);
+        //SAFETY: create_info was made with a builder from values the surface
+        //told us it supports
+        let swapchain = unsafe { loader.create_swapchain(&create_info, None) }?;
+
+        let destroy_swapchain = |e| {
+            //SAFETY: nothing else has been made from the swapchain yet
+            unsafe { loader.destroy_swapchain(swapchain, None) };
+            e
+        };
+        //SAFETY: swapchain was just created from this loader
+        let images = unsafe { loader.get_swapchain_images(swapchain) }
+            .map_err(destroy_swapchain)?;
+
+        let mut image_views = Vec::with_capacity(images.len());
+        for &image in &images {
+            match create_image_view(device, image, format.format) {
+                Ok(image_view) => image_views.push(image_view),
+                Err(e) => {
+                    //SAFETY: these views were made just above and haven't
+                    //been handed out to anything
+                    unsafe {
+                        for image_view in image_views {
+                            device.destroy_image_view(image_view, None);
+                        }
+                    }
+                    return Err(destroy_swapchain(e));
+                }
+            }
+        }
+
+        log::info!(
+            "Created swapchain {}x{} with {} images, format {:?}, \
+            present mode {:?}",
+            extent.width,
+            extent.height,
+            images.len(),
+            format,
+            present_mode
+        );
+
+        Ok(Swapchain {
+            loader,
+            swapchain,
+            images,
+            image_views,
+            extent,
+            format,
+            present_mode,
+            usage,
+            prefer_srgb,
+            device: device.clone(),
+        })
+    }
+
+    //Build a new swapchain in place of this one, e.g. after a resize. The
+    //old one is handed to the driver as old_swapchain so it can reuse what
+    //it can, then destroyed once the new one exists
+    pub(crate) fn recreate(
+        &mut self,
+        instance: &Instance,
+        surface_info: &SurfaceInfo,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+    ) -> Result<(), vk::Result> {
+        //SAFETY: we can't destroy anything the GPU might still be using
+        unsafe { self.device.device_wait_idle() }?;
+        let new_swapchain = Swapchain::new(
+            instance,
+            &self.device,
+            surface_info,
+            preferred_present_mode,
+            self.prefer_srgb,
+            self.swapchain,
+        )?;
+        //dropping the old one takes its views and the retired swapchain with
+        //it
+        *self = new_swapchain;
+        Ok(())
+    }
+
+    pub fn handle(&self) -> vk::SwapchainKHR {
+        self.swapchain
+    }
+
+    pub fn loader(&self) -> &khr::Swapchain {
+        &self.loader
+    }
+
+    pub fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+
+    pub fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    //The format picked according to RenderContextBuilder::prefer_srgb
+    pub fn format(&self) -> vk::SurfaceFormatKHR {
+        self.format
+    }
+
+    //What the images can be used for. Always includes COLOR_ATTACHMENT
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
+    }
+
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    pub(crate) fn prefer_srgb(&self) -> bool {
+        self.prefer_srgb
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        //SAFETY: the views and swapchain were made in new and whoever owns us
+        //makes sure the GPU is done with them before dropping
+        unsafe {
+            for &image_view in &self.image_views {
+                self.device.destroy_image_view(image_view, None);
+            }
+            self.loader.destroy_swapchain(self.swapchain, None);
+        }
+    }
+}
+
+fn create_image_view(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+) -> Result<vk::ImageView, vk::Result> {
+    let create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build(),
+        )
+        .build();
+    //SAFETY: image is a live swapchain image and create_info was made with a
+    //builder
+    unsafe { device.create_image_view(&create_info, None) }
+}
+
+//sRGB so we get correct gamma for free, unless prefer_srgb is false because
+//the shaders do their own gamma and want UNORM. Either way the other kind
+//comes next, and surfaces always report at least one format so falling
+//back to the first is fine
+pub(crate) fn choose_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    prefer_srgb: bool,
+) -> vk::SurfaceFormatKHR {
+    let srgb = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB];
+    let unorm = [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM];
+    let preferences = if prefer_srgb {
+        srgb.into_iter().chain(unorm)
+    } else {
+        unorm.into_iter().chain(srgb)
+    };
+    preferences
+        .flat_map(|preferred| {
+            formats.iter().copied().find(|format| {
+                format.format == preferred
+                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            })
+        })
+        .next()
+        .unwrap_or(formats[0])
+}
+
+//What choose_present_mode goes for when nothing was asked for, best first.
+//MAILBOX gives low latency without tearing and IMMEDIATE at least doesn't
+//wait on vblank. FIFO is guaranteed by the spec so there's always a match
+const PRESENT_MODE_PREFERENCES: [vk::PresentModeKHR; 3] = [
+    vk::PresentModeKHR::MAILBOX,
+    vk::PresentModeKHR::IMMEDIATE,
+    vk::PresentModeKHR::FIFO,
+];
+
+//Use what the user asked for if we can, otherwise the first of
+//PRESENT_MODE_PREFERENCES the surface supports
+pub(crate) fn choose_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+    preferred: Option<vk::PresentModeKHR>,
+) -> vk::PresentModeKHR {
+    if let Some(preferred) = preferred {
+        if present_modes.contains(&preferred) {
+            return preferred;
+        }
+        log::warn!(
+            "Present mode {:?} isn't supported by this surface",
+            preferred
+        );
+    }
+    //a driver leaving FIFO out of the list is broken, but FIFO is still
+    //the safest thing to ask it for
+    let chosen = PRESENT_MODE_PREFERENCES
+        .into_iter()
+        .find(|mode| present_modes.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+    if chosen != PRESENT_MODE_PREFERENCES[0] {
+        log::info!(
+            "Surface doesn't support {:?}, falling back to {:?}",
+            PRESENT_MODE_PREFERENCES[0],
+            chosen
+        );
+    }
+    chosen
+}
+
+//The closest thing to no vsync the surface supports. MAILBOX doesn't tear
+//so it goes first, and FIFO is all that's left if neither it nor
+//IMMEDIATE is there
+pub(crate) fn unsynced_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    [
+        vk::PresentModeKHR::MAILBOX,
+        vk::PresentModeKHR::IMMEDIATE,
+        vk::PresentModeKHR::FIFO_RELAXED,
+    ]
+    .into_iter()
+    .find(|mode| present_modes.contains(mode))
+    .unwrap_or_else(|| {
+        log::warn!("Surface only supports FIFO, vsync stays on");
+        vk::PresentModeKHR::FIFO
+    })
+}
+
+//The surface decides the extent unless current_extent is the u32::MAX
+//sentinel, in which case we go by the window and clamp to what's allowed
+pub(crate) fn choose_extent(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    window_extent: vk::Extent2D,
+) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        capabilities.current_extent
+    } else {
+        vk::Extent2D {
+            width: window_extent.width.clamp(
+                capabilities.min_image_extent.width,
+                capabilities.max_image_extent.width,
+            ),
+            height: window_extent.height.clamp(
+                capabilities.min_image_extent.height,
+                capabilities.max_image_extent.height,
+            ),
+        }
+    }
+}
+
+//One more than the minimum so we aren't stuck waiting on the driver. A max
+//of 0 means there is no limit
+pub(crate) fn choose_image_count(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+) -> u32 {
+    let image_count = capabilities.min_image_count + 1;
+    if capabilities.max_image_count != 0 {
+        image_count.min(capabilities.max_image_count)
+    } else {
+        image_count
+    }
+}
+
+//Opaque if the surface allows it, since we don't draw with transparency in
+//mind. Not every surface offers it though
+pub(crate) fn choose_composite_alpha(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+) -> vk::CompositeAlphaFlagsKHR {
+    //surfaces have to support at least one of these
+    [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+    ]
+    .into_iter()
+    .find(|&flag| capabilities.supported_composite_alpha.contains(flag))
+    .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::PresentModeKHR as Mode;
+
+    #[test]
+    fn present_mode_prefers_mailbox() {
+        assert_eq!(
+            choose_present_mode(&[Mode::MAILBOX, Mode::FIFO], None),
+            Mode::MAILBOX
+        );
+    }
+
+    #[test]
+    fn present_mode_falls_back_to_immediate_before_fifo() {
+        assert_eq!(
+            choose_present_mode(&[Mode::IMMEDIATE, Mode::FIFO], None),
+            Mode::IMMEDIATE
+        );
+    }
+
+    #[test]
+    fn present_mode_falls_back_to_fifo() {
+        assert_eq!(choose_present_mode(&[Mode::FIFO], None), Mode::FIFO);
+        assert_eq!(choose_present_mode(&[], None), Mode::FIFO);
+    }
+
+    #[test]
+    fn present_mode_honors_supported_preference() {
+        assert_eq!(
+            choose_present_mode(&[Mode::MAILBOX, Mode::FIFO], Some(Mode::FIFO)),
+            Mode::FIFO
+        );
+    }
+
+    #[test]
+    fn present_mode_ignores_unsupported_preference() {
+        assert_eq!(
+            choose_present_mode(
+                &[Mode::IMMEDIATE, Mode::FIFO],
+                Some(Mode::MAILBOX)
+            ),
+            Mode::IMMEDIATE
+        );
+    }
+
+    fn composite_alpha(
+        supported: vk::CompositeAlphaFlagsKHR,
+    ) -> vk::CompositeAlphaFlagsKHR {
+        choose_composite_alpha(&vk::SurfaceCapabilitiesKHR {
+            supported_composite_alpha: supported,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn composite_alpha_prefers_opaque() {
+        use vk::CompositeAlphaFlagsKHR as Alpha;
+        assert_eq!(
+            composite_alpha(Alpha::OPAQUE | Alpha::PRE_MULTIPLIED),
+            Alpha::OPAQUE
+        );
+        assert_eq!(
+            composite_alpha(Alpha::PRE_MULTIPLIED | Alpha::INHERIT),
+            Alpha::INHERIT
+        );
+        assert_eq!(
+            composite_alpha(Alpha::POST_MULTIPLIED | Alpha::PRE_MULTIPLIED),
+            Alpha::PRE_MULTIPLIED
+        );
+        assert_eq!(
+            composite_alpha(Alpha::POST_MULTIPLIED),
+            Alpha::POST_MULTIPLIED
+        );
+    }
+}
@@ -0,0 +1,569 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::path::Path;
+
+use ash::{vk, Device};
+
+use crate::{
+    barrier::{transition_image_layout, transition_subresource},
+    memory::{Image, MemoryLocation},
+    render_context::{RenderContext, RenderContextError},
+    sampler::{Sampler, SamplerBuilder},
+};
+
+//Textures are always decoded to 8 bit RGBA and treated as sRGB color
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+//A sampled image with a full mip chain, its view and a trilinear sampler
+//with as much anisotropy as the device allows. Ready to go in a combined
+//image sampler descriptor. Cubemaps have a single level and a sampler that
+//clamps to the edges instead
+pub struct Texture {
+    sampler: Sampler,
+    view: vk::ImageView,
+    image: Image,
+    mip_levels: u32,
+    device: Device,
+}
+
+impl Texture {
+    pub(crate) fn load(
+        render_context: &RenderContext,
+        path: &Path,
+    ) -> Result<Texture, RenderContextError> {
+        let pixels = image::open(path)
+            .map_err(|e| {
+                log::error!("Failed to load texture {}: {}", path.display(), e);
+                RenderContextError::TextureLoadFailed(e)
+            })?
+            .to_rgba8();
+        let (width, height) = pixels.dimensions();
+        Texture::from_rgba(
+            render_context,
+            width,
+            height,
+            pixels.as_raw(),
+            &path.display().to_string(),
+        )
+    }
+
+    //Upload width by height 8 bit sRGB RGBA pixels, row by row from the
+    //top. name is what the texture goes by in logs and graphics debuggers
+    pub(crate) fn from_rgba(
+        render_context: &RenderContext,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        name: &str,
+    ) -> Result<Texture, RenderContextError> {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize * 4,
+            "pixels don't match a {}x{} RGBA image",
+            width,
+            height
+        );
+        let device = render_context.device();
+
+        //blitting down the mip chain needs linear filtering on the format.
+        //Without it we're stuck with just the base level
+        let mip_levels = if render_context
+            .format_features(TEXTURE_FORMAT, vk::ImageTiling::OPTIMAL)
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            mip_level_count(width, height)
+        } else {
+            log::warn!(
+                "{:?} can't be linearly filtered, {} gets no mipmaps",
+                TEXTURE_FORMAT,
+                name
+            );
+            1
+        };
+
+        let mut staging = render_context.create_buffer(
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        staging.write(pixels);
+
+        let extent = vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        };
+        let image = render_context.create_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(TEXTURE_FORMAT)
+                .extent(extent)
+                .mip_levels(mip_levels)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(
+                    vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST
+                        | vk::ImageUsageFlags::SAMPLED,
+                )
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            MemoryLocation::GpuOnly,
+        )?;
+        render_context.set_object_name(image.handle(), name);
+
+        render_context
+            .commands()
+            .submit_one_time(
+                render_context.graphics_queue(),
+                |command_buffer| {
+                    record_upload(
+                        device,
+                        command_buffer,
+                        staging.handle(),
+                        image.handle(),
+                        width,
+                        height,
+                        mip_levels,
+                    )
+                },
+            )
+            .map_err(RenderContextError::UploadFailed)?;
+        //the upload has finished so staging can go
+        drop(staging);
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image.handle())
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(TEXTURE_FORMAT)
+            .subresource_range(color_range(0, mip_levels))
+            .build();
+        //SAFETY: the image was just made with a matching format
+        let view = unsafe { device.create_image_view(&view_info, None) }
+            .map_err(RenderContextError::ImageCreationFailed)?;
+
+        let sampler = match SamplerBuilder::new()
+            .lod_range(0.0, mip_levels as f32)
+            .build(render_context)
+        {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                //SAFETY: nothing has used the view yet
+                unsafe { device.destroy_image_view(view, None) };
+                return Err(e);
+            }
+        };
+
+        log::debug!(
+            "Loaded texture {} ({}x{}, {} mip levels)",
+            name,
+            width,
+            height,
+            mip_levels
+        );
+        Ok(Texture {
+            sampler,
+            view,
+            image,
+            mip_levels,
+            device: device.clone(),
+        })
+    }
+
+    //The six faces in +X, -X, +Y, -Y, +Z, -Z order, as array layers of one
+    //image with a cube view
+    pub(crate) fn load_cubemap(
+        render_context: &RenderContext,
+        paths: [&Path; 6],
+    ) -> Result<Texture, RenderContextError> {
+        let mut faces: Vec<image::DynamicImage> =
+            Vec::with_capacity(paths.len());
+        for (face, path) in paths.iter().enumerate() {
+            let pixels = image::open(path).map_err(|e| {
+                log::error!(
+                    "Failed to load cubemap face {}: {}",
+                    path.display(),
+                    e
+                );
+                RenderContextError::TextureLoadFailed(e)
+            })?;
+            //cube faces have to be square, and the same size as each other
+            //to fit in one image
+            let (width, height) = (pixels.width(), pixels.height());
+            let mismatched = match faces.first() {
+                Some(first) => {
+                    (first.width(), first.height()) != (width, height)
+                        || first.color() != pixels.color()
+                }
+                None => width != height,
+            };
+            if mismatched {
+                log::error!(
+                    "Cubemap face {} is {}x{} {:?}, which doesn't fit with \
+                    the other faces",
+                    path.display(),
+                    width,
+                    height,
+                    pixels.color()
+                );
+                return Err(RenderContextError::MismatchedCubemapFace(face));
+            }
+            faces.push(pixels);
+        }
+        let size = faces[0].width();
+        let device = render_context.device();
+
+        let face_bytes = (size * size * 4) as vk::DeviceSize;
+        let mut staging = render_context.create_buffer(
+            face_bytes * faces.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let pixels: Vec<u8> = faces
+            .iter()
+            .flat_map(|face| face.to_rgba8().into_raw())
+            .collect();
+        staging.write(&pixels);
+
+        let image = render_context.create_image(
+            &vk::ImageCreateInfo::builder()
+                .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(TEXTURE_FORMAT)
+                .extent(vk::Extent3D {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(6)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(
+                    vk::ImageUsageFlags::TRANSFER_DST
+                        | vk::ImageUsageFlags::SAMPLED,
+                )
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            MemoryLocation::GpuOnly,
+        )?;
+        render_context.set_object_name(
+            image.handle(),
+            &format!("cubemap {}", paths[0].display()),
+        );
+
+        let regions: Vec<_> = (0..6)
+            .map(|face| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(face as vk::DeviceSize * face_bytes)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: face,
+                        layer_count: 1,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: size,
+                        height: size,
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect();
+        render_context
+            .commands()
+            .submit_one_time(
+                render_context.graphics_queue(),
+                |command_buffer| {
+                    transition_image_layout(
+                        device,
+                        command_buffer,
+                        image.handle(),
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageAspectFlags::COLOR,
+                        1,
+                    )
+                    .expect("cubemap uploads only make supported transitions");
+                    //SAFETY: the staging buffer holds all six faces one
+                    //after another and every layer was just moved to
+                    //TRANSFER_DST_OPTIMAL
+                    unsafe {
+                        device.cmd_copy_buffer_to_image(
+                            command_buffer,
+                            staging.handle(),
+                            image.handle(),
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &regions,
+                        )
+                    }
+                    transition_image_layout(
+                        device,
+                        command_buffer,
+                        image.handle(),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageAspectFlags::COLOR,
+                        1,
+                    )
+                    .expect("cubemap uploads only make supported transitions");
+                },
+            )
+            .map_err(RenderContextError::UploadFailed)?;
+        //the upload has finished so staging can go
+        drop(staging);
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image.handle())
+            .view_type(vk::ImageViewType::CUBE)
+            .format(TEXTURE_FORMAT)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 6,
+            })
+            .build();
+        //SAFETY: the image was just made cube compatible with six layers and
+        //a matching format
+        let view = unsafe { device.create_image_view(&view_info, None) }
+            .map_err(RenderContextError::ImageCreationFailed)?;
+
+        let sampler = match SamplerBuilder::new()
+            .address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .lod_range(0.0, 1.0)
+            .build(render_context)
+        {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                //SAFETY: nothing has used the view yet
+                unsafe { device.destroy_image_view(view, None) };
+                return Err(e);
+            }
+        };
+
+        log::debug!(
+            "Loaded cubemap {} ({}x{} faces)",
+            paths[0].display(),
+            size,
+            size
+        );
+        Ok(Texture {
+            sampler,
+            view,
+            image,
+            mip_levels: 1,
+            device: device.clone(),
+        })
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler.handle()
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    //What a combined image sampler descriptor needs to point at us
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.handle(),
+            image_view: self.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure the GPU is done with the texture.
+        //The sampler and image are dropped after this
+        unsafe { self.device.destroy_image_view(self.view, None) }
+    }
+}
+
+//Enough levels to halve the largest side all the way down to 1
+fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+fn color_range(
+    base_mip_level: u32,
+    level_count: u32,
+) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level,
+        level_count,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+//Move level_count levels of image starting at mip_level between layouts
+fn barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    mip_level: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    level_count: u32,
+) {
+    transition_subresource(
+        device,
+        command_buffer,
+        image,
+        old_layout,
+        new_layout,
+        color_range(mip_level, level_count),
+    )
+    .expect("texture uploads only make supported layout transitions")
+}
+
+//Copy the pixels into level 0 then blit each level down into the next,
+//leaving every level ready to sample from fragment shaders
+fn record_upload(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    staging: vk::Buffer,
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) {
+    let transfer_dst = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+    let transfer_src = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+    let shader_read = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+    barrier(
+        device,
+        command_buffer,
+        image,
+        0,
+        vk::ImageLayout::UNDEFINED,
+        transfer_dst,
+        mip_levels,
+    );
+    let region = vk::BufferImageCopy::builder()
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .build();
+    //SAFETY: the staging buffer holds width * height RGBA pixels and level 0
+    //was just moved to TRANSFER_DST_OPTIMAL
+    unsafe {
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        )
+    }
+
+    let mut level_width = width as i32;
+    let mut level_height = height as i32;
+    for level in 1..mip_levels {
+        barrier(
+            device,
+            command_buffer,
+            image,
+            level - 1,
+            transfer_dst,
+            transfer_src,
+            1,
+        );
+        let next_width = (level_width / 2).max(1);
+        let next_height = (level_height / 2).max(1);
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level - 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: level_width,
+                    y: level_height,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: next_width,
+                    y: next_height,
+                    z: 1,
+                },
+            ])
+            .build();
+        //SAFETY: the source level is in TRANSFER_SRC_OPTIMAL and the
+        //destination level is still in TRANSFER_DST_OPTIMAL
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            )
+        }
+        barrier(
+            device,
+            command_buffer,
+            image,
+            level - 1,
+            transfer_src,
+            shader_read,
+            1,
+        );
+        level_width = next_width;
+        level_height = next_height;
+    }
+    //the last level was only ever written to
+    barrier(
+        device,
+        command_buffer,
+        image,
+        mip_levels - 1,
+        transfer_dst,
+        shader_read,
+        1,
+    );
+}
@@ -0,0 +1,119 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use ash::{extensions::khr, vk, Device};
+
+//Timeline semaphores are core in 1.2 and an extension before that, with
+//the same entry points behind a different loader
+#[derive(Clone)]
+pub(crate) enum TimelineFns {
+    Core,
+    Khr(khr::TimelineSemaphore),
+}
+
+//A semaphore holding a counter that only goes up. Queue submissions and the
+//host can both signal it to a value and wait for it to reach one, and any
+//number of waits can be outstanding at once, unlike binary semaphores
+pub struct TimelineSemaphore {
+    semaphore: vk::Semaphore,
+    fns: TimelineFns,
+    device: Device,
+}
+
+impl TimelineSemaphore {
+    pub(crate) fn new(
+        device: &Device,
+        fns: &TimelineFns,
+        initial_value: u64,
+    ) -> Result<TimelineSemaphore, vk::Result> {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let create_info =
+            vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+        //SAFETY: create_info was made with a builder and type_info outlives
+        //the call
+        let semaphore = unsafe { device.create_semaphore(&create_info, None) }?;
+        Ok(TimelineSemaphore {
+            semaphore,
+            fns: fns.clone(),
+            device: device.clone(),
+        })
+    }
+
+    pub fn handle(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+
+    //The counter's current value
+    pub fn value(&self) -> Result<u64, vk::Result> {
+        //SAFETY: the semaphore is ours and alive
+        unsafe {
+            match &self.fns {
+                TimelineFns::Core => {
+                    self.device.get_semaphore_counter_value(self.semaphore)
+                }
+                TimelineFns::Khr(loader) => {
+                    loader.get_semaphore_counter_value(self.semaphore)
+                }
+            }
+        }
+    }
+
+    //Set the counter from the host. value has to be more than the current
+    //value and than any pending signal
+    pub fn signal(&self, value: u64) -> Result<(), vk::Result> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value)
+            .build();
+        //SAFETY: signal_info was made with a builder
+        unsafe {
+            match &self.fns {
+                TimelineFns::Core => self.device.signal_semaphore(&signal_info),
+                TimelineFns::Khr(loader) => {
+                    loader.signal_semaphore(&signal_info)
+                }
+            }
+        }
+    }
+
+    //Block until the counter reaches value or timeout nanoseconds pass.
+    //Ok(false) means it timed out
+    pub fn wait(&self, value: u64, timeout: u64) -> Result<bool, vk::Result> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+        //SAFETY: wait_info was made with a builder and the arrays outlive
+        //the call
+        let result = unsafe {
+            match &self.fns {
+                TimelineFns::Core => {
+                    self.device.wait_semaphores(&wait_info, timeout)
+                }
+                TimelineFns::Khr(loader) => {
+                    loader.wait_semaphores(&wait_info, timeout)
+                }
+            }
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        //SAFETY: whoever owns us makes sure nothing pending signals or waits
+        //on the semaphore before dropping us
+        unsafe { self.device.destroy_semaphore(self.semaphore, None) }
+    }
+}
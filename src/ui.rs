@@ -0,0 +1,594 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::{collections::HashMap, mem::offset_of, time::Instant};
+
+use ash::vk;
+use egui::{
+    epaint::{ImageDelta, Primitive, Vertex},
+    ClippedPrimitive, Color32, Event, FullOutput, ImageData, Key, Modifiers,
+    PlatformOutput, PointerButton, Pos2, RawInput, Rect, TextureFilter,
+    TextureId, Vec2,
+};
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+use crate::{
+    app::FrameContext,
+    descriptor::{
+        DescriptorPool, DescriptorSetLayout, DescriptorSetLayoutBuilder,
+    },
+    frame::FrameRecorder,
+    memory::FrameArena,
+    pipeline::{GraphicsPipelineBuilder, Pipeline},
+    render_context::{RenderContext, RenderContextError},
+    sampler::{Sampler, SamplerBuilder},
+    shader::ShaderModule,
+    texture::Texture,
+};
+
+//How many points of scrolling one line of mouse wheel is worth. Same as
+//egui's own winit integration
+const POINTS_PER_LINE: f32 = 50.0;
+
+//The egui shaders, assembled by hand like debug_draw's. They're equivalent
+//to
+//
+//  layout(push_constant) uniform Push { vec2 screen_size; };
+//  layout(location = 0) in vec2 pos;
+//  layout(location = 1) in vec2 uv;
+//  layout(location = 2) in vec4 color;
+//  layout(location = 0) out vec2 out_uv;
+//  layout(location = 1) out vec4 out_color;
+//  void main() {
+//      vec2 ndc = pos * 2.0 / screen_size - vec2(1.0);
+//      gl_Position = vec4(ndc, 0.0, 1.0);
+//      out_uv = uv;
+//      out_color = vec4(pow(color.rgb, vec3(2.2)), color.a);
+//  }
+//
+//and
+//
+//  layout(set = 0, binding = 0) uniform sampler2D tex;
+//  layout(location = 0) in vec2 uv;
+//  layout(location = 1) in vec4 color;
+//  layout(location = 0) out vec4 out_color;
+//  void main() { out_color = color * texture(tex, uv); }
+
+const EGUI_VERT: &[u32] = &[
+    0x07230203, 0x00010000, 0x00000000, 0x0000002f, 0x00000000, 0x00020011,
+    0x00000001, 0x0006000b, 0x00000001, 0x4c534c47, 0x6474732e, 0x3035342e,
+    0x00000000, 0x0003000e, 0x00000000, 0x00000001, 0x000b000f, 0x00000000,
+    0x00000002, 0x6e69616d, 0x00000000, 0x00000003, 0x00000004, 0x00000005,
+    0x00000006, 0x00000007, 0x00000008, 0x00040047, 0x00000003, 0x0000001e,
+    0x00000000, 0x00040047, 0x00000004, 0x0000001e, 0x00000001, 0x00040047,
+    0x00000005, 0x0000001e, 0x00000002, 0x00040047, 0x00000006, 0x0000001e,
+    0x00000000, 0x00040047, 0x00000007, 0x0000001e, 0x00000001, 0x00040047,
+    0x00000008, 0x0000000b, 0x00000000, 0x00030047, 0x00000009, 0x00000002,
+    0x00050048, 0x00000009, 0x00000000, 0x00000023, 0x00000000, 0x00020013,
+    0x0000000a, 0x00030021, 0x0000000b, 0x0000000a, 0x00030016, 0x0000000c,
+    0x00000020, 0x00040017, 0x0000000d, 0x0000000c, 0x00000002, 0x00040017,
+    0x0000000e, 0x0000000c, 0x00000003, 0x00040017, 0x0000000f, 0x0000000c,
+    0x00000004, 0x0003001e, 0x00000009, 0x0000000d, 0x00040020, 0x00000010,
+    0x00000009, 0x00000009, 0x0004003b, 0x00000010, 0x00000011, 0x00000009,
+    0x00040015, 0x00000012, 0x00000020, 0x00000001, 0x0004002b, 0x00000012,
+    0x00000013, 0x00000000, 0x00040020, 0x00000014, 0x00000009, 0x0000000d,
+    0x00040020, 0x00000015, 0x00000001, 0x0000000d, 0x00040020, 0x00000016,
+    0x00000001, 0x0000000f, 0x00040020, 0x00000017, 0x00000003, 0x0000000d,
+    0x00040020, 0x00000018, 0x00000003, 0x0000000f, 0x0004003b, 0x00000015,
+    0x00000003, 0x00000001, 0x0004003b, 0x00000015, 0x00000004, 0x00000001,
+    0x0004003b, 0x00000016, 0x00000005, 0x00000001, 0x0004003b, 0x00000017,
+    0x00000006, 0x00000003, 0x0004003b, 0x00000018, 0x00000007, 0x00000003,
+    0x0004003b, 0x00000018, 0x00000008, 0x00000003, 0x0004002b, 0x0000000c,
+    0x00000019, 0x00000000, 0x0004002b, 0x0000000c, 0x0000001a, 0x3f800000,
+    0x0004002b, 0x0000000c, 0x0000001b, 0x40000000, 0x0004002b, 0x0000000c,
+    0x0000001c, 0x400ccccd, 0x0006002c, 0x0000000e, 0x0000001d, 0x0000001c,
+    0x0000001c, 0x0000001c, 0x0005002c, 0x0000000d, 0x0000001e, 0x0000001a,
+    0x0000001a, 0x00050036, 0x0000000a, 0x00000002, 0x00000000, 0x0000000b,
+    0x000200f8, 0x0000001f, 0x00050041, 0x00000014, 0x00000020, 0x00000011,
+    0x00000013, 0x0004003d, 0x0000000d, 0x00000021, 0x00000020, 0x0004003d,
+    0x0000000d, 0x00000022, 0x00000003, 0x0005008e, 0x0000000d, 0x00000023,
+    0x00000022, 0x0000001b, 0x00050088, 0x0000000d, 0x00000024, 0x00000023,
+    0x00000021, 0x00050083, 0x0000000d, 0x00000025, 0x00000024, 0x0000001e,
+    0x00050051, 0x0000000c, 0x00000026, 0x00000025, 0x00000000, 0x00050051,
+    0x0000000c, 0x00000027, 0x00000025, 0x00000001, 0x00070050, 0x0000000f,
+    0x00000028, 0x00000026, 0x00000027, 0x00000019, 0x0000001a, 0x0003003e,
+    0x00000008, 0x00000028, 0x0004003d, 0x0000000d, 0x00000029, 0x00000004,
+    0x0003003e, 0x00000006, 0x00000029, 0x0004003d, 0x0000000f, 0x0000002a,
+    0x00000005, 0x0008004f, 0x0000000e, 0x0000002b, 0x0000002a, 0x0000002a,
+    0x00000000, 0x00000001, 0x00000002, 0x0007000c, 0x0000000e, 0x0000002c,
+    0x00000001, 0x0000001a, 0x0000002b, 0x0000001d, 0x00050051, 0x0000000c,
+    0x0000002d, 0x0000002a, 0x00000003, 0x00050050, 0x0000000f, 0x0000002e,
+    0x0000002c, 0x0000002d, 0x0003003e, 0x00000007, 0x0000002e, 0x000100fd,
+    0x00010038,
+];
+
+const EGUI_FRAG: &[u32] = &[
+    0x07230203, 0x00010000, 0x00000000, 0x00000017, 0x00000000, 0x00020011,
+    0x00000001, 0x0003000e, 0x00000000, 0x00000001, 0x0008000f, 0x00000004,
+    0x00000001, 0x6e69616d, 0x00000000, 0x00000002, 0x00000003, 0x00000004,
+    0x00030010, 0x00000001, 0x00000007, 0x00040047, 0x00000002, 0x0000001e,
+    0x00000000, 0x00040047, 0x00000003, 0x0000001e, 0x00000001, 0x00040047,
+    0x00000004, 0x0000001e, 0x00000000, 0x00040047, 0x00000005, 0x00000022,
+    0x00000000, 0x00040047, 0x00000005, 0x00000021, 0x00000000, 0x00020013,
+    0x00000006, 0x00030021, 0x00000007, 0x00000006, 0x00030016, 0x00000008,
+    0x00000020, 0x00040017, 0x00000009, 0x00000008, 0x00000002, 0x00040017,
+    0x0000000a, 0x00000008, 0x00000004, 0x00090019, 0x0000000b, 0x00000008,
+    0x00000001, 0x00000000, 0x00000000, 0x00000000, 0x00000001, 0x00000000,
+    0x0003001b, 0x0000000c, 0x0000000b, 0x00040020, 0x0000000d, 0x00000000,
+    0x0000000c, 0x0004003b, 0x0000000d, 0x00000005, 0x00000000, 0x00040020,
+    0x0000000e, 0x00000001, 0x00000009, 0x00040020, 0x0000000f, 0x00000001,
+    0x0000000a, 0x00040020, 0x00000010, 0x00000003, 0x0000000a, 0x0004003b,
+    0x0000000e, 0x00000002, 0x00000001, 0x0004003b, 0x0000000f, 0x00000003,
+    0x00000001, 0x0004003b, 0x00000010, 0x00000004, 0x00000003, 0x00050036,
+    0x00000006, 0x00000001, 0x00000000, 0x00000007, 0x000200f8, 0x00000011,
+    0x0004003d, 0x0000000c, 0x00000012, 0x00000005, 0x0004003d, 0x00000009,
+    0x00000013, 0x00000002, 0x00050057, 0x0000000a, 0x00000014, 0x00000012,
+    0x00000013, 0x0004003d, 0x0000000a, 0x00000015, 0x00000003, 0x00050085,
+    0x0000000a, 0x00000016, 0x00000015, 0x00000014, 0x0003003e, 0x00000004,
+    0x00000016, 0x000100fd, 0x00010038,
+];
+
+//One of egui's textures. Kept on the CPU as well so patches can be applied
+//and the whole thing uploaded again
+struct UiTexture {
+    pixels: Vec<Color32>,
+    size: [usize; 2],
+    //one per frame in flight, all pointing at texture
+    sets: Vec<vk::DescriptorSet>,
+    _pool: DescriptorPool,
+    _texture: Texture,
+}
+
+//Draws egui in the context's render pass. Each frame feed raw_input to
+//egui::Context::run, hand what it returns to prepare before
+//RenderContext::draw_frame, then call paint from the draw callback after
+//everything else so the UI ends up on top. Colors come out right on sRGB
+//swapchains, which RenderContextBuilder::prefer_srgb picks by default.
+//egui's paint callbacks aren't supported and get skipped
+pub struct UiRenderer {
+    pipeline: Pipeline,
+    layout: DescriptorSetLayout,
+    linear: Sampler,
+    nearest: Sampler,
+    textures: HashMap<TextureId, UiTexture>,
+    //textures egui is done with, and the frame number they were last used
+    //by. Dropped once that frame is done
+    retired: Vec<(u64, UiTexture)>,
+    //freed by the last prepare, but the frame after it still draws with
+    //them
+    pending_free: Vec<TextureId>,
+    primitives: Vec<ClippedPrimitive>,
+    pixels_per_point: f32,
+    max_texture_side: usize,
+    start: Instant,
+}
+
+impl UiRenderer {
+    //Build the UI pipeline for the context's own render pass
+    pub fn new(
+        render_context: &RenderContext,
+    ) -> Result<UiRenderer, RenderContextError> {
+        let device = render_context.device();
+        let vertex_shader = ShaderModule::new(device, EGUI_VERT)
+            .map_err(RenderContextError::ShaderModuleCreationFailed)?;
+        let fragment_shader = ShaderModule::new(device, EGUI_FRAG)
+            .map_err(RenderContextError::ShaderModuleCreationFailed)?;
+        let layout = DescriptorSetLayoutBuilder::new()
+            .add_combined_image_sampler(0, vk::ShaderStageFlags::FRAGMENT)
+            .build(render_context)?;
+        let pipeline = GraphicsPipelineBuilder::new(
+            &vertex_shader,
+            &fragment_shader,
+            render_context.render_pass(),
+        )
+        .vertex_input(
+            &[vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<Vertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            &[
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: offset_of!(Vertex, pos) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: offset_of!(Vertex, uv) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 2,
+                    binding: 0,
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    offset: offset_of!(Vertex, color) as u32,
+                },
+            ],
+        )
+        .cull_mode(vk::CullModeFlags::NONE)
+        .premultiplied_alpha_blending(true)
+        .depth_test(false)
+        .descriptor_set_layouts(&[&layout])
+        .push_constant_range(
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            std::mem::size_of::<[f32; 2]>() as u32,
+        )
+        .name("egui")
+        .build(render_context)?;
+        //egui's textures have no mip levels worth sampling
+        let sampler = |filter| {
+            SamplerBuilder::new()
+                .filter(filter)
+                .address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .anisotropy(1.0)
+                .lod_range(0.0, 0.0)
+                .build(render_context)
+        };
+        Ok(UiRenderer {
+            pipeline,
+            layout,
+            linear: sampler(vk::Filter::LINEAR)?,
+            nearest: sampler(vk::Filter::NEAREST)?,
+            textures: HashMap::new(),
+            retired: Vec::new(),
+            pending_free: Vec::new(),
+            primitives: Vec::new(),
+            pixels_per_point: 1.0,
+            max_texture_side: render_context.max_image_dimension_2d() as usize,
+            start: Instant::now(),
+        })
+    }
+
+    //This frame's input for egui, built from the context's input state
+    pub fn raw_input(&self, frame: &FrameContext) -> RawInput {
+        let scale = frame.scale_factor as f32;
+        let input = frame.input;
+        let modifiers = egui_modifiers(input.modifiers());
+        let mut events = Vec::new();
+        let pointer = input
+            .mouse_position()
+            .map(|(x, y)| Pos2::new(x as f32 / scale, y as f32 / scale));
+        match pointer {
+            Some(pos) => {
+                events.push(Event::PointerMoved(pos));
+                let buttons = input
+                    .pressed_buttons()
+                    .iter()
+                    .map(|&button| (button, true))
+                    .chain(
+                        input
+                            .released_buttons()
+                            .iter()
+                            .map(|&button| (button, false)),
+                    );
+                for (button, pressed) in buttons {
+                    if let Some(button) = egui_button(button) {
+                        events.push(Event::PointerButton {
+                            pos,
+                            button,
+                            pressed,
+                            modifiers,
+                        });
+                    }
+                }
+            }
+            None => events.push(Event::PointerGone),
+        }
+        let (scroll_x, scroll_y) = input.scroll_delta();
+        if scroll_x != 0.0 || scroll_y != 0.0 {
+            events.push(Event::Scroll(
+                Vec2::new(scroll_x, scroll_y) * POINTS_PER_LINE,
+            ));
+        }
+        let keys = input
+            .pressed_keys()
+            .iter()
+            .map(|&key| (key, true))
+            .chain(input.released_keys().iter().map(|&key| (key, false)));
+        for (key, pressed) in keys {
+            if let Some(key) = egui_key(key) {
+                events.push(Event::Key {
+                    key,
+                    pressed,
+                    modifiers,
+                });
+            }
+        }
+        if !input.text().is_empty() {
+            events.push(Event::Text(input.text().to_string()));
+        }
+
+        let size = frame.window.inner_size();
+        RawInput {
+            screen_rect: Some(Rect::from_min_size(
+                Pos2::ZERO,
+                Vec2::new(size.width as f32, size.height as f32) / scale,
+            )),
+            pixels_per_point: Some(scale),
+            max_texture_side: Some(self.max_texture_side),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers,
+            events,
+            ..RawInput::default()
+        }
+    }
+
+    //Upload the textures egui changed and tessellate its shapes for the
+    //next paint. Returns what egui wants done with the window, e.g. the
+    //cursor icon. Blocks while new textures upload
+    pub fn prepare(
+        &mut self,
+        render_context: &RenderContext,
+        ctx: &egui::Context,
+        output: FullOutput,
+    ) -> Result<PlatformOutput, RenderContextError> {
+        let submitted = render_context.frames_submitted();
+        let in_flight = render_context.frames_in_flight() as u64;
+        self.retired
+            .retain(|(frame, _)| frame + in_flight > submitted);
+        //the last paint was for the frame submitted since
+        for id in std::mem::take(&mut self.pending_free) {
+            if let Some(texture) = self.textures.remove(&id) {
+                self.retired.push((submitted, texture));
+            }
+        }
+        for (id, delta) in &output.textures_delta.set {
+            self.set_texture(render_context, *id, delta)?;
+        }
+        self.pending_free = output.textures_delta.free;
+        self.primitives = ctx.tessellate(output.shapes);
+        self.pixels_per_point = ctx.pixels_per_point();
+        Ok(output.platform_output)
+    }
+
+    fn set_texture(
+        &mut self,
+        render_context: &RenderContext,
+        id: TextureId,
+        delta: &ImageDelta,
+    ) -> Result<(), RenderContextError> {
+        let patch: Vec<Color32> = match &delta.image {
+            ImageData::Color(image) => image.pixels.clone(),
+            ImageData::Font(image) => image.srgba_pixels(1.0).collect(),
+        };
+        let [width, height] = delta.image.size();
+        let (pixels, size) = match delta.pos {
+            None => (patch, [width, height]),
+            Some([x, y]) => {
+                let Some(old) = self.textures.get(&id) else {
+                    log::warn!("egui patched {:?}, which doesn't exist", id);
+                    return Ok(());
+                };
+                let mut pixels = old.pixels.clone();
+                for (row, patch_row) in patch.chunks_exact(width).enumerate() {
+                    let start = (y + row) * old.size[0] + x;
+                    pixels[start..start + width].copy_from_slice(patch_row);
+                }
+                (pixels, old.size)
+            }
+        };
+
+        let bytes: Vec<u8> =
+            pixels.iter().flat_map(|pixel| pixel.to_array()).collect();
+        let texture = Texture::from_rgba(
+            render_context,
+            size[0] as u32,
+            size[1] as u32,
+            &bytes,
+            &format!("egui {:?}", id),
+        )?;
+        let pool = DescriptorPool::new(render_context, &self.layout)?;
+        let sets = pool.allocate_sets(&self.layout)?;
+        let sampler = match delta.filter {
+            TextureFilter::Nearest => &self.nearest,
+            TextureFilter::Linear => &self.linear,
+        };
+        for &set in &sets {
+            pool.update_sampler(set, 0, texture.view(), sampler.handle());
+        }
+        let texture = UiTexture {
+            pixels,
+            size,
+            sets,
+            _pool: pool,
+            _texture: texture,
+        };
+        //frames already submitted may still be drawing with the old one
+        if let Some(old) = self.textures.insert(id, texture) {
+            self.retired.push((render_context.frames_submitted(), old));
+        }
+        Ok(())
+    }
+
+    //Draw what the last prepare tessellated, copying the meshes into
+    //arena. arena has to have been reset for this frame already. Meshes
+    //that don't fit are dropped with a warning
+    pub fn paint(&self, recorder: &FrameRecorder, arena: &mut FrameArena) {
+        if self.primitives.is_empty() {
+            return;
+        }
+        let extent = recorder.extent();
+        let scale = self.pixels_per_point;
+        recorder.bind_pipeline(&self.pipeline);
+        recorder.push_constants(
+            &[extent.width as f32 / scale, extent.height as f32 / scale],
+            vk::ShaderStageFlags::VERTEX,
+        );
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in &self.primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            if mesh.is_empty() {
+                continue;
+            }
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                log::warn!(
+                    "egui drew with {:?}, which doesn't exist",
+                    mesh.texture_id
+                );
+                continue;
+            };
+            //clip rects are in points and can hang off the screen
+            let min_x = (clip_rect.min.x * scale)
+                .round()
+                .clamp(0.0, extent.width as f32) as u32;
+            let min_y = (clip_rect.min.y * scale)
+                .round()
+                .clamp(0.0, extent.height as f32)
+                as u32;
+            let max_x = (clip_rect.max.x * scale)
+                .round()
+                .clamp(min_x as f32, extent.width as f32)
+                as u32;
+            let max_y = (clip_rect.max.y * scale)
+                .round()
+                .clamp(min_y as f32, extent.height as f32)
+                as u32;
+            if min_x == max_x || min_y == max_y {
+                continue;
+            }
+            let (Some(vertices), Some(indices)) = (
+                arena.alloc_slice(&mesh.vertices),
+                arena.alloc_slice(&mesh.indices),
+            ) else {
+                log::warn!(
+                    "egui doesn't fit in what's left of the frame arena"
+                );
+                break;
+            };
+            recorder.set_scissor(vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min_x as i32,
+                    y: min_y as i32,
+                },
+                extent: vk::Extent2D {
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                },
+            });
+            recorder
+                .bind_descriptor_set(0, texture.sets[recorder.frame_index()]);
+            recorder.bind_vertex_slice(0, vertices);
+            recorder.bind_index_slice(indices, vk::IndexType::UINT32);
+            recorder.draw_indexed_instanced(
+                mesh.indices.len() as u32,
+                1,
+                0,
+                0,
+                0,
+            );
+        }
+        recorder.reset_viewport();
+    }
+}
+
+fn egui_modifiers(modifiers: ModifiersState) -> Modifiers {
+    Modifiers {
+        alt: modifiers.alt(),
+        ctrl: modifiers.ctrl(),
+        shift: modifiers.shift(),
+        mac_cmd: cfg!(target_os = "macos") && modifiers.logo(),
+        command: if cfg!(target_os = "macos") {
+            modifiers.logo()
+        } else {
+            modifiers.ctrl()
+        },
+    }
+}
+
+fn egui_button(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        MouseButton::Middle => Some(PointerButton::Middle),
+        MouseButton::Other(_) => None,
+    }
+}
+
+fn egui_key(key: VirtualKeyCode) -> Option<Key> {
+    Some(match key {
+        VirtualKeyCode::Down => Key::ArrowDown,
+        VirtualKeyCode::Left => Key::ArrowLeft,
+        VirtualKeyCode::Right => Key::ArrowRight,
+        VirtualKeyCode::Up => Key::ArrowUp,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::Key0 => Key::Num0,
+        VirtualKeyCode::Key1 => Key::Num1,
+        VirtualKeyCode::Key2 => Key::Num2,
+        VirtualKeyCode::Key3 => Key::Num3,
+        VirtualKeyCode::Key4 => Key::Num4,
+        VirtualKeyCode::Key5 => Key::Num5,
+        VirtualKeyCode::Key6 => Key::Num6,
+        VirtualKeyCode::Key7 => Key::Num7,
+        VirtualKeyCode::Key8 => Key::Num8,
+        VirtualKeyCode::Key9 => Key::Num9,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::B => Key::B,
+        VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::D => Key::D,
+        VirtualKeyCode::E => Key::E,
+        VirtualKeyCode::F => Key::F,
+        VirtualKeyCode::G => Key::G,
+        VirtualKeyCode::H => Key::H,
+        VirtualKeyCode::I => Key::I,
+        VirtualKeyCode::J => Key::J,
+        VirtualKeyCode::K => Key::K,
+        VirtualKeyCode::L => Key::L,
+        VirtualKeyCode::M => Key::M,
+        VirtualKeyCode::N => Key::N,
+        VirtualKeyCode::O => Key::O,
+        VirtualKeyCode::P => Key::P,
+        VirtualKeyCode::Q => Key::Q,
+        VirtualKeyCode::R => Key::R,
+        VirtualKeyCode::S => Key::S,
+        VirtualKeyCode::T => Key::T,
+        VirtualKeyCode::U => Key::U,
+        VirtualKeyCode::V => Key::V,
+        VirtualKeyCode::W => Key::W,
+        VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Y => Key::Y,
+        VirtualKeyCode::Z => Key::Z,
+        VirtualKeyCode::F1 => Key::F1,
+        VirtualKeyCode::F2 => Key::F2,
+        VirtualKeyCode::F3 => Key::F3,
+        VirtualKeyCode::F4 => Key::F4,
+        VirtualKeyCode::F5 => Key::F5,
+        VirtualKeyCode::F6 => Key::F6,
+        VirtualKeyCode::F7 => Key::F7,
+        VirtualKeyCode::F8 => Key::F8,
+        VirtualKeyCode::F9 => Key::F9,
+        VirtualKeyCode::F10 => Key::F10,
+        VirtualKeyCode::F11 => Key::F11,
+        VirtualKeyCode::F12 => Key::F12,
+        VirtualKeyCode::F13 => Key::F13,
+        VirtualKeyCode::F14 => Key::F14,
+        VirtualKeyCode::F15 => Key::F15,
+        VirtualKeyCode::F16 => Key::F16,
+        VirtualKeyCode::F17 => Key::F17,
+        VirtualKeyCode::F18 => Key::F18,
+        VirtualKeyCode::F19 => Key::F19,
+        VirtualKeyCode::F20 => Key::F20,
+        _ => return None,
+    })
+}
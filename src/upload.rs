@@ -0,0 +1,551 @@
+/*
+This Source Code Form is subject to the terms of the Mozilla Public
+License, v. 2.0. If a copy of the MPL was not distributed with this
+file, You can obtain one at https://mozilla.org/MPL/2.0/.
+*/
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+
+use ash::{vk, Device};
+
+use crate::{
+    memory::{
+        Buffer, IndexBuffer, MemoryLocation, SharedAllocator, VertexBuffer,
+    },
+    render_context::{RenderContextError, VkError},
+    submit::Submission,
+    timeline::{TimelineFns, TimelineSemaphore},
+};
+
+//What the graphics queue has to do before it can use the buffers from a
+//flushed batch: wait on semaphore and, if the batch went through a
+//different queue family, acquire ownership of the buffers
+pub(crate) struct Handoff {
+    pub id: u64,
+    pub semaphore: vk::Semaphore,
+    //what to wait for semaphore to reach if it's a timeline semaphore.
+    //Ignored for binary ones
+    pub wait_value: u64,
+    //every stage the uploaded buffers get used in
+    pub wait_stage: vk::PipelineStageFlags,
+    //(buffer, access) pairs that need an acquire barrier. Empty when the
+    //upload ran on the graphics family
+    pub acquires: Vec<(vk::Buffer, vk::AccessFlags)>,
+    pub src_family: u32,
+    pub dst_family: u32,
+    //fence of the frame that waits on semaphore, once it's been
+    //submitted. Nothing else may wait on it after that
+    pub consumed_by: Option<vk::Fence>,
+}
+
+//Flushed batches draw_frame hasn't made the graphics queue wait on yet,
+//along with where their ids come from. Every upload manager of a context
+//shares the one counter so ids never collide between managers
+pub(crate) struct Handoffs {
+    list: Mutex<Vec<Handoff>>,
+    next_id: AtomicU64,
+}
+
+impl Default for Handoffs {
+    fn default() -> Self {
+        Handoffs {
+            list: Mutex::default(),
+            //ids double as timeline values, which start at 0, so the first
+            //batch has to signal more
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+//Shared between the context and its upload managers
+pub(crate) type SharedHandoffs = Arc<Handoffs>;
+
+pub(crate) fn lock_handoffs(
+    handoffs: &SharedHandoffs,
+) -> MutexGuard<'_, Vec<Handoff>> {
+    //a panic while holding the lock can't leave the list half updated
+    handoffs
+        .list
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+//An id no other batch of the context has had. Each manager only ever sees
+//its ids go up, which is all its timeline semaphore needs
+fn next_handoff_id(handoffs: &SharedHandoffs) -> u64 {
+    handoffs.next_id.fetch_add(1, Ordering::Relaxed)
+}
+
+//Record the acquire half of the ownership transfers handoff needs
+pub(crate) fn record_acquire(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    handoff: &Handoff,
+) {
+    if handoff.acquires.is_empty() {
+        return;
+    }
+    let barriers: Vec<_> = handoff
+        .acquires
+        .iter()
+        .map(|&(buffer, dst_access)| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(handoff.src_family)
+                .dst_queue_family_index(handoff.dst_family)
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        })
+        .collect();
+    //SAFETY: the command buffer is recording outside a render pass and the
+    //semaphore wait at wait_stage orders this after the release
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            handoff.wait_stage,
+            handoff.wait_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &barriers,
+            &[],
+        )
+    }
+}
+
+//A copy waiting for the next flush
+struct PendingCopy {
+    staging: Buffer,
+    buffer: vk::Buffer,
+    size: vk::DeviceSize,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+}
+
+//A submitted batch whose resources can't be freed until both the copy and
+//the frame that waited on it are done
+struct Batch {
+    //also the value the batch signals the manager's timeline semaphore to
+    id: u64,
+    command_buffer: vk::CommandBuffer,
+    //the batch's own fence and binary semaphore, or a null fence and the
+    //manager's timeline semaphore when there is one
+    fence: vk::Fence,
+    semaphore: vk::Semaphore,
+    //kept alive until the copy is done
+    _staging: Vec<Buffer>,
+}
+
+//Uploads buffers on the dedicated transfer queue when the device has one,
+//so rendering carries on while they copy. Buffers handed out are only
+//filled in once flush submits them, and the first draw_frame after that
+//waits for the copy before touching them. Keep them alive until then.
+//Without a dedicated transfer family the copies go on the graphics queue,
+//so don't flush from one thread while another is drawing
+pub struct UploadManager {
+    pending: Vec<PendingCopy>,
+    batches: Vec<Batch>,
+    handoffs: SharedHandoffs,
+    //tracks every batch when the device supports timeline semaphores,
+    //otherwise each batch gets a fence and binary semaphore of its own
+    timeline: Option<TimelineSemaphore>,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    transfer_family: u32,
+    graphics_family: u32,
+    allocator: SharedAllocator,
+    device: Device,
+}
+
+impl UploadManager {
+    pub(crate) fn new(
+        device: &Device,
+        allocator: &SharedAllocator,
+        handoffs: &SharedHandoffs,
+        transfer_family: u32,
+        queue: vk::Queue,
+        graphics_family: u32,
+        timeline_fns: Option<&TimelineFns>,
+    ) -> Result<UploadManager, VkError> {
+        let timeline = timeline_fns
+            .map(|fns| TimelineSemaphore::new(device, fns, 0))
+            .transpose()
+            .map_err(|e| VkError::new("vkCreateSemaphore", e))?;
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(transfer_family)
+            .build();
+        //SAFETY: pool_info was made with a builder
+        let command_pool =
+            unsafe { device.create_command_pool(&pool_info, None) }
+                .map_err(|e| VkError::new("vkCreateCommandPool", e))?;
+        Ok(UploadManager {
+            pending: Vec::new(),
+            batches: Vec::new(),
+            handoffs: handoffs.clone(),
+            timeline,
+            command_pool,
+            queue,
+            transfer_family,
+            graphics_family,
+            allocator: allocator.clone(),
+            device: device.clone(),
+        })
+    }
+
+    //Whether copies run on a different queue family than rendering
+    pub fn is_dedicated(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+
+    //How many copies are waiting for flush
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    //A device local buffer that data gets copied into at the next flush.
+    //Frames submitted after that flush can use it at dst_stage for
+    //dst_access. Empty data is an EmptyBuffer error
+    pub fn upload_buffer<T: Copy>(
+        &mut self,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> Result<Buffer, RenderContextError> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return Err(RenderContextError::EmptyBuffer);
+        }
+        let mut staging = Buffer::new(
+            &self.device,
+            &self.allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        staging.write(data);
+        let buffer = Buffer::new(
+            &self.device,
+            &self.allocator,
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        self.pending.push(PendingCopy {
+            staging,
+            buffer: buffer.handle(),
+            size,
+            dst_stage,
+            dst_access,
+        });
+        Ok(buffer)
+    }
+
+    //RenderContext::create_vertex_buffer without blocking
+    pub fn create_vertex_buffer<T: Copy>(
+        &mut self,
+        data: &[T],
+    ) -> Result<VertexBuffer, RenderContextError> {
+        let buffer = self.upload_buffer(
+            data,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        )?;
+        Ok(VertexBuffer {
+            buffer,
+            count: data.len() as u32,
+        })
+    }
+
+    //RenderContext::create_index_buffer without blocking
+    pub fn create_index_buffer(
+        &mut self,
+        indices: &[u32],
+    ) -> Result<IndexBuffer, RenderContextError> {
+        self.create_index_buffer_typed(indices, vk::IndexType::UINT32)
+    }
+
+    //RenderContext::create_index_buffer_u16 without blocking
+    pub fn create_index_buffer_u16(
+        &mut self,
+        indices: &[u16],
+    ) -> Result<IndexBuffer, RenderContextError> {
+        self.create_index_buffer_typed(indices, vk::IndexType::UINT16)
+    }
+
+    fn create_index_buffer_typed<T: Copy>(
+        &mut self,
+        indices: &[T],
+        index_type: vk::IndexType,
+    ) -> Result<IndexBuffer, RenderContextError> {
+        let buffer = self.upload_buffer(
+            indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::INDEX_READ,
+        )?;
+        Ok(IndexBuffer {
+            buffer,
+            count: indices.len() as u32,
+            index_type,
+        })
+    }
+
+    //Submit every pending copy as one batch without waiting for it. Also
+    //frees batches that are done with
+    pub fn flush(&mut self) -> Result<(), RenderContextError> {
+        self.reclaim();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let device = &self.device;
+
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+        //SAFETY: alloc_info was made with a builder from our own pool
+        let command_buffer =
+            unsafe { device.allocate_command_buffers(&alloc_info) }.map_err(
+                VkError::with_op(
+                    "vkAllocateCommandBuffers",
+                    RenderContextError::UploadFailed,
+                ),
+            )?[0];
+        //SAFETY: default create infos are valid
+        let sync = match &self.timeline {
+            Some(timeline) => Ok((vk::Fence::null(), timeline.handle())),
+            None => unsafe {
+                device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)
+                    .map_err(|e| VkError::new("vkCreateFence", e))
+                    .and_then(|fence| {
+                        device
+                            .create_semaphore(
+                                &vk::SemaphoreCreateInfo::default(),
+                                None,
+                            )
+                            .map(|semaphore| (fence, semaphore))
+                            .map_err(|e| {
+                                device.destroy_fence(fence, None);
+                                VkError::new("vkCreateSemaphore", e)
+                            })
+                    })
+            },
+        };
+        let (fence, semaphore) = match sync {
+            Ok(sync) => sync,
+            Err(e) => {
+                //SAFETY: the command buffer hasn't been used
+                unsafe {
+                    device.free_command_buffers(
+                        self.command_pool,
+                        &[command_buffer],
+                    )
+                };
+                return Err(RenderContextError::UploadFailed(e));
+            }
+        };
+        let id = next_handoff_id(&self.handoffs);
+
+        let dedicated = self.is_dedicated();
+        let mut wait_stage = vk::PipelineStageFlags::empty();
+        let mut acquires = Vec::new();
+        let barriers: Vec<_> = pending
+            .iter()
+            .map(|copy| {
+                wait_stage |= copy.dst_stage;
+                let barrier = vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .buffer(copy.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE);
+                if dedicated {
+                    //release to the graphics family. The destination
+                    //access only matters on the acquire side
+                    acquires.push((copy.buffer, copy.dst_access));
+                    barrier
+                        .dst_access_mask(vk::AccessFlags::empty())
+                        .src_queue_family_index(self.transfer_family)
+                        .dst_queue_family_index(self.graphics_family)
+                        .build()
+                } else {
+                    barrier
+                        .dst_access_mask(copy.dst_access)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .build()
+                }
+            })
+            .collect();
+        //the release barrier's second scope is ignored, and transfer
+        //queues might not support graphics stages anyway
+        let barrier_dst_stage = if dedicated {
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        } else {
+            wait_stage
+        };
+
+        let submission = Submission::default()
+            .command_buffer(command_buffer)
+            .fence(fence);
+        let submission = match self.timeline {
+            Some(_) => submission.signal_timeline(semaphore, id),
+            None => submission.signal(semaphore),
+        };
+        //SAFETY: the command buffer is fresh and every buffer is alive and
+        //at least size bytes
+        let result = unsafe {
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                        .build(),
+                )
+                .map_err(|e| VkError::new("vkBeginCommandBuffer", e))
+                .and_then(|()| {
+                    for copy in &pending {
+                        device.cmd_copy_buffer(
+                            command_buffer,
+                            copy.staging.handle(),
+                            copy.buffer,
+                            &[vk::BufferCopy {
+                                src_offset: 0,
+                                dst_offset: 0,
+                                size: copy.size,
+                            }],
+                        );
+                    }
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        barrier_dst_stage,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &barriers,
+                        &[],
+                    );
+                    device
+                        .end_command_buffer(command_buffer)
+                        .map_err(|e| VkError::new("vkEndCommandBuffer", e))
+                })
+                .and_then(|()| submission.submit(device, self.queue))
+        };
+        if let Err(e) = result {
+            //SAFETY: nothing was submitted so none of these are in use
+            unsafe {
+                if self.timeline.is_none() {
+                    device.destroy_semaphore(semaphore, None);
+                    device.destroy_fence(fence, None);
+                }
+                device
+                    .free_command_buffers(self.command_pool, &[command_buffer]);
+            }
+            return Err(RenderContextError::UploadFailed(e));
+        }
+
+        lock_handoffs(&self.handoffs).push(Handoff {
+            id,
+            semaphore,
+            wait_value: id,
+            wait_stage,
+            acquires,
+            src_family: self.transfer_family,
+            dst_family: self.graphics_family,
+            consumed_by: None,
+        });
+        log::debug!("Flushed {} uploads", pending.len());
+        self.batches.push(Batch {
+            id,
+            command_buffer,
+            fence,
+            semaphore,
+            _staging: pending.into_iter().map(|copy| copy.staging).collect(),
+        });
+        Ok(())
+    }
+
+    //Free batches whose copy is done and whose semaphore has been waited on
+    //by a frame that has finished. Frame fences get reused, but only after
+    //draw_frame has waited for them, so a signaled fence means the frame
+    //that consumed the semaphore or a later one using the fence is done
+    fn reclaim(&mut self) {
+        let mut handoffs = lock_handoffs(&self.handoffs);
+        let device = &self.device;
+        let command_pool = self.command_pool;
+        let timeline = &self.timeline;
+        self.batches.retain(|batch| {
+            let copied = match timeline {
+                Some(timeline) => {
+                    timeline.value().is_ok_and(|value| value >= batch.id)
+                }
+                //SAFETY: the fence is ours and alive
+                None => unsafe { device.get_fence_status(batch.fence) }
+                    .unwrap_or(false),
+            };
+            let waited =
+                match handoffs.iter().find(|handoff| handoff.id == batch.id) {
+                    //SAFETY: frame fences live as long as the context
+                    Some(handoff) => handoff.consumed_by.is_some_and(|fence| {
+                        unsafe { device.get_fence_status(fence) }
+                            .unwrap_or(false)
+                    }),
+                    None => true,
+                };
+            if !(copied && waited) {
+                return true;
+            }
+            handoffs.retain(|handoff| handoff.id != batch.id);
+            //SAFETY: the copy and the frame waiting on the semaphore are
+            //both done
+            unsafe {
+                if timeline.is_none() {
+                    device.destroy_semaphore(batch.semaphore, None);
+                    device.destroy_fence(batch.fence, None);
+                }
+                device.free_command_buffers(
+                    command_pool,
+                    &[batch.command_buffer],
+                );
+            }
+            false
+        });
+    }
+}
+
+impl Drop for UploadManager {
+    fn drop(&mut self) {
+        //frames waiting on our semaphores might still be running so wait
+        //for everything rather than just our own fences
+        //SAFETY: the device is alive for as long as we are
+        if let Err(e) = unsafe { self.device.device_wait_idle() } {
+            log::error!("Failed to wait for device idle: {:?}", e);
+        }
+        let mut handoffs = lock_handoffs(&self.handoffs);
+        for batch in &self.batches {
+            handoffs.retain(|handoff| handoff.id != batch.id);
+            if self.timeline.is_some() {
+                continue;
+            }
+            //SAFETY: the device is idle
+            unsafe {
+                self.device.destroy_semaphore(batch.semaphore, None);
+                self.device.destroy_fence(batch.fence, None);
+            }
+        }
+        //SAFETY: the device is idle. Destroying the pool frees the command
+        //buffers with it, and the timeline semaphore goes when our fields
+        //are dropped
+        unsafe { self.device.destroy_command_pool(self.command_pool, None) }
+    }
+}